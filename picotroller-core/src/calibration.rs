@@ -0,0 +1,293 @@
+use crate::controller::{Controller, ADC_MAX_VALUE_3V3};
+use crate::trigger::TriggerCalibration;
+use packed_struct::prelude::*;
+
+/// Full scale of a calibrated axis, either side of center.
+pub const AXIS_MAX: i32 = i16::MAX as i32;
+
+/// Ticks spent averaging the resting position of the sticks.
+const CENTER_SAMPLES: u32 = 50;
+/// Minimum raw travel either side of center for a calibration to be accepted.
+const MIN_TRAVEL: u16 = 256;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "6")]
+pub struct AxisCalibration {
+    #[packed_field]
+    pub min: u16,
+    #[packed_field]
+    pub center: u16,
+    #[packed_field]
+    pub max: u16,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            min: 0,
+            center: (ADC_MAX_VALUE_3V3 as u16).div_ceil(2),
+            max: ADC_MAX_VALUE_3V3 as u16,
+        }
+    }
+}
+
+impl AxisCalibration {
+    pub fn is_valid(&self) -> bool {
+        self.min
+            .checked_add(MIN_TRAVEL)
+            .is_some_and(|min| min <= self.center)
+            && self
+                .center
+                .checked_add(MIN_TRAVEL)
+                .is_some_and(|center| center <= self.max)
+    }
+
+    /// Maps a raw ADC reading onto `-AXIS_MAX..=AXIS_MAX`, scaling each side of center
+    /// separately so an off-center resting position still reaches full deflection.
+    #[inline]
+    pub fn apply(&self, raw: u16) -> i32 {
+        let raw = raw.max(self.min).min(self.max) as i32;
+        let center = self.center as i32;
+        if raw >= center {
+            (raw - center) * AXIS_MAX / (self.max as i32 - center).max(1)
+        } else {
+            (raw - center) * AXIS_MAX / (center - self.min as i32).max(1)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "12")]
+pub struct StickCalibration {
+    #[packed_field(element_size_bytes = "6")]
+    pub x: AxisCalibration,
+    #[packed_field(element_size_bytes = "6")]
+    pub y: AxisCalibration,
+}
+
+impl StickCalibration {
+    pub fn is_valid(&self) -> bool {
+        self.x.is_valid() && self.y.is_valid()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationStep {
+    /// Waiting for both stick buttons to be released so the sticks can settle.
+    Release,
+    /// Averaging the resting position of both sticks and triggers.
+    Center,
+    /// Recording the extents while the sticks are rotated and the triggers pulled, until a
+    /// stick button is pressed.
+    Extents,
+}
+
+/// Walks through calibrating both sticks and any wired triggers, fed with the raw
+/// controller state once per tick.
+#[derive(Debug)]
+pub struct Calibrator {
+    step: CalibrationStep,
+    samples: u32,
+    sums: [u32; 4],
+    sticks: [StickCalibration; 2],
+    trigger_samples: [u32; 2],
+    trigger_sums: [u32; 2],
+    triggers: [Option<TriggerCalibration>; 2],
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self {
+            step: CalibrationStep::Release,
+            samples: 0,
+            sums: [0; 4],
+            sticks: [StickCalibration::default(); 2],
+            trigger_samples: [0; 2],
+            trigger_sums: [0; 2],
+            triggers: [None; 2],
+        }
+    }
+}
+
+impl Calibrator {
+    pub fn step(&self) -> CalibrationStep {
+        self.step
+    }
+
+    /// The trigger calibration recorded so far, `None` for triggers that were not wired
+    /// throughout. Each should be checked with `TriggerCalibration::is_valid`, as triggers
+    /// left alone keep their previous calibration.
+    pub fn triggers(&self) -> [Option<TriggerCalibration>; 2] {
+        self.triggers
+    }
+
+    /// Returns the recorded calibration once the extents step is finished. The result
+    /// should be checked with `StickCalibration::is_valid` before it is used.
+    pub fn update(&mut self, controller: &Controller) -> Option<[StickCalibration; 2]> {
+        let raw = [
+            controller.joy_l.x,
+            controller.joy_l.y,
+            controller.joy_r.x,
+            controller.joy_r.y,
+        ];
+        let triggers = [controller.trigger_l.raw, controller.trigger_r.raw];
+        let pressed = controller.joy_l.button || controller.joy_r.button;
+
+        match self.step {
+            CalibrationStep::Release => {
+                if !pressed {
+                    self.step = CalibrationStep::Center;
+                }
+            }
+            CalibrationStep::Center => {
+                for (sum, value) in self.sums.iter_mut().zip(raw) {
+                    *sum += value as u32;
+                }
+                for (index, value) in triggers.into_iter().enumerate() {
+                    if let Some(value) = value {
+                        self.trigger_sums[index] += value as u32;
+                        self.trigger_samples[index] += 1;
+                    }
+                }
+                self.samples += 1;
+                if self.samples == CENTER_SAMPLES {
+                    let sums = self.sums;
+                    for (axis, sum) in self.axes_mut().into_iter().zip(sums) {
+                        let center = (sum / CENTER_SAMPLES) as u16;
+                        *axis = AxisCalibration {
+                            min: center,
+                            center,
+                            max: center,
+                        };
+                    }
+                    for index in 0..2 {
+                        self.triggers[index] = (self.trigger_samples[index] == CENTER_SAMPLES)
+                            .then(|| {
+                                let released = (self.trigger_sums[index] / CENTER_SAMPLES) as u16;
+                                TriggerCalibration {
+                                    released,
+                                    pressed: released,
+                                }
+                            });
+                    }
+                    self.step = CalibrationStep::Extents;
+                }
+            }
+            CalibrationStep::Extents => {
+                if pressed {
+                    return Some(self.sticks);
+                }
+                for (axis, value) in self.axes_mut().into_iter().zip(raw) {
+                    axis.min = axis.min.min(value);
+                    axis.max = axis.max.max(value);
+                }
+                for (trigger, value) in self.triggers.iter_mut().zip(triggers) {
+                    match (trigger, value) {
+                        (Some(trigger), Some(value)) => {
+                            // Pulled furthest from rest, in whichever direction it reads
+                            let released = trigger.released;
+                            if value.abs_diff(released) > trigger.pressed.abs_diff(released) {
+                                trigger.pressed = value;
+                            }
+                        }
+                        (trigger, _) => *trigger = None,
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn axes_mut(&mut self) -> [&mut AxisCalibration; 4] {
+        let [l, r] = &mut self.sticks;
+        [&mut l.x, &mut l.y, &mut r.x, &mut r.y]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn off_center_rest_scales_each_side() {
+        let calibration = AxisCalibration {
+            min: 1000,
+            center: 1500,
+            max: 3500,
+        };
+        assert_eq!(calibration.apply(1500), 0);
+        // Halfway along the long side and the short side both read half deflection
+        assert_eq!(calibration.apply(2500), AXIS_MAX / 2);
+        assert_eq!(calibration.apply(1250), -(AXIS_MAX / 2));
+        assert_eq!(calibration.apply(3500), AXIS_MAX);
+        assert_eq!(calibration.apply(1000), -AXIS_MAX);
+    }
+
+    #[test]
+    fn readings_past_the_extents_are_clamped() {
+        let calibration = AxisCalibration {
+            min: 1000,
+            center: 1500,
+            max: 3500,
+        };
+        assert_eq!(calibration.apply(0), -AXIS_MAX);
+        assert_eq!(calibration.apply(u16::MAX), AXIS_MAX);
+    }
+
+    #[test]
+    fn collapsed_side_reads_center() {
+        let calibration = AxisCalibration {
+            min: 1500,
+            center: 1500,
+            max: 3500,
+        };
+        assert_eq!(calibration.apply(0), 0);
+        assert_eq!(calibration.apply(1500), 0);
+        assert_eq!(calibration.apply(3500), AXIS_MAX);
+
+        let calibration = AxisCalibration {
+            min: 1000,
+            center: 3500,
+            max: 3500,
+        };
+        assert_eq!(calibration.apply(u16::MAX), 0);
+        assert_eq!(calibration.apply(1000), -AXIS_MAX);
+    }
+
+    #[test]
+    fn validity_needs_travel_both_sides() {
+        assert!(AxisCalibration::default().is_valid());
+        let calibration = AxisCalibration {
+            min: 1000,
+            center: 1000 + MIN_TRAVEL,
+            max: 1000 + 2 * MIN_TRAVEL,
+        };
+        assert!(calibration.is_valid());
+        assert!(!AxisCalibration {
+            min: calibration.min + 1,
+            ..calibration
+        }
+        .is_valid());
+        assert!(!AxisCalibration {
+            max: calibration.max - 1,
+            ..calibration
+        }
+        .is_valid());
+        assert!(!AxisCalibration {
+            min: 1500,
+            center: 1500,
+            max: 3500,
+        }
+        .is_valid());
+        // Travel that would run past the top of the range
+        assert!(!AxisCalibration {
+            min: 1000,
+            center: u16::MAX - 1,
+            max: u16::MAX,
+        }
+        .is_valid());
+    }
+}
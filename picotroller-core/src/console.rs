@@ -0,0 +1,1167 @@
+use crate::controller::{
+    buttons, Button, Control, Controller, Deadzone, DeadzoneShape, DpadMode, SocdMode,
+};
+use crate::curve::{Curve, CurveShape, CURVE_POINTS};
+use crate::device::ReportResolution;
+use crate::keyboard::{keys, Direction, KeyInput};
+use crate::macros::MACRO_COUNT;
+use crate::mode::UsbMode;
+use crate::settings::Settings;
+use crate::trigger::{TriggerMode, HAIR_SENSITIVITY_MAX};
+use crate::turbo::Turbo;
+use core::fmt::Write;
+
+const LINE_SIZE: usize = 80;
+/// Room for the help text, the longest output.
+const OUTPUT_SIZE: usize = 2560;
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+commands:\r
+  state                       live button and stick state\r
+  settings                    current settings\r
+  set mode <mode>             hid, xinput, switch, ds4, keyboard or keyboard_joystick,\r
+                              applied when plugged in again\r
+  set resolution <low|high>   HID report resolution, applied when plugged in again\r
+  set deadzone <l|r> <axial|radial|scaled> <inner> <outer> <anti>\r
+  set curve <l|r> <linear|power|scurve> <amount 0-100>\r
+  set curve <l|r> custom <7 points between 0 and 32767>\r
+  set mouse <speed>           right stick mouse speed in keyboard modes, 0-255\r
+  set turbo <rate> <duty>     turbo presses per second 1-30, percent held 1-99,\r
+                              pressed and released for at least 10ms each\r
+  set trigger <l|r> threshold <1-100>\r
+                              percent of analog trigger travel pressing its button\r
+  set trigger <l|r> hair <1-50>\r
+                              press and release on moving this percent in or out\r
+  set dpad <hat|stick|buttons>\r
+                              report the D-pad as a hat, the left stick or buttons\r
+  set socd <neutral|last|up>  opposite D-pad directions cancel, last or up wins\r
+  map <button> <hid button..|none>\r
+  turbo <button> <on|off>     rapid fire while held, also start + select + button\r
+  key <button|up|down|left|right> <key>\r
+                              key for keyboard modes: a-z, 0-9, f1-f12, space, shift,\r
+                              mouse_left, none, ... or a HID usage number\r
+  macro                       list macros\r
+  macro record <n>            record from the next change of input until macro stop\r
+  macro play <n>              play back, also by pressing its trigger\r
+  macro stop                  end recording or playback\r
+  macro trigger <n> <button|none>\r
+  macro loop <n> <on|off>     start over at the end until stopped\r
+  macro cancel <n> <on|off>   stop on any button press, not only the trigger\r
+  macro clear <n>\r
+  profile [<number|name>]     list profiles, or switch to one\r
+  name <name>                 rename the active profile\r
+  save                        write settings to flash\r
+  load                        read settings from flash\r
+  defaults                    restore default settings\r
+";
+
+/// Commands changing mapping or deadzones edit the active profile.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command<'a> {
+    Help,
+    State,
+    Settings,
+    Mode(UsbMode),
+    Resolution(ReportResolution),
+    Deadzone(usize, Deadzone),
+    Curve(usize, Curve),
+    Map(Button, u16),
+    Key(KeyInput, u8),
+    MouseSpeed(u8),
+    Turbo(Button, bool),
+    TurboRate(Turbo),
+    /// The mode of an analog trigger, with its threshold or hair trigger sensitivity
+    Trigger(usize, TriggerMode, u8),
+    Dpad(DpadMode),
+    Socd(SocdMode),
+    Macros,
+    MacroRecord(usize),
+    MacroPlay(usize),
+    MacroStop,
+    MacroTrigger(usize, Option<Button>),
+    MacroLoop(usize, bool),
+    MacroCancel(usize, bool),
+    MacroClear(usize),
+    Profiles,
+    Profile(&'a str),
+    Name(&'a str),
+    Save,
+    Load,
+    Defaults,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+}
+
+impl ParseError {
+    fn message(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument, try help",
+            ParseError::InvalidArgument => "invalid argument, try help",
+        }
+    }
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        let mut args = line.split_whitespace();
+        let command = match args.next().ok_or(ParseError::Empty)? {
+            "help" | "?" => Command::Help,
+            "state" => Command::State,
+            "settings" => Command::Settings,
+            "set" => match next(&mut args)? {
+                "mode" => Command::Mode(match next(&mut args)? {
+                    "hid" => UsbMode::Hid,
+                    "xinput" => UsbMode::XInput,
+                    "switch" => UsbMode::Switch,
+                    "ds4" => UsbMode::Ds4,
+                    "keyboard" => UsbMode::Keyboard,
+                    "keyboard_joystick" => UsbMode::KeyboardJoystick,
+                    _ => return Err(ParseError::InvalidArgument),
+                }),
+                "resolution" => Command::Resolution(match next(&mut args)? {
+                    "low" => ReportResolution::Low,
+                    "high" => ReportResolution::High,
+                    _ => return Err(ParseError::InvalidArgument),
+                }),
+                "deadzone" => {
+                    let stick = parse_stick(next(&mut args)?)?;
+                    let deadzone = Deadzone {
+                        shape: match next(&mut args)? {
+                            "axial" => DeadzoneShape::Axial,
+                            "radial" => DeadzoneShape::Radial,
+                            "scaled" => DeadzoneShape::ScaledRadial,
+                            _ => return Err(ParseError::InvalidArgument),
+                        },
+                        inner: parse_number(next(&mut args)?)?,
+                        outer: parse_number(next(&mut args)?)?,
+                        anti: parse_number(next(&mut args)?)?,
+                    };
+                    if !deadzone.is_valid() {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::Deadzone(stick, deadzone)
+                }
+                "curve" => {
+                    let stick = parse_stick(next(&mut args)?)?;
+                    let shape = match next(&mut args)? {
+                        "linear" => CurveShape::Linear,
+                        "power" => CurveShape::Power,
+                        "scurve" => CurveShape::SCurve,
+                        "custom" => CurveShape::Custom,
+                        _ => return Err(ParseError::InvalidArgument),
+                    };
+                    let mut curve = Curve {
+                        shape,
+                        ..Curve::default()
+                    };
+                    match shape {
+                        CurveShape::Linear => {}
+                        CurveShape::Power | CurveShape::SCurve => {
+                            curve.amount = parse_number(next(&mut args)?)?
+                                .try_into()
+                                .map_err(|_| ParseError::InvalidArgument)?;
+                        }
+                        // The endpoints are fixed, only the points in between are given
+                        CurveShape::Custom => {
+                            for point in &mut curve.points[1..CURVE_POINTS - 1] {
+                                *point = parse_number(next(&mut args)?)?
+                                    .try_into()
+                                    .map_err(|_| ParseError::InvalidArgument)?;
+                            }
+                        }
+                    }
+                    if !curve.is_valid() {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::Curve(stick, curve)
+                }
+                "mouse" => Command::MouseSpeed(
+                    parse_number(next(&mut args)?)?
+                        .try_into()
+                        .map_err(|_| ParseError::InvalidArgument)?,
+                ),
+                "turbo" => {
+                    let mut number = || -> Result<u8, ParseError> {
+                        parse_number(next(&mut args)?)?
+                            .try_into()
+                            .map_err(|_| ParseError::InvalidArgument)
+                    };
+                    let turbo = Turbo {
+                        buttons: 0,
+                        rate: number()?,
+                        duty: number()?,
+                    };
+                    if !turbo.is_valid() {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::TurboRate(turbo)
+                }
+                "trigger" => {
+                    let trigger = parse_stick(next(&mut args)?)?;
+                    let (mode, max) = match next(&mut args)? {
+                        "threshold" => (TriggerMode::Threshold, 100),
+                        "hair" => (TriggerMode::Hair, HAIR_SENSITIVITY_MAX),
+                        _ => return Err(ParseError::InvalidArgument),
+                    };
+                    let percent = parse_number(next(&mut args)?)?;
+                    if !(1..=max as i32).contains(&percent) {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::Trigger(trigger, mode, percent as u8)
+                }
+                "dpad" => Command::Dpad(match next(&mut args)? {
+                    "hat" => DpadMode::Hat,
+                    "stick" => DpadMode::LeftStick,
+                    "buttons" => DpadMode::Buttons,
+                    _ => return Err(ParseError::InvalidArgument),
+                }),
+                "socd" => Command::Socd(match next(&mut args)? {
+                    "neutral" => SocdMode::Neutral,
+                    "last" => SocdMode::LastInput,
+                    "up" => SocdMode::UpPriority,
+                    _ => return Err(ParseError::InvalidArgument),
+                }),
+                _ => return Err(ParseError::InvalidArgument),
+            },
+            "map" => {
+                let button =
+                    Button::from_name(next(&mut args)?).ok_or(ParseError::InvalidArgument)?;
+                let mut mask = 0;
+                let mut targets = args.peekable();
+                if targets.peek().is_none() {
+                    return Err(ParseError::MissingArgument);
+                }
+                for target in targets {
+                    if target != "none" {
+                        mask |= parse_hid_button(target)?;
+                    }
+                }
+                Command::Map(button, mask)
+            }
+            "key" => {
+                let input =
+                    KeyInput::from_name(next(&mut args)?).ok_or(ParseError::InvalidArgument)?;
+                let key = keys::from_name(next(&mut args)?).ok_or(ParseError::InvalidArgument)?;
+                Command::Key(input, key)
+            }
+            "turbo" => {
+                let button =
+                    Button::from_name(next(&mut args)?).ok_or(ParseError::InvalidArgument)?;
+                Command::Turbo(button, parse_on_off(next(&mut args)?)?)
+            }
+            "macro" => match args.next() {
+                None => Command::Macros,
+                Some("stop") => Command::MacroStop,
+                Some(action) => {
+                    let index = parse_macro(next(&mut args)?)?;
+                    match action {
+                        "record" => Command::MacroRecord(index),
+                        "play" => Command::MacroPlay(index),
+                        "trigger" => Command::MacroTrigger(
+                            index,
+                            match next(&mut args)? {
+                                "none" => None,
+                                name => Some(
+                                    Button::from_name(name).ok_or(ParseError::InvalidArgument)?,
+                                ),
+                            },
+                        ),
+                        "loop" => Command::MacroLoop(index, parse_on_off(next(&mut args)?)?),
+                        "cancel" => Command::MacroCancel(index, parse_on_off(next(&mut args)?)?),
+                        "clear" => Command::MacroClear(index),
+                        _ => return Err(ParseError::InvalidArgument),
+                    }
+                }
+            },
+            "profile" => match args.next() {
+                Some(profile) => Command::Profile(profile),
+                None => Command::Profiles,
+            },
+            "name" => Command::Name(next(&mut args)?),
+            "save" => Command::Save,
+            "load" => Command::Load,
+            "defaults" => Command::Defaults,
+            _ => return Err(ParseError::UnknownCommand),
+        };
+        Ok(command)
+    }
+}
+
+fn next<'a>(args: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, ParseError> {
+    args.next().ok_or(ParseError::MissingArgument)
+}
+
+fn parse_stick(arg: &str) -> Result<usize, ParseError> {
+    match arg {
+        "l" => Ok(0),
+        "r" => Ok(1),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+fn parse_on_off(arg: &str) -> Result<bool, ParseError> {
+    match arg {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+/// A macro by its number from 1, as its index.
+fn parse_macro(arg: &str) -> Result<usize, ParseError> {
+    match arg.parse::<usize>() {
+        Ok(number @ 1..=MACRO_COUNT) => Ok(number - 1),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+fn parse_number(arg: &str) -> Result<i32, ParseError> {
+    arg.parse().map_err(|_| ParseError::InvalidArgument)
+}
+
+/// A HID button by name, or by its number from 1 to 16.
+fn parse_hid_button(arg: &str) -> Result<u16, ParseError> {
+    if let Some((_, mask)) = buttons::NAMES.iter().find(|(name, _)| *name == arg) {
+        return Ok(*mask);
+    }
+    match arg.parse::<u16>() {
+        Ok(number @ 1..=16) => Ok(1 << (number - 1)),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+/// Settings storage and pipeline state the console asks the main loop to act on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    Save,
+    Load,
+    /// Switch to the profile at this index
+    Profile(usize),
+    /// Record into the macro at this index
+    MacroRecord(usize),
+    /// Play back the macro at this index
+    MacroPlay(usize),
+    MacroStop,
+    /// Drop the recording of the macro at this index
+    MacroClear(usize),
+    /// Go back to the default settings
+    Defaults,
+}
+
+/// Output waiting to be written to the serial port.
+struct Output {
+    data: [u8; OUTPUT_SIZE],
+    len: usize,
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let bytes = s.as_bytes();
+        let end = self.len + bytes.len();
+        if end > OUTPUT_SIZE {
+            return Err(core::fmt::Error);
+        }
+        self.data[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Line based command shell over the CDC serial port.
+pub struct Console {
+    line: [u8; LINE_SIZE],
+    line_len: usize,
+    after_cr: bool,
+    output: Output,
+    request: Option<Request>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            line: [0; LINE_SIZE],
+            line_len: 0,
+            after_cr: false,
+            output: Output {
+                data: [0; OUTPUT_SIZE],
+                len: 0,
+            },
+            request: None,
+        }
+    }
+}
+
+impl Console {
+    /// Feeds bytes received over serial, echoing them and running each completed line.
+    pub fn receive(&mut self, data: &[u8], settings: &mut Settings, controller: &mut Controller) {
+        for &byte in data {
+            // Terminals end lines with CR, LF or both
+            let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+            match byte {
+                b'\n' if after_cr => {}
+                b'\r' | b'\n' => {
+                    let _ = self.output.write_str("\r\n");
+                    // Commands borrow from the line, so it is copied out of `self` first
+                    let line = self.line;
+                    let result = core::str::from_utf8(&line[..self.line_len])
+                        .map_err(|_| ParseError::InvalidArgument)
+                        .and_then(Command::parse);
+                    self.line_len = 0;
+                    match result {
+                        Ok(command) => self.execute(command, settings, controller),
+                        Err(ParseError::Empty) => {}
+                        Err(e) => {
+                            let _ = writeln!(self.output, "{}\r", e.message());
+                        }
+                    }
+                    // Requests print the prompt once the main loop has handled them
+                    if self.request.is_none() {
+                        let _ = self.output.write_str(PROMPT);
+                    }
+                }
+                0x08 | 0x7f if self.line_len > 0 => {
+                    self.line_len -= 1;
+                    let _ = self.output.write_str("\x08 \x08");
+                }
+                0x20..=0x7e if self.line_len < LINE_SIZE => {
+                    self.line[self.line_len] = byte;
+                    self.line_len += 1;
+                    let _ = self.output.write_char(byte as char);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Output waiting to be written to the serial port.
+    pub fn pending(&self) -> &[u8] {
+        &self.output.data[..self.output.len]
+    }
+
+    /// Drops the first `count` bytes of pending output once they have been written.
+    pub fn consume(&mut self, count: usize) {
+        self.output.data.copy_within(count..self.output.len, 0);
+        self.output.len -= count;
+    }
+
+    pub fn take_request(&mut self) -> Option<Request> {
+        self.request.take()
+    }
+
+    /// Prints the outcome of a request, followed by the prompt.
+    pub fn print(&mut self, message: &str) {
+        let _ = write!(self.output, "{}\r\n{}", message, PROMPT);
+    }
+
+    fn execute(&mut self, command: Command, settings: &mut Settings, controller: &mut Controller) {
+        let out = &mut self.output;
+        let _ = match command {
+            Command::Help => out.write_str(HELP),
+            Command::State => write_state(out, controller),
+            Command::Settings => write_settings(out, settings),
+            Command::Mode(mode) => {
+                settings.mode = mode;
+                out.write_str("mode changes after save and plugging in again\r\n")
+            }
+            Command::Resolution(resolution) => {
+                settings.resolution = resolution;
+                out.write_str("resolution changes after save and plugging in again\r\n")
+            }
+            Command::Deadzone(stick, deadzone) => {
+                settings.profile_mut().deadzone[stick] = deadzone;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Curve(stick, curve) => {
+                settings.profile_mut().curve[stick] = curve;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Map(button, mask) => {
+                settings.profile_mut().remap.set(button, mask);
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Key(input, key) => {
+                settings.profile_mut().keymap.set(input, key);
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::MouseSpeed(speed) => {
+                settings.profile_mut().keymap.mouse_speed = speed;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Turbo(button, enabled) => {
+                settings.profile_mut().turbo.set_enabled(button, enabled);
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::TurboRate(turbo) => {
+                let profile = settings.profile_mut();
+                profile.turbo.rate = turbo.rate;
+                profile.turbo.duty = turbo.duty;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Trigger(trigger, mode, percent) => {
+                let trigger = &mut settings.profile_mut().trigger[trigger];
+                trigger.mode = mode;
+                match mode {
+                    TriggerMode::Threshold => trigger.threshold = percent,
+                    TriggerMode::Hair => trigger.sensitivity = percent,
+                }
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Dpad(mode) => {
+                settings.profile_mut().dpad = mode;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Socd(mode) => {
+                settings.profile_mut().socd = mode;
+                settings.apply(controller);
+                Ok(())
+            }
+            Command::Macros => write_macros(out, settings),
+            Command::MacroRecord(index) => {
+                self.request = Some(Request::MacroRecord(index));
+                Ok(())
+            }
+            Command::MacroPlay(index) => {
+                self.request = Some(Request::MacroPlay(index));
+                Ok(())
+            }
+            Command::MacroStop => {
+                self.request = Some(Request::MacroStop);
+                Ok(())
+            }
+            Command::MacroTrigger(index, trigger) => {
+                settings.macros[index].set_trigger(trigger);
+                Ok(())
+            }
+            Command::MacroLoop(index, looping) => {
+                settings.macros[index].set_looping(looping);
+                Ok(())
+            }
+            Command::MacroCancel(index, cancel) => {
+                settings.macros[index].set_cancel_on_press(cancel);
+                Ok(())
+            }
+            Command::MacroClear(index) => {
+                self.request = Some(Request::MacroClear(index));
+                Ok(())
+            }
+            Command::Profiles => write_profiles(out, settings),
+            Command::Profile(name) => match settings.find_profile(name) {
+                Some(index) => {
+                    self.request = Some(Request::Profile(index));
+                    Ok(())
+                }
+                None => out.write_str("no such profile\r\n"),
+            },
+            Command::Name(name) => {
+                if settings.profile_mut().set_name(name) {
+                    Ok(())
+                } else {
+                    out.write_str("names are up to 12 characters, without spaces\r\n")
+                }
+            }
+            Command::Save => {
+                self.request = Some(Request::Save);
+                Ok(())
+            }
+            Command::Load => {
+                self.request = Some(Request::Load);
+                Ok(())
+            }
+            Command::Defaults => {
+                self.request = Some(Request::Defaults);
+                Ok(())
+            }
+        };
+    }
+}
+
+fn write_state(out: &mut impl Write, controller: &Controller) -> core::fmt::Result {
+    out.write_str("pressed:")?;
+    for button in Button::ALL {
+        if controller.is_pressed(button) {
+            write!(out, " {}", button.name())?;
+        }
+    }
+    for direction in Direction::ALL {
+        if controller.dpad.is_pressed(direction) {
+            write!(out, " {}", Control::Dpad(direction).name())?;
+        }
+    }
+    out.write_str("\r\n")?;
+    for (name, joy) in [("left", &controller.joy_l), ("right", &controller.joy_r)] {
+        let (x, y) = joy.axes();
+        write!(out, "{}: raw {} {} -> {} {}\r\n", name, joy.x, joy.y, x, y)?;
+    }
+    for (name, trigger) in [("l", &controller.trigger_l), ("r", &controller.trigger_r)] {
+        if let Some(raw) = trigger.raw {
+            let pressed = if trigger.is_pressed() { " pressed" } else { "" };
+            write!(
+                out,
+                "trigger {}: raw {} -> {}{}\r\n",
+                name,
+                raw,
+                trigger.value(),
+                pressed
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_profiles(out: &mut impl Write, settings: &Settings) -> core::fmt::Result {
+    for (index, profile) in settings.profiles.iter().enumerate() {
+        let marker = if index == settings.active_profile() {
+            '*'
+        } else {
+            ' '
+        };
+        write!(out, "{} {} {}\r\n", marker, index + 1, profile.name())?;
+    }
+    Ok(())
+}
+
+fn write_macros(out: &mut impl Write, settings: &Settings) -> core::fmt::Result {
+    for (index, sequence) in settings.macros.iter().enumerate() {
+        let trigger = sequence.trigger().map_or("none", |button| button.name());
+        let on_off = |on| if on { "on" } else { "off" };
+        write!(
+            out,
+            "{} trigger {} steps {} {}ms loop {} cancel {}\r\n",
+            index + 1,
+            trigger,
+            sequence.steps().len(),
+            sequence.ticks() * 10,
+            on_off(sequence.is_looping()),
+            on_off(sequence.cancels_on_press())
+        )?;
+    }
+    Ok(())
+}
+
+fn write_settings(out: &mut impl Write, settings: &Settings) -> core::fmt::Result {
+    let mode = match settings.mode {
+        UsbMode::Hid => "hid",
+        UsbMode::XInput => "xinput",
+        UsbMode::Switch => "switch",
+        UsbMode::Ds4 => "ds4",
+        UsbMode::Keyboard => "keyboard",
+        UsbMode::KeyboardJoystick => "keyboard_joystick",
+    };
+    let resolution = match settings.resolution {
+        ReportResolution::Low => "low",
+        ReportResolution::High => "high",
+    };
+    write!(out, "mode {}\r\nresolution {}\r\n", mode, resolution)?;
+    for (name, stick) in [
+        ("l", &settings.calibration[0]),
+        ("r", &settings.calibration[1]),
+    ] {
+        write!(
+            out,
+            "calibration {} x {} {} {} y {} {} {}\r\n",
+            name,
+            stick.x.min,
+            stick.x.center,
+            stick.x.max,
+            stick.y.min,
+            stick.y.center,
+            stick.y.max
+        )?;
+    }
+    for (name, trigger) in [
+        ("l", &settings.trigger_calibration[0]),
+        ("r", &settings.trigger_calibration[1]),
+    ] {
+        write!(
+            out,
+            "calibration trigger {} {} {}\r\n",
+            name, trigger.released, trigger.pressed
+        )?;
+    }
+    let profile = settings.profile();
+    write!(out, "profile {}\r\n", profile.name())?;
+    for (name, deadzone) in [("l", &profile.deadzone[0]), ("r", &profile.deadzone[1])] {
+        let shape = match deadzone.shape {
+            DeadzoneShape::Axial => "axial",
+            DeadzoneShape::Radial => "radial",
+            DeadzoneShape::ScaledRadial => "scaled",
+        };
+        write!(
+            out,
+            "deadzone {} {} {} {} {}\r\n",
+            name, shape, deadzone.inner, deadzone.outer, deadzone.anti
+        )?;
+    }
+    for (name, curve) in [("l", &profile.curve[0]), ("r", &profile.curve[1])] {
+        write!(out, "curve {} ", name)?;
+        match curve.shape {
+            CurveShape::Linear => out.write_str("linear")?,
+            CurveShape::Power => write!(out, "power {}", curve.amount)?,
+            CurveShape::SCurve => write!(out, "scurve {}", curve.amount)?,
+            CurveShape::Custom => {
+                out.write_str("custom")?;
+                for point in &curve.points[1..CURVE_POINTS - 1] {
+                    write!(out, " {}", point)?;
+                }
+            }
+        }
+        out.write_str("\r\n")?;
+    }
+    for button in Button::ALL {
+        write!(out, "map {}", button.name())?;
+        let mask = profile.remap.get(button);
+        if mask == 0 {
+            out.write_str(" none")?;
+        }
+        for bit in 0..16 {
+            if mask & (1 << bit) != 0 {
+                match buttons::NAMES.iter().find(|(_, b)| *b == 1 << bit) {
+                    Some((name, _)) => write!(out, " {}", name)?,
+                    None => write!(out, " {}", bit + 1)?,
+                }
+            }
+        }
+        out.write_str("\r\n")?;
+    }
+    let inputs = Button::ALL
+        .into_iter()
+        .map(KeyInput::Button)
+        .chain(Direction::ALL.into_iter().map(KeyInput::Stick));
+    for input in inputs {
+        let name = match input {
+            KeyInput::Button(button) => button.name(),
+            KeyInput::Stick(direction) => direction.name(),
+        };
+        write!(out, "key {} ", name)?;
+        write_key(out, profile.keymap.get(input))?;
+        out.write_str("\r\n")?;
+    }
+    for (name, trigger) in [("l", &profile.trigger[0]), ("r", &profile.trigger[1])] {
+        match trigger.mode {
+            TriggerMode::Threshold => {
+                write!(out, "trigger {} threshold {}", name, trigger.threshold)?
+            }
+            TriggerMode::Hair => write!(out, "trigger {} hair {}", name, trigger.sensitivity)?,
+        }
+        out.write_str("\r\n")?;
+    }
+    let dpad = match profile.dpad {
+        DpadMode::Hat => "hat",
+        DpadMode::LeftStick => "stick",
+        DpadMode::Buttons => "buttons",
+    };
+    let socd = match profile.socd {
+        SocdMode::Neutral => "neutral",
+        SocdMode::LastInput => "last",
+        SocdMode::UpPriority => "up",
+    };
+    write!(out, "dpad {}\r\nsocd {}\r\n", dpad, socd)?;
+    write!(out, "mouse {}\r\n", profile.keymap.mouse_speed)?;
+    write!(out, "turbo {} {}", profile.turbo.rate, profile.turbo.duty)?;
+    for button in Button::ALL {
+        if profile.turbo.is_enabled(button) {
+            write!(out, " {}", button.name())?;
+        }
+    }
+    out.write_str("\r\n")
+}
+
+/// Writes a key the way `keys::from_name` reads it.
+fn write_key(out: &mut impl Write, key: u8) -> core::fmt::Result {
+    if let Some((name, _)) = keys::NAMES.iter().find(|(_, code)| *code == key) {
+        return out.write_str(name);
+    }
+    match key {
+        0x04..=0x1d => out.write_char((b'a' + key - 0x04) as char),
+        0x1e..=0x26 => out.write_char((b'1' + key - 0x1e) as char),
+        0x27 => out.write_char('0'),
+        0x3a..=0x45 => write!(out, "f{}", key - 0x3a + 1),
+        _ => write!(out, "{}", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::AXIS_MAX;
+
+    #[test]
+    fn parses_map_to_several_buttons() {
+        assert_eq!(
+            Command::parse("map front_r south 16"),
+            Ok(Command::Map(Button::FrontR, buttons::BTN_SOUTH | 1 << 15))
+        );
+        assert_eq!(
+            Command::parse("map start none"),
+            Ok(Command::Map(Button::Start, 0))
+        );
+        assert_eq!(
+            Command::parse("map start"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("map start 17"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parses_deadzone() {
+        assert_eq!(
+            Command::parse("set deadzone r axial 1000 500 0"),
+            Ok(Command::Deadzone(
+                1,
+                Deadzone {
+                    shape: DeadzoneShape::Axial,
+                    inner: 1000,
+                    outer: 500,
+                    anti: 0,
+                }
+            ))
+        );
+        assert_eq!(
+            Command::parse("set deadzone r axial 1000 500"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("set deadzone r axial 30000 5000 0"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set deadzone l axial 2000000000 2000000000 0"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parses_curve() {
+        assert_eq!(
+            Command::parse("set curve r power 40"),
+            Ok(Command::Curve(
+                1,
+                Curve {
+                    shape: CurveShape::Power,
+                    amount: 40,
+                    ..Curve::default()
+                }
+            ))
+        );
+        let custom = "set curve l custom 1000 2000 4000 8000 16000 24000 30000";
+        match Command::parse(custom) {
+            Ok(Command::Curve(0, curve)) => {
+                assert_eq!(curve.points[3], 4000);
+                assert_eq!(curve.points[CURVE_POINTS - 1] as i32, AXIS_MAX);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            Command::parse("set curve l custom 1000 500 4000 8000 16000 24000 30000"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set curve l scurve 101"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parses_key() {
+        assert_eq!(
+            Command::parse("key front_l space"),
+            Ok(Command::Key(KeyInput::Button(Button::FrontL), keys::SPACE))
+        );
+        assert_eq!(
+            Command::parse("key up f1"),
+            Ok(Command::Key(KeyInput::Stick(Direction::Up), 0x3a))
+        );
+        assert_eq!(
+            Command::parse("key sideways a"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(Command::parse("key up"), Err(ParseError::MissingArgument));
+        assert_eq!(
+            Command::parse("set mouse 256"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn settings_list_keys_by_name() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(b"key under_l f2\r", &mut settings, &mut controller);
+        console.consume(console.pending().len());
+        console.receive(b"settings\r", &mut settings, &mut controller);
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.contains("key under_l f2\r\n"));
+        assert!(text.contains("key up w\r\n"));
+        assert!(text.contains("key front_r mouse_left\r\n"));
+        assert!(text.contains("mouse 12\r\n"));
+    }
+
+    #[test]
+    fn parses_turbo() {
+        assert_eq!(
+            Command::parse("turbo front_r on"),
+            Ok(Command::Turbo(Button::FrontR, true))
+        );
+        assert_eq!(
+            Command::parse("turbo start off"),
+            Ok(Command::Turbo(Button::Start, false))
+        );
+        assert_eq!(
+            Command::parse("turbo front_r fast"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set turbo 20 30"),
+            Ok(Command::TurboRate(Turbo {
+                buttons: 0,
+                rate: 20,
+                duty: 30
+            }))
+        );
+        assert_eq!(
+            Command::parse("set turbo 31 50"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set turbo 10 100"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set turbo 30 20"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set turbo 10"),
+            Err(ParseError::MissingArgument)
+        );
+    }
+
+    #[test]
+    fn turbo_edits_active_profile() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(
+            b"turbo front_r on\rturbo under_l on\rset turbo 15 40\r",
+            &mut settings,
+            &mut controller,
+        );
+        let turbo = settings.profile().turbo;
+        assert!(turbo.is_enabled(Button::FrontR));
+        assert!(turbo.is_enabled(Button::UnderL));
+        assert_eq!((turbo.rate, turbo.duty), (15, 40));
+        assert_eq!(controller.turbo, turbo);
+        console.consume(console.pending().len());
+        console.receive(b"settings\r", &mut settings, &mut controller);
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.ends_with("turbo 15 40 under_l front_r\r\n> "));
+    }
+
+    #[test]
+    fn trigger_mode_edits_active_profile() {
+        assert_eq!(
+            Command::parse("set trigger r hair 51"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set trigger l threshold 0"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set trigger l fast 10"),
+            Err(ParseError::InvalidArgument)
+        );
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(
+            b"set trigger l threshold 30\rset trigger r hair 8\rsettings\r",
+            &mut settings,
+            &mut controller,
+        );
+        let [l, r] = settings.profile().trigger;
+        assert_eq!((l.mode, l.threshold), (TriggerMode::Threshold, 30));
+        assert_eq!((r.mode, r.sensitivity), (TriggerMode::Hair, 8));
+        assert_eq!(controller.trigger_r.settings, r);
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.contains("trigger l threshold 30\r\ntrigger r hair 8\r\n"));
+        assert!(text.contains("calibration trigger r 0 4095\r\n"));
+    }
+
+    #[test]
+    fn dpad_options_edit_active_profile() {
+        assert_eq!(
+            Command::parse("set dpad stick"),
+            Ok(Command::Dpad(DpadMode::LeftStick))
+        );
+        assert_eq!(
+            Command::parse("set socd last"),
+            Ok(Command::Socd(SocdMode::LastInput))
+        );
+        assert_eq!(
+            Command::parse("set dpad mouse"),
+            Err(ParseError::InvalidArgument)
+        );
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(
+            b"set dpad buttons\rset socd up\rsettings\r",
+            &mut settings,
+            &mut controller,
+        );
+        assert_eq!(settings.profile().dpad, DpadMode::Buttons);
+        assert_eq!(controller.dpad_mode, DpadMode::Buttons);
+        assert_eq!(controller.socd.mode, SocdMode::UpPriority);
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.contains("dpad buttons\r\nsocd up\r\n"));
+    }
+
+    #[test]
+    fn rejects_unknown() {
+        assert_eq!(Command::parse("   "), Err(ParseError::Empty));
+        assert_eq!(Command::parse("reboot"), Err(ParseError::UnknownCommand));
+        assert_eq!(
+            Command::parse("set mode ps5"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn runs_line_with_backspace() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(b"map selex\x08ct none\r", &mut settings, &mut controller);
+        assert_eq!(settings.profile().remap.get(Button::Select), 0);
+        assert_eq!(controller.remap.get(Button::Select), 0);
+        assert!(console.pending().ends_with(PROMPT.as_bytes()));
+    }
+
+    #[test]
+    fn save_is_left_to_caller() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(b"save\n", &mut settings, &mut controller);
+        assert_eq!(console.take_request(), Some(Request::Save));
+        assert_eq!(console.take_request(), None);
+        assert!(!console.pending().ends_with(PROMPT.as_bytes()));
+        console.print("saved");
+        assert_eq!(console.pending(), b"save\r\nsaved\r\n> ");
+    }
+
+    #[test]
+    fn profile_switch_is_left_to_caller() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(
+            b"name racing\rprofile racing\r",
+            &mut settings,
+            &mut controller,
+        );
+        assert_eq!(settings.profile().name(), "racing");
+        assert_eq!(console.take_request(), Some(Request::Profile(0)));
+        console.receive(b"profile 9\r", &mut settings, &mut controller);
+        assert_eq!(console.take_request(), None);
+    }
+
+    #[test]
+    fn help_fits_output() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(b"help\r", &mut settings, &mut controller);
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.starts_with("help\r\ncommands:"));
+        assert!(text.ends_with("restore default settings\r\n> "));
+    }
+
+    #[test]
+    fn parses_macro() {
+        assert_eq!(Command::parse("macro"), Ok(Command::Macros));
+        assert_eq!(Command::parse("macro stop"), Ok(Command::MacroStop));
+        assert_eq!(
+            Command::parse("macro record 2"),
+            Ok(Command::MacroRecord(1))
+        );
+        assert_eq!(Command::parse("macro play 1"), Ok(Command::MacroPlay(0)));
+        assert_eq!(
+            Command::parse("macro trigger 1 thumb_r"),
+            Ok(Command::MacroTrigger(0, Some(Button::ThumbR)))
+        );
+        assert_eq!(
+            Command::parse("macro trigger 1 none"),
+            Ok(Command::MacroTrigger(0, None))
+        );
+        assert_eq!(
+            Command::parse("macro loop 2 on"),
+            Ok(Command::MacroLoop(1, true))
+        );
+        assert_eq!(
+            Command::parse("macro cancel 1 off"),
+            Ok(Command::MacroCancel(0, false))
+        );
+        assert_eq!(Command::parse("macro clear 2"), Ok(Command::MacroClear(1)));
+        assert_eq!(
+            Command::parse("macro play 3"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("macro jump 1"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("macro record"),
+            Err(ParseError::MissingArgument)
+        );
+    }
+
+    #[test]
+    fn macro_options_edit_settings() {
+        let mut console = Console::default();
+        let mut settings = Settings::default();
+        let mut controller = Controller::default();
+        console.receive(
+            b"macro trigger 2 under_r\rmacro loop 2 on\rmacro cancel 2 off\rmacro\r",
+            &mut settings,
+            &mut controller,
+        );
+        let sequence = settings.macros[1];
+        assert_eq!(sequence.trigger(), Some(Button::UnderR));
+        assert!(sequence.is_looping());
+        assert!(!sequence.cancels_on_press());
+        let text = core::str::from_utf8(console.pending()).unwrap();
+        assert!(text.contains("1 trigger none steps 0 0ms loop off cancel on\r\n"));
+        assert!(text.contains("2 trigger under_r steps 0 0ms loop on cancel off\r\n"));
+
+        console.receive(b"macro record 2\r", &mut settings, &mut controller);
+        assert_eq!(console.take_request(), Some(Request::MacroRecord(1)));
+    }
+}
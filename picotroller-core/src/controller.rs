@@ -0,0 +1,954 @@
+use crate::calibration::{StickCalibration, AXIS_MAX};
+use crate::curve::Curve;
+use crate::device::{self, JoystickHiResReport, JoystickReport};
+use crate::ds4::{self, Ds4Report};
+use crate::keyboard::{
+    self, keys, Direction, KeyInput, KeyMap, KeyboardReport, STICK_KEY_THRESHOLD,
+};
+use crate::remap::RemapTable;
+use crate::switch::{self, SwitchReport};
+use crate::trigger::TriggerState;
+use crate::turbo::Turbo;
+use crate::xinput::{self, XInputReport};
+use core::fmt::Debug;
+use packed_struct::prelude::*;
+
+pub use crate::deadzone::{Deadzone, DeadzoneShape};
+
+pub(crate) const ADC_MAX_VALUE_3V3: i32 = 4095;
+
+#[allow(unused)]
+pub mod buttons {
+    pub const BTN_SOUTH: u16 = 1 << 0;
+    pub const BTN_EAST: u16 = 1 << 1;
+    pub const BTN_C: u16 = 1 << 2;
+    pub const BTN_NORTH: u16 = 1 << 3;
+    pub const BTN_WEST: u16 = 1 << 4;
+    pub const BTN_Z: u16 = 1 << 5;
+    pub const BTN_TL: u16 = 1 << 6;
+    pub const BTN_TR: u16 = 1 << 7;
+    pub const BTN_TL2: u16 = 1 << 8;
+    pub const BTN_TR2: u16 = 1 << 9;
+    pub const BTN_SELECT: u16 = 1 << 10;
+    pub const BTN_START: u16 = 1 << 11;
+    pub const BTN_MODE: u16 = 1 << 12;
+    pub const BTN_THUMBL: u16 = 1 << 13;
+    pub const BTN_THUMBR: u16 = 1 << 14;
+
+    pub const NAMES: [(&str, u16); 15] = [
+        ("south", BTN_SOUTH),
+        ("east", BTN_EAST),
+        ("c", BTN_C),
+        ("north", BTN_NORTH),
+        ("west", BTN_WEST),
+        ("z", BTN_Z),
+        ("tl", BTN_TL),
+        ("tr", BTN_TR),
+        ("tl2", BTN_TL2),
+        ("tr2", BTN_TR2),
+        ("select", BTN_SELECT),
+        ("start", BTN_START),
+        ("mode", BTN_MODE),
+        ("thumbl", BTN_THUMBL),
+        ("thumbr", BTN_THUMBR),
+    ];
+}
+
+/// HID buttons and the XInput buttons they are reported as.
+const XINPUT_BUTTONS: [(u16, u16); 11] = [
+    (buttons::BTN_SOUTH, xinput::buttons::A),
+    (buttons::BTN_EAST, xinput::buttons::B),
+    (buttons::BTN_WEST, xinput::buttons::X),
+    (buttons::BTN_NORTH, xinput::buttons::Y),
+    (buttons::BTN_TL, xinput::buttons::SHOULDER_L),
+    (buttons::BTN_TR, xinput::buttons::SHOULDER_R),
+    (buttons::BTN_SELECT, xinput::buttons::BACK),
+    (buttons::BTN_START, xinput::buttons::START),
+    (buttons::BTN_MODE, xinput::buttons::GUIDE),
+    (buttons::BTN_THUMBL, xinput::buttons::THUMB_L),
+    (buttons::BTN_THUMBR, xinput::buttons::THUMB_R),
+];
+
+/// D-pad directions and the XInput buttons they are reported as.
+const XINPUT_DPAD: [(Direction, u16); 4] = [
+    (Direction::Up, xinput::buttons::DPAD_UP),
+    (Direction::Down, xinput::buttons::DPAD_DOWN),
+    (Direction::Left, xinput::buttons::DPAD_LEFT),
+    (Direction::Right, xinput::buttons::DPAD_RIGHT),
+];
+
+/// HID buttons and the DS4 buttons they are reported as.
+const DS4_BUTTONS: [(u16, u16); 13] = [
+    (buttons::BTN_SOUTH, ds4::buttons::CROSS),
+    (buttons::BTN_EAST, ds4::buttons::CIRCLE),
+    (buttons::BTN_WEST, ds4::buttons::SQUARE),
+    (buttons::BTN_NORTH, ds4::buttons::TRIANGLE),
+    (buttons::BTN_TL, ds4::buttons::L1),
+    (buttons::BTN_TR, ds4::buttons::R1),
+    (buttons::BTN_TL2, ds4::buttons::L2),
+    (buttons::BTN_TR2, ds4::buttons::R2),
+    (buttons::BTN_SELECT, ds4::buttons::SHARE),
+    (buttons::BTN_START, ds4::buttons::OPTIONS),
+    (buttons::BTN_MODE, ds4::buttons::PS),
+    (buttons::BTN_THUMBL, ds4::buttons::L3),
+    (buttons::BTN_THUMBR, ds4::buttons::R3),
+];
+
+/// HID buttons and the Switch buttons in the same position, so south is B rather than A.
+const SWITCH_BUTTONS: [(u16, u16); 13] = [
+    (buttons::BTN_SOUTH, switch::buttons::B),
+    (buttons::BTN_EAST, switch::buttons::A),
+    (buttons::BTN_WEST, switch::buttons::Y),
+    (buttons::BTN_NORTH, switch::buttons::X),
+    (buttons::BTN_TL, switch::buttons::L),
+    (buttons::BTN_TR, switch::buttons::R),
+    (buttons::BTN_TL2, switch::buttons::ZL),
+    (buttons::BTN_TR2, switch::buttons::ZR),
+    (buttons::BTN_SELECT, switch::buttons::MINUS),
+    (buttons::BTN_START, switch::buttons::PLUS),
+    (buttons::BTN_MODE, switch::buttons::HOME),
+    (buttons::BTN_THUMBL, switch::buttons::THUMB_L),
+    (buttons::BTN_THUMBR, switch::buttons::THUMB_R),
+];
+
+#[derive(Debug, Default)]
+pub struct JoyState {
+    pub button: bool,
+    pub x: u16,
+    pub y: u16,
+    pub calibration: StickCalibration,
+    pub deadzone: Deadzone,
+    pub curve: Curve,
+}
+
+impl JoyState {
+    /// Position of the stick after calibration, deadzone and response curve, each axis within
+    /// `-AXIS_MAX..=AXIS_MAX`.
+    #[inline]
+    pub fn axes(&self) -> (i32, i32) {
+        self.curve.apply(self.deadzone.apply((
+            self.calibration.x.apply(self.x),
+            self.calibration.y.apply(self.y),
+        )))
+    }
+}
+
+/// Physical buttons of the controller.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Button {
+    ThumbL,
+    ThumbR,
+    UnderL,
+    UnderR,
+    FrontL,
+    FrontR,
+    Start,
+    Select,
+}
+
+impl Button {
+    pub const COUNT: usize = 8;
+    pub const ALL: [Button; Button::COUNT] = [
+        Button::ThumbL,
+        Button::ThumbR,
+        Button::UnderL,
+        Button::UnderR,
+        Button::FrontL,
+        Button::FrontR,
+        Button::Start,
+        Button::Select,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::ThumbL => "thumb_l",
+            Button::ThumbR => "thumb_r",
+            Button::UnderL => "under_l",
+            Button::UnderR => "under_r",
+            Button::FrontL => "front_l",
+            Button::FrontR => "front_r",
+            Button::Start => "start",
+            Button::Select => "select",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|button| button.name() == name)
+    }
+}
+
+/// What a digital input is wired to, one of the buttons or a direction of the D-pad.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Control {
+    Button(Button),
+    Dpad(Direction),
+}
+
+impl Control {
+    pub const COUNT: usize = Button::COUNT + 4;
+
+    pub fn index(&self) -> usize {
+        match self {
+            Control::Button(button) => *button as usize,
+            Control::Dpad(direction) => Button::COUNT + *direction as usize,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Control::Button(button) => button.name(),
+            Control::Dpad(Direction::Up) => "dpad_up",
+            Control::Dpad(Direction::Down) => "dpad_down",
+            Control::Dpad(Direction::Left) => "dpad_left",
+            Control::Dpad(Direction::Right) => "dpad_right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Button::ALL
+            .into_iter()
+            .map(Control::Button)
+            .chain(Direction::ALL.into_iter().map(Control::Dpad))
+            .find(|control| control.name() == name)
+    }
+}
+
+impl From<Button> for Control {
+    fn from(button: Button) -> Self {
+        Control::Button(button)
+    }
+}
+
+/// How a D-pad resolves opposite directions held at the same time, as hitbox style
+/// controllers allow. Tournaments require one of these rather than reporting both.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SocdMode {
+    /// Opposite directions cancel out.
+    #[default]
+    Neutral = 0,
+    /// The direction pressed last wins, the other one comes back once it is released.
+    LastInput = 1,
+    /// Up wins over down, left and right cancel out.
+    UpPriority = 2,
+}
+
+/// How the D-pad is reported.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DpadMode {
+    /// The hat switch in HID mode, the D-pad of the other reports.
+    #[default]
+    Hat = 0,
+    /// Full deflection of the left stick, in place of the stick while a direction is held.
+    LeftStick = 1,
+    /// HID buttons 17 to 20, for games that ignore the hat. The other reports have no
+    /// buttons to spare and keep their D-pad.
+    Buttons = 2,
+}
+
+/// Directions held on a D-pad.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Dpad {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Dpad {
+    pub fn is_pressed(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::Up => self.up,
+            Direction::Down => self.down,
+            Direction::Left => self.left,
+            Direction::Right => self.right,
+        }
+    }
+
+    pub fn set(&mut self, direction: Direction, pressed: bool) {
+        match direction {
+            Direction::Up => self.up = pressed,
+            Direction::Down => self.down = pressed,
+            Direction::Left => self.left = pressed,
+            Direction::Right => self.right = pressed,
+        }
+    }
+
+    pub fn is_centered(&self) -> bool {
+        *self == Dpad::default()
+    }
+
+    /// Position of a hat switch, 0 for up then clockwise in steps of 45 degrees, `None` when
+    /// centered. Opposite directions cancel out, in case they were not cleaned.
+    pub fn hat(&self) -> Option<u8> {
+        let vertical = self.down as i8 - self.up as i8;
+        let horizontal = self.right as i8 - self.left as i8;
+        match (vertical, horizontal) {
+            (-1, 0) => Some(0),
+            (-1, 1) => Some(1),
+            (0, 1) => Some(2),
+            (1, 1) => Some(3),
+            (1, 0) => Some(4),
+            (1, -1) => Some(5),
+            (0, -1) => Some(6),
+            (-1, -1) => Some(7),
+            _ => None,
+        }
+    }
+
+    /// Bits of the directions, up, down, left and right from the lowest.
+    pub fn bits(&self) -> u8 {
+        Direction::ALL
+            .iter()
+            .enumerate()
+            .filter(|(_, direction)| self.is_pressed(**direction))
+            .fold(0, |bits, (bit, _)| bits | 1 << bit)
+    }
+
+    /// The directions set in `bits`, the reverse of `Dpad::bits`.
+    pub fn from_bits(bits: u8) -> Self {
+        let mut dpad = Dpad::default();
+        for (bit, direction) in Direction::ALL.into_iter().enumerate() {
+            dpad.set(direction, bits & 1 << bit != 0);
+        }
+        dpad
+    }
+}
+
+/// Cleans the held directions of a D-pad so opposite ones are never reported together,
+/// following the order they were pressed in for `SocdMode::LastInput`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SocdCleaner {
+    pub mode: SocdMode,
+    held: Dpad,
+    /// Last pressed of up and down, and of left and right
+    last_vertical: Option<Direction>,
+    last_horizontal: Option<Direction>,
+}
+
+impl SocdCleaner {
+    pub fn new(mode: SocdMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// Takes the directions held now and returns the ones to report. Opposite directions
+    /// pressed in the same update count as down or right being pressed last.
+    pub fn update(&mut self, held: Dpad) -> Dpad {
+        for direction in Direction::ALL {
+            if held.is_pressed(direction) && !self.held.is_pressed(direction) {
+                match direction {
+                    Direction::Up | Direction::Down => self.last_vertical = Some(direction),
+                    Direction::Left | Direction::Right => self.last_horizontal = Some(direction),
+                }
+            }
+        }
+        self.held = held;
+
+        let (up, down) = self.resolve(
+            held.up,
+            held.down,
+            self.last_vertical == Some(Direction::Up),
+            true,
+        );
+        let (left, right) = self.resolve(
+            held.left,
+            held.right,
+            self.last_horizontal == Some(Direction::Left),
+            false,
+        );
+        Dpad {
+            up,
+            down,
+            left,
+            right,
+        }
+    }
+
+    /// Resolves a pair of opposite directions, `first_last` telling whether the first was
+    /// pressed after the second. Up priority only applies to the vertical pair.
+    fn resolve(&self, first: bool, second: bool, first_last: bool, vertical: bool) -> (bool, bool) {
+        if !(first && second) {
+            return (first, second);
+        }
+        match self.mode {
+            SocdMode::Neutral => (false, false),
+            SocdMode::LastInput => (first_last, !first_last),
+            SocdMode::UpPriority => (vertical, false),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Controller {
+    pub joy_l: JoyState,
+    pub joy_r: JoyState,
+    pub trigger_l: TriggerState,
+    pub trigger_r: TriggerState,
+    /// Directions of the D-pad to report, cleaned by `socd`
+    pub dpad: Dpad,
+    pub socd: SocdCleaner,
+    pub dpad_mode: DpadMode,
+    pub under_l: bool,
+    pub under_r: bool,
+    pub front_l: bool,
+    pub front_r: bool,
+    pub start: bool,
+    pub select: bool,
+    pub remap: RemapTable,
+    pub keymap: KeyMap,
+    pub turbo: Turbo,
+    /// Held turbo buttons currently in their released phase, set by `TurboClock`
+    pub turbo_released: u8,
+}
+
+impl Controller {
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::ThumbL => self.joy_l.button = pressed,
+            Button::ThumbR => self.joy_r.button = pressed,
+            Button::UnderL => self.under_l = pressed,
+            Button::UnderR => self.under_r = pressed,
+            Button::FrontL => self.front_l = pressed,
+            Button::FrontR => self.front_r = pressed,
+            Button::Start => self.start = pressed,
+            Button::Select => self.select = pressed,
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::ThumbL => self.joy_l.button,
+            Button::ThumbR => self.joy_r.button,
+            Button::UnderL => self.under_l,
+            Button::UnderR => self.under_r,
+            Button::FrontL => self.front_l,
+            Button::FrontR => self.front_r,
+            Button::Start => self.start,
+            Button::Select => self.select,
+        }
+    }
+
+    /// Takes the directions held on the D-pad, once per tick.
+    pub fn set_dpad(&mut self, held: Dpad) {
+        self.dpad = self.socd.update(held);
+    }
+
+    /// Whether `button` shows as pressed in reports, which turbo toggles while it is held.
+    pub fn is_reported(&self, button: Button) -> bool {
+        self.is_pressed(button) && self.turbo_released & (1 << button as u8) == 0
+    }
+
+    #[inline]
+    pub fn hid_report(&self, report: &mut JoystickReport) {
+        let [lx, ly, rx, ry] = self.report_axes();
+        report.lx = scale_i8(lx);
+        report.ly = scale_i8(ly);
+        report.rx = scale_i8(rx);
+        report.ry = scale_i8(ry);
+        // The descriptor shares the stick range, triggers only use its positive half
+        report.lz = scale_i8(self.trigger_l.value());
+        report.rz = scale_i8(self.trigger_r.value());
+        report.buttons = self.report_buttons();
+        (report.hat, report.dpad) = self.report_hat();
+    }
+
+    #[inline]
+    pub fn hid_report_hires(&self, report: &mut JoystickHiResReport) {
+        let [lx, ly, rx, ry] = self.report_axes();
+        report.lx = lx as i16;
+        report.ly = ly as i16;
+        report.rx = rx as i16;
+        report.ry = ry as i16;
+        report.lz = self.trigger_l.value() as i16;
+        report.rz = self.trigger_r.value() as i16;
+        report.buttons = self.report_buttons();
+        (report.hat, report.dpad) = self.report_hat();
+    }
+
+    #[inline]
+    pub fn xinput_report(&self, report: &mut XInputReport) {
+        // The HID report puts ly in the X usage and lx in Y, with Y pointing down. XInput
+        // has the same X with Y pointing up.
+        let [lx, ly, rx, ry] = self.report_axes();
+        report.lx = ly as i16;
+        report.ly = -lx as i16;
+        report.rx = ry as i16;
+        report.ry = -rx as i16;
+
+        let pressed = self.report_buttons();
+        let dpad = self.report_dpad();
+        report.buttons = XINPUT_BUTTONS
+            .iter()
+            .filter(|(hid, _)| pressed & hid != 0)
+            .fold(0, |buttons, (_, xinput)| buttons | xinput)
+            | XINPUT_DPAD
+                .iter()
+                .filter(|(direction, _)| dpad.is_pressed(*direction))
+                .fold(0, |buttons, (_, xinput)| buttons | xinput);
+        [report.lt, report.rt] = self.report_triggers();
+    }
+
+    #[inline]
+    pub fn switch_report(&self, report: &mut SwitchReport) {
+        // Same orientation as the HID report, the axes are unsigned with the center at 0x80
+        let [lx, ly, rx, ry] = self.report_axes();
+        report.lx = scale_u8(ly);
+        report.ly = scale_u8(lx);
+        report.rx = scale_u8(ry);
+        report.ry = scale_u8(rx);
+
+        let pressed = self.report_buttons();
+        report.buttons = SWITCH_BUTTONS
+            .iter()
+            .filter(|(hid, _)| pressed & hid != 0)
+            .fold(0, |buttons, (_, switch)| buttons | switch);
+        report.hat = self.report_dpad().hat().unwrap_or(switch::HAT_CENTER);
+    }
+
+    #[inline]
+    pub fn ds4_report(&self, report: &mut Ds4Report) {
+        // Same orientation as the Switch report
+        let [lx, ly, rx, ry] = self.report_axes();
+        report.lx = scale_u8(ly);
+        report.ly = scale_u8(lx);
+        report.rx = scale_u8(ry);
+        report.ry = scale_u8(rx);
+
+        let pressed = self.report_buttons();
+        report.buttons = DS4_BUTTONS
+            .iter()
+            .filter(|(hid, _)| pressed & hid != 0)
+            .fold(0, |buttons, (_, ds4)| buttons | ds4);
+        report.hat = self.report_dpad().hat().unwrap_or(ds4::HAT_CENTER);
+        [report.lt, report.rt] = self.report_triggers();
+    }
+
+    #[inline]
+    pub fn keyboard_report(&self, report: &mut KeyboardReport) {
+        // Same orientation as the Switch report, X right and Y down
+        let [lx, ly, rx, ry] = self.report_axes();
+        let held = |input: KeyInput| match input {
+            KeyInput::Button(button) => self.is_reported(button),
+            KeyInput::Stick(direction) if self.report_dpad().is_pressed(direction) => true,
+            KeyInput::Stick(Direction::Up) => lx < -STICK_KEY_THRESHOLD,
+            KeyInput::Stick(Direction::Down) => lx > STICK_KEY_THRESHOLD,
+            KeyInput::Stick(Direction::Left) => ly < -STICK_KEY_THRESHOLD,
+            KeyInput::Stick(Direction::Right) => ly > STICK_KEY_THRESHOLD,
+        };
+        let inputs = Button::ALL
+            .into_iter()
+            .map(KeyInput::Button)
+            .chain(Direction::ALL.into_iter().map(KeyInput::Stick));
+        report.mouse_buttons = 0;
+        for (slot, input) in report.keys.iter_mut().zip(inputs) {
+            let key = self.keymap.get(input);
+            *slot = if held(input) { key } else { keys::NONE };
+            match *slot {
+                keys::MOUSE_LEFT => report.mouse_buttons |= 1 << 0,
+                keys::MOUSE_RIGHT => report.mouse_buttons |= 1 << 1,
+                keys::MOUSE_MIDDLE => report.mouse_buttons |= 1 << 2,
+                _ => {}
+            }
+        }
+        report.mouse = keyboard::mouse_velocity(self.keymap.mouse_speed, (ry, rx));
+    }
+
+    /// Stick positions as reported to the host, with X inverted to match how the sticks are
+    /// mounted. The first axis points down and the second right, the D-pad moves the left
+    /// stick that way in `DpadMode::LeftStick`.
+    #[inline]
+    fn report_axes(&self) -> [i32; 4] {
+        let (lx, ly) = self.joy_l.axes();
+        let (rx, ry) = self.joy_r.axes();
+        let dpad = self.dpad;
+        if self.dpad_mode == DpadMode::LeftStick && !dpad.is_centered() {
+            let axis = |negative: bool, positive: bool| match (negative, positive) {
+                (true, false) => -AXIS_MAX,
+                (false, true) => AXIS_MAX,
+                _ => 0,
+            };
+            return [
+                axis(dpad.up, dpad.down),
+                axis(dpad.left, dpad.right),
+                -rx,
+                ry,
+            ];
+        }
+        [-lx, ly, -rx, ry]
+    }
+
+    /// The D-pad unless it moves the left stick.
+    #[inline]
+    fn report_dpad(&self) -> Dpad {
+        match self.dpad_mode {
+            DpadMode::LeftStick => Dpad::default(),
+            DpadMode::Hat | DpadMode::Buttons => self.dpad,
+        }
+    }
+
+    /// Hat switch and D-pad buttons of the HID reports.
+    #[inline]
+    fn report_hat(&self) -> (u8, u8) {
+        match self.dpad_mode {
+            DpadMode::Hat => (self.dpad.hat().unwrap_or(device::HAT_CENTER), 0),
+            DpadMode::LeftStick => (device::HAT_CENTER, 0),
+            DpadMode::Buttons => (device::HAT_CENTER, self.dpad.bits()),
+        }
+    }
+
+    /// HID buttons of the physical buttons, and the trigger buttons of analog triggers
+    /// pulled past their threshold.
+    #[inline]
+    fn report_buttons(&self) -> u16 {
+        let mut pressed = self.remap.apply(|button| self.is_reported(button));
+        if self.trigger_l.is_pressed() {
+            pressed |= buttons::BTN_TL2;
+        }
+        if self.trigger_r.is_pressed() {
+            pressed |= buttons::BTN_TR2;
+        }
+        pressed
+    }
+
+    /// Analog trigger travel for the XInput and DS4 reports, full while a button mapped to
+    /// the trigger button is held.
+    #[inline]
+    fn report_triggers(&self) -> [u8; 2] {
+        let mapped = self.remap.apply(|button| self.is_reported(button));
+        [
+            (self.trigger_l, buttons::BTN_TL2),
+            (self.trigger_r, buttons::BTN_TR2),
+        ]
+        .map(|(trigger, button)| {
+            if mapped & button != 0 {
+                u8::MAX
+            } else {
+                (trigger.value() * u8::MAX as i32 / AXIS_MAX) as u8
+            }
+        })
+    }
+}
+
+#[inline]
+fn scale_i8(value: i32) -> i8 {
+    (value >> 8) as i8
+}
+
+#[inline]
+fn scale_u8(value: i32) -> u8 {
+    (scale_i8(value) as u8) ^ 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESSES: [(Direction, bool); 8] = [
+        (Direction::Up, true),
+        (Direction::Down, true),
+        (Direction::Left, true),
+        (Direction::Right, true),
+        (Direction::Up, false),
+        (Direction::Down, false),
+        (Direction::Left, false),
+        (Direction::Right, false),
+    ];
+
+    /// Every order of pressing and releasing each direction once, releases after presses.
+    fn orderings() -> Vec<Vec<(Direction, bool)>> {
+        fn extend(order: &mut Vec<(Direction, bool)>, orders: &mut Vec<Vec<(Direction, bool)>>) {
+            if order.len() == PRESSES.len() {
+                orders.push(order.clone());
+                return;
+            }
+            for event @ (direction, pressed) in PRESSES {
+                let done = order.contains(&event);
+                let released_before_press = !pressed && !order.contains(&(direction, true));
+                if !done && !released_before_press {
+                    order.push(event);
+                    extend(order, orders);
+                    order.pop();
+                }
+            }
+        }
+        let mut orders = Vec::new();
+        extend(&mut Vec::new(), &mut orders);
+        orders
+    }
+
+    /// What `mode` should report of a pair of held directions, given when each was pressed.
+    fn expected(
+        mode: SocdMode,
+        first: Option<usize>,
+        second: Option<usize>,
+        vertical: bool,
+    ) -> (bool, bool) {
+        match (first, second) {
+            (Some(first), Some(second)) => match mode {
+                SocdMode::Neutral => (false, false),
+                SocdMode::LastInput => (first > second, second > first),
+                SocdMode::UpPriority => (vertical, false),
+            },
+            (first, second) => (first.is_some(), second.is_some()),
+        }
+    }
+
+    #[test]
+    fn socd_resolves_every_ordering() {
+        let orders = orderings();
+        // 8! orders, halved for each direction's release coming after its press
+        assert_eq!(orders.len(), 2520);
+        for mode in [SocdMode::Neutral, SocdMode::LastInput, SocdMode::UpPriority] {
+            for order in &orders {
+                let mut cleaner = SocdCleaner::new(mode);
+                let mut held = Dpad::default();
+                let mut pressed_at = [None; 4];
+                for (step, &(direction, pressed)) in order.iter().enumerate() {
+                    held.set(direction, pressed);
+                    pressed_at[direction as usize] = pressed.then_some(step);
+                    let cleaned = cleaner.update(held);
+
+                    let [up, down, left, right] = pressed_at;
+                    let (up, down) = expected(mode, up, down, true);
+                    let (left, right) = expected(mode, left, right, false);
+                    let expected = Dpad {
+                        up,
+                        down,
+                        left,
+                        right,
+                    };
+                    assert_eq!(cleaned, expected, "{:?} {:?} step {}", mode, order, step);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn socd_never_reports_opposites() {
+        for mode in [SocdMode::Neutral, SocdMode::LastInput, SocdMode::UpPriority] {
+            let mut cleaner = SocdCleaner::new(mode);
+            for held in 0..16u8 {
+                let held = Dpad {
+                    up: held & 1 != 0,
+                    down: held & 2 != 0,
+                    left: held & 4 != 0,
+                    right: held & 8 != 0,
+                };
+                let cleaned = cleaner.update(held);
+                assert!(!(cleaned.up && cleaned.down), "{:?} {:?}", mode, held);
+                assert!(!(cleaned.left && cleaned.right), "{:?} {:?}", mode, held);
+                for direction in Direction::ALL {
+                    assert!(!cleaned.is_pressed(direction) || held.is_pressed(direction));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn socd_last_input_breaks_ties_towards_down_and_right() {
+        let mut cleaner = SocdCleaner::new(SocdMode::LastInput);
+        let all = Dpad {
+            up: true,
+            down: true,
+            left: true,
+            right: true,
+        };
+        let expected = Dpad {
+            down: true,
+            right: true,
+            ..Dpad::default()
+        };
+        assert_eq!(cleaner.update(all), expected);
+        // Holding on doesn't change the winner, releasing it brings back the other
+        assert_eq!(cleaner.update(all), expected);
+        let released = Dpad { down: false, ..all };
+        assert_eq!(
+            cleaner.update(released),
+            Dpad {
+                up: true,
+                right: true,
+                ..Dpad::default()
+            }
+        );
+    }
+
+    #[test]
+    fn switch_report_centers_axes_and_maps_by_position() {
+        let mut controller = Controller::default();
+        controller.joy_l.x = 2048;
+        controller.joy_l.y = 2048;
+        controller.joy_r.x = 0;
+        controller.joy_r.y = 2048;
+        controller.set_button(Button::FrontR, true);
+        controller.set_button(Button::Start, true);
+        let mut report = SwitchReport::default();
+        controller.switch_report(&mut report);
+        assert_eq!((report.lx, report.ly), (0x80, 0x80));
+        assert_eq!((report.rx, report.ry), (0x80, 0xff));
+        assert_eq!(report.hat, switch::HAT_CENTER);
+        assert_eq!(report.buttons, switch::buttons::B | switch::buttons::PLUS);
+    }
+
+    #[test]
+    fn ds4_report_maps_triggers_to_buttons_and_analog() {
+        let mut controller = Controller::default();
+        controller.joy_l.x = 2048;
+        controller.joy_l.y = 2048;
+        controller.joy_r.x = 2048;
+        controller.joy_r.y = 2048;
+        controller.remap.set(Button::UnderL, buttons::BTN_TL2);
+        controller.set_button(Button::UnderL, true);
+        controller.set_button(Button::FrontR, true);
+        let mut report = Ds4Report::default();
+        controller.ds4_report(&mut report);
+        assert_eq!(
+            (report.lx, report.ly, report.rx, report.ry),
+            (0x80, 0x80, 0x80, 0x80)
+        );
+        assert_eq!(report.buttons, ds4::buttons::L2 | ds4::buttons::CROSS);
+        assert_eq!((report.lt, report.rt), (u8::MAX, 0));
+    }
+
+    #[test]
+    fn analog_triggers_fill_z_axes_and_trigger_buttons() {
+        let mut controller = Controller::default();
+        controller.joy_l.x = 2048;
+        controller.joy_l.y = 2048;
+        controller.joy_r.x = 2048;
+        controller.joy_r.y = 2048;
+        controller.trigger_l.update(Some(4095));
+        controller.trigger_r.update(Some(1024));
+        let mut report = JoystickReport::default();
+        controller.hid_report(&mut report);
+        assert_eq!((report.lz, report.rz), (127, 32));
+        // Only the left trigger is past the default threshold of half its travel
+        assert_eq!(report.buttons, buttons::BTN_TL2);
+
+        let mut report = JoystickHiResReport::default();
+        controller.hid_report_hires(&mut report);
+        assert_eq!((report.lz, report.rz), (AXIS_MAX as i16, 8193));
+
+        let mut report = XInputReport::default();
+        controller.xinput_report(&mut report);
+        assert_eq!((report.lt, report.rt), (u8::MAX, 63));
+        // A button mapped to the trigger button pulls it all the way
+        controller.remap.set(Button::FrontR, buttons::BTN_TR2);
+        controller.set_button(Button::FrontR, true);
+        controller.xinput_report(&mut report);
+        assert_eq!((report.lt, report.rt), (u8::MAX, u8::MAX));
+    }
+
+    #[test]
+    fn keyboard_report_presses_keys_and_moves_mouse() {
+        let mut controller = Controller::default();
+        // Left stick pushed up, right stick pushed down
+        controller.joy_l.x = 4095;
+        controller.joy_l.y = 2048;
+        controller.joy_r.x = 0;
+        controller.joy_r.y = 2048;
+        controller.set_button(Button::Start, true);
+        controller.set_button(Button::FrontR, true);
+        let mut report = KeyboardReport::default();
+        controller.keyboard_report(&mut report);
+        let pressed: Vec<_> = report.pressed_keys().collect();
+        assert_eq!(pressed, [keys::ESCAPE, keys::W]);
+        assert_eq!(report.mouse_buttons, 1 << 0);
+        let speed = controller.keymap.mouse_speed as i32 * 256;
+        assert_eq!(report.mouse.0, 0);
+        assert!(report.mouse.1 > speed * 9 / 10 && report.mouse.1 <= speed);
+    }
+
+    #[test]
+    fn keyboard_report_ignores_small_stick_movement() {
+        let mut controller = Controller::default();
+        controller.joy_l.x = 2048;
+        controller.joy_l.y = 2048 + 600;
+        controller.joy_r.x = 2048;
+        controller.joy_r.y = 2048;
+        let mut report = KeyboardReport::default();
+        controller.keyboard_report(&mut report);
+        assert_eq!(report, KeyboardReport::default());
+    }
+
+    fn dpad(up: bool, down: bool, left: bool, right: bool) -> Dpad {
+        Dpad {
+            up,
+            down,
+            left,
+            right,
+        }
+    }
+
+    #[test]
+    fn dpad_hat_goes_clockwise_from_up() {
+        let hats = [
+            (dpad(true, false, false, false), Some(0)),
+            (dpad(true, false, false, true), Some(1)),
+            (dpad(false, false, false, true), Some(2)),
+            (dpad(false, true, false, true), Some(3)),
+            (dpad(false, true, false, false), Some(4)),
+            (dpad(false, true, true, false), Some(5)),
+            (dpad(false, false, true, false), Some(6)),
+            (dpad(true, false, true, false), Some(7)),
+            (Dpad::default(), None),
+            (dpad(true, true, false, false), None),
+            (dpad(true, true, true, false), Some(6)),
+        ];
+        for (dpad, hat) in hats {
+            assert_eq!(dpad.hat(), hat, "{:?}", dpad);
+        }
+        assert_eq!(dpad(true, false, false, true).bits(), 0b1001);
+    }
+
+    #[test]
+    fn dpad_modes_fill_hid_report() {
+        let mut controller = Controller::default();
+        controller.joy_l.x = 2048;
+        controller.joy_l.y = 2048;
+        controller.joy_r.x = 2048;
+        controller.joy_r.y = 2048;
+        controller.set_dpad(dpad(true, false, false, true));
+        let mut report = JoystickReport::default();
+        controller.hid_report(&mut report);
+        assert_eq!((report.hat, report.dpad), (1, 0));
+        assert_eq!((report.lx, report.ly), (0, 0));
+
+        controller.dpad_mode = DpadMode::Buttons;
+        controller.hid_report(&mut report);
+        assert_eq!((report.hat, report.dpad), (device::HAT_CENTER, 0b1001));
+
+        // Up pushes the stick the same way as the left stick pushed up
+        controller.dpad_mode = DpadMode::LeftStick;
+        controller.hid_report(&mut report);
+        assert_eq!((report.hat, report.dpad), (device::HAT_CENTER, 0));
+        assert_eq!((report.lx, report.ly), (-128, 127));
+        let mut report = JoystickHiResReport::default();
+        controller.hid_report_hires(&mut report);
+        assert_eq!((report.lx, report.ly), (-AXIS_MAX as i16, AXIS_MAX as i16));
+    }
+
+    #[test]
+    fn dpad_fills_console_reports() {
+        let mut controller = Controller::default();
+        controller.set_dpad(dpad(false, true, true, false));
+        let mut report = XInputReport::default();
+        controller.xinput_report(&mut report);
+        assert_eq!(
+            report.buttons,
+            xinput::buttons::DPAD_DOWN | xinput::buttons::DPAD_LEFT
+        );
+        let mut report = SwitchReport::default();
+        controller.switch_report(&mut report);
+        assert_eq!(report.hat, 5);
+        let mut report = Ds4Report::default();
+        controller.ds4_report(&mut report);
+        assert_eq!(report.hat, 5);
+    }
+}
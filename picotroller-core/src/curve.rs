@@ -0,0 +1,199 @@
+use crate::calibration::AXIS_MAX;
+use crate::deadzone::{magnitude, scale_vector};
+use packed_struct::prelude::*;
+
+/// Points of a custom curve, evenly spaced over `0..=AXIS_MAX`.
+pub const CURVE_POINTS: usize = 9;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CurveShape {
+    Linear = 0,
+    /// Blends towards a cubic, for finer control near the center and fast turns at the edge.
+    Power = 1,
+    /// Blends towards smoothstep, slow near the center and the edge and fast in between.
+    SCurve = 2,
+    /// Interpolates the user supplied `points`.
+    Custom = 3,
+}
+
+/// Response curve applied to the distance of a stick from the center, after the deadzone.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "20")]
+pub struct Curve {
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub shape: CurveShape,
+    /// Strength of `Power` and `SCurve` in percent, 0 is linear.
+    #[packed_field]
+    pub amount: u8,
+    /// Output for each of the `CURVE_POINTS` inputs of a `Custom` curve, packed_struct needs
+    /// a literal length.
+    #[packed_field]
+    pub points: [u16; 9],
+}
+
+const _: () = assert!(CURVE_POINTS == 9);
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self {
+            shape: CurveShape::Linear,
+            amount: 0,
+            points: core::array::from_fn(|i| point_input(i) as u16),
+        }
+    }
+}
+
+impl Curve {
+    /// Custom points must start at 0, end at `AXIS_MAX` and never decrease, which keeps the
+    /// curve monotonic with its endpoints in place like the built-in shapes.
+    pub fn is_valid(&self) -> bool {
+        self.amount <= 100
+            && self.points[0] == 0
+            && self.points[CURVE_POINTS - 1] as i32 == AXIS_MAX
+            && self.points.windows(2).all(|pair| pair[0] <= pair[1])
+    }
+
+    pub fn apply(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        if self.shape == CurveShape::Linear {
+            return (x, y);
+        }
+        // Past full deflection in a corner the curve has reached its end, scaling stays at 1
+        let magnitude = magnitude(x, y).min(AXIS_MAX);
+        scale_vector(x, y, magnitude, self.map(magnitude))
+    }
+
+    /// Maps a distance from the center within `0..=AXIS_MAX` onto the curve.
+    pub fn map(&self, value: i32) -> i32 {
+        let x = value as i64;
+        let max = AXIS_MAX as i64;
+        let target = match self.shape {
+            CurveShape::Linear => return value,
+            CurveShape::Power => x * x * x / (max * max),
+            CurveShape::SCurve => (3 * x * x * max - 2 * x * x * x) / (max * max),
+            CurveShape::Custom => return self.interpolate(value),
+        };
+        (x + (self.amount as i64 * (target - x)).div_euclid(100)) as i32
+    }
+
+    fn interpolate(&self, value: i32) -> i32 {
+        let segment = ((value as i64 * (CURVE_POINTS as i64 - 1) / AXIS_MAX as i64) as usize)
+            .min(CURVE_POINTS - 2);
+        let (x0, x1) = (point_input(segment), point_input(segment + 1));
+        let (y0, y1) = (self.points[segment] as i32, self.points[segment + 1] as i32);
+        y0 + (value - x0) * (y1 - y0) / (x1 - x0)
+    }
+}
+
+/// Input of custom curve point `index`.
+fn point_input(index: usize) -> i32 {
+    (index as i64 * AXIS_MAX as i64 / (CURVE_POINTS as i64 - 1)) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(shape: CurveShape, amount: u8) -> Curve {
+        Curve {
+            shape,
+            amount,
+            ..Curve::default()
+        }
+    }
+
+    /// Maps every input and checks the output never decreases and the endpoints stay put.
+    fn assert_monotonic_with_endpoints(curve: &Curve) {
+        assert_eq!(curve.map(0), 0, "{:?}", curve);
+        assert_eq!(curve.map(AXIS_MAX), AXIS_MAX, "{:?}", curve);
+        let mut last = 0;
+        for x in 0..=AXIS_MAX {
+            let y = curve.map(x);
+            assert!(y >= last, "{:?} decreases at {}", curve, x);
+            assert!(y <= AXIS_MAX, "{:?} overshoots at {}", curve, x);
+            last = y;
+        }
+    }
+
+    #[test]
+    fn linear_is_identity() {
+        let linear = Curve::default();
+        assert!(linear.is_valid());
+        assert_monotonic_with_endpoints(&linear);
+        assert_eq!(linear.map(12345), 12345);
+        assert_eq!(linear.apply((-20000, 30000)), (-20000, 30000));
+    }
+
+    #[test]
+    fn power_is_monotonic_with_endpoints() {
+        for amount in [0, 1, 33, 50, 99, 100] {
+            assert_monotonic_with_endpoints(&curve(CurveShape::Power, amount));
+        }
+    }
+
+    #[test]
+    fn power_is_slower_near_center() {
+        let power = curve(CurveShape::Power, 100);
+        assert_eq!(power.map(AXIS_MAX / 2), AXIS_MAX / 8);
+        assert!(curve(CurveShape::Power, 50).map(AXIS_MAX / 2) > AXIS_MAX / 8);
+    }
+
+    #[test]
+    fn s_curve_is_monotonic_with_endpoints() {
+        for amount in [0, 1, 33, 50, 99, 100] {
+            assert_monotonic_with_endpoints(&curve(CurveShape::SCurve, amount));
+        }
+    }
+
+    #[test]
+    fn s_curve_is_slow_at_center_and_edge() {
+        let s_curve = curve(CurveShape::SCurve, 100);
+        assert!(s_curve.map(AXIS_MAX / 8) < AXIS_MAX / 8);
+        assert!(s_curve.map(AXIS_MAX * 7 / 8) > AXIS_MAX * 7 / 8);
+        assert!((s_curve.map(AXIS_MAX / 2) - AXIS_MAX / 2).abs() <= 1);
+    }
+
+    #[test]
+    fn custom_interpolates_points() {
+        let mut custom = curve(CurveShape::Custom, 0);
+        custom.points = [0, 1000, 2000, 4000, 8000, 16000, 24000, 30000, 32767];
+        assert!(custom.is_valid());
+        assert_monotonic_with_endpoints(&custom);
+        assert_eq!(custom.map(point_input(3)), 4000);
+        let halfway = (point_input(4) + point_input(5)) / 2;
+        assert!((custom.map(halfway) - 12000).abs() <= 1);
+    }
+
+    #[test]
+    fn custom_with_flat_segment_is_monotonic() {
+        let mut custom = curve(CurveShape::Custom, 0);
+        custom.points = [0, 0, 0, 10000, 10000, 10000, 20000, 32767, 32767];
+        assert!(custom.is_valid());
+        assert_monotonic_with_endpoints(&custom);
+    }
+
+    #[test]
+    fn rejects_invalid_custom_points() {
+        let mut custom = curve(CurveShape::Custom, 0);
+        custom.points[4] = custom.points[3] - 1;
+        assert!(!custom.is_valid());
+        let mut custom = curve(CurveShape::Custom, 0);
+        custom.points[0] = 1;
+        assert!(!custom.is_valid());
+        let mut custom = curve(CurveShape::Custom, 0);
+        custom.points[CURVE_POINTS - 1] = 30000;
+        assert!(!custom.is_valid());
+        assert!(!curve(CurveShape::Power, 101).is_valid());
+    }
+
+    #[test]
+    fn apply_keeps_direction() {
+        let power = curve(CurveShape::Power, 100);
+        let (x, y) = power.apply((-16000, 12000));
+        assert!(x < 0 && y > 0);
+        assert!((x * 3 + y * 4).abs() <= 4);
+        assert_eq!(power.apply((0, 0)), (0, 0));
+        assert_eq!(power.apply((AXIS_MAX, AXIS_MAX)), (AXIS_MAX, AXIS_MAX));
+    }
+}
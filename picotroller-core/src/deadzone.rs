@@ -0,0 +1,241 @@
+use crate::calibration::AXIS_MAX;
+use packed_struct::prelude::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeadzoneShape {
+    /// Each axis has its own deadzone, snapping the stick to the axes near the center.
+    Axial = 0,
+    /// The stick is ignored within a circle, outside of it the position is passed through.
+    Radial = 1,
+    /// Like `Radial`, but the travel outside of the circle is rescaled to start from zero.
+    ScaledRadial = 2,
+}
+
+/// Deadzone applied to a calibrated stick position. Sizes are in the units of `AXIS_MAX`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "13")]
+pub struct Deadzone {
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub shape: DeadzoneShape,
+    /// Travel around the center that is reported as zero.
+    #[packed_field]
+    pub inner: i32,
+    /// Travel before the edge that is reported as full deflection.
+    #[packed_field]
+    pub outer: i32,
+    /// Smallest deflection reported once outside of the inner deadzone, to cancel out a
+    /// deadzone the game applies on its own.
+    #[packed_field]
+    pub anti: i32,
+}
+
+impl Default for Deadzone {
+    fn default() -> Self {
+        Self {
+            shape: DeadzoneShape::ScaledRadial,
+            inner: AXIS_MAX / 16,
+            outer: AXIS_MAX / 32,
+            anti: 0,
+        }
+    }
+}
+
+impl Deadzone {
+    pub fn is_valid(&self) -> bool {
+        self.inner >= 0
+            && self.outer >= 0
+            && self
+                .inner
+                .checked_add(self.outer)
+                .is_some_and(|sum| sum < AXIS_MAX)
+            && (0..AXIS_MAX).contains(&self.anti)
+    }
+
+    pub fn apply(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        match self.shape {
+            DeadzoneShape::Axial => (
+                x.signum() * self.rescale(x.abs()),
+                y.signum() * self.rescale(y.abs()),
+            ),
+            DeadzoneShape::Radial => {
+                let magnitude = magnitude(x, y);
+                if magnitude <= self.inner {
+                    (0, 0)
+                } else if magnitude >= AXIS_MAX - self.outer {
+                    scale_vector(x, y, magnitude, AXIS_MAX)
+                } else {
+                    scale_vector(x, y, magnitude, magnitude.max(self.anti))
+                }
+            }
+            DeadzoneShape::ScaledRadial => {
+                let magnitude = magnitude(x, y);
+                scale_vector(x, y, magnitude, self.rescale(magnitude))
+            }
+        }
+    }
+
+    /// Maps a distance from the center between the inner and outer deadzones onto
+    /// `anti..=AXIS_MAX`.
+    #[inline]
+    fn rescale(&self, value: i32) -> i32 {
+        let edge = AXIS_MAX - self.outer;
+        if value <= self.inner {
+            0
+        } else if value >= edge {
+            AXIS_MAX
+        } else {
+            self.anti + (value - self.inner) * (AXIS_MAX - self.anti) / (edge - self.inner)
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn magnitude(x: i32, y: i32) -> i32 {
+    isqrt((x * x) as u32 + (y * y) as u32) as i32
+}
+
+/// Scales `(x, y)` of length `from` to length `to`, keeping its direction.
+#[inline]
+pub(crate) fn scale_vector(x: i32, y: i32, from: i32, to: i32) -> (i32, i32) {
+    if from == 0 {
+        (0, 0)
+    } else {
+        (x * to / from, y * to / from)
+    }
+}
+
+fn isqrt(value: u32) -> u32 {
+    let mut root = 0u32;
+    let mut bit = 1u32 << 30;
+    let mut rem = value;
+    while bit > rem {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INNER: i32 = 4096;
+    const OUTER: i32 = 2048;
+
+    fn deadzone(shape: DeadzoneShape, anti: i32) -> Deadzone {
+        Deadzone {
+            shape,
+            inner: INNER,
+            outer: OUTER,
+            anti,
+        }
+    }
+
+    #[test]
+    fn axial_ignores_each_axis_separately() {
+        let dz = deadzone(DeadzoneShape::Axial, 0);
+        assert_eq!(dz.apply((0, 0)), (0, 0));
+        assert_eq!(dz.apply((INNER, -INNER)), (0, 0));
+        let (x, y) = dz.apply((INNER - 1, 20000));
+        assert_eq!(x, 0);
+        assert!(y > 0 && y < 20000);
+        assert_eq!(
+            dz.apply((-AXIS_MAX, AXIS_MAX - OUTER)),
+            (-AXIS_MAX, AXIS_MAX)
+        );
+    }
+
+    #[test]
+    fn axial_is_continuous_at_inner_edge() {
+        let dz = deadzone(DeadzoneShape::Axial, 0);
+        let (x, _) = dz.apply((INNER + 1, 0));
+        assert!((0..=8).contains(&x));
+    }
+
+    #[test]
+    fn radial_ignores_inside_circle() {
+        let dz = deadzone(DeadzoneShape::Radial, 0);
+        assert_eq!(dz.apply((2000, 2000)), (0, 0));
+        assert_eq!(dz.apply((-2800, 2800)), (0, 0));
+        // outside of the circle even though each axis is within the inner size
+        let (x, y) = dz.apply((3500, 3500));
+        assert!((x - 3500).abs() <= 1 && (y - 3500).abs() <= 1);
+    }
+
+    #[test]
+    fn radial_passes_through_outside_circle() {
+        let dz = deadzone(DeadzoneShape::Radial, 0);
+        assert_eq!(dz.apply((10000, 0)), (10000, 0));
+        assert_eq!(dz.apply((0, -20000)), (0, -20000));
+        assert_eq!(dz.apply((AXIS_MAX - OUTER, 0)), (AXIS_MAX, 0));
+    }
+
+    #[test]
+    fn scaled_radial_starts_from_zero_at_circle() {
+        let dz = deadzone(DeadzoneShape::ScaledRadial, 0);
+        assert_eq!(dz.apply((INNER, 0)), (0, 0));
+        let (x, y) = dz.apply((INNER + 10, 0));
+        assert!(x > 0 && x <= 20);
+        assert_eq!(y, 0);
+        assert_eq!(dz.apply((0, -(AXIS_MAX - OUTER))), (0, -AXIS_MAX));
+    }
+
+    #[test]
+    fn scaled_radial_keeps_direction() {
+        let dz = deadzone(DeadzoneShape::ScaledRadial, 0);
+        let (x, y) = dz.apply((12000, -12000));
+        assert_eq!(x, -y);
+        assert!(x > 0 && x < 12000);
+    }
+
+    #[test]
+    fn scaled_radial_limits_corners_to_circle() {
+        let dz = deadzone(DeadzoneShape::ScaledRadial, 0);
+        let (x, y) = dz.apply((AXIS_MAX, AXIS_MAX));
+        assert_eq!(x, y);
+        assert!((magnitude(x, y) - AXIS_MAX).abs() <= 1);
+    }
+
+    #[test]
+    fn anti_deadzone_skips_small_deflection() {
+        for shape in [
+            DeadzoneShape::Axial,
+            DeadzoneShape::Radial,
+            DeadzoneShape::ScaledRadial,
+        ] {
+            let dz = deadzone(shape, 8000);
+            assert_eq!(dz.apply((INNER - 1, 0)), (0, 0));
+            let (x, _) = dz.apply((INNER + 1, 0));
+            assert!(x >= 8000, "{:?} gave {}", shape, x);
+            assert_eq!(dz.apply((AXIS_MAX, 0)), (AXIS_MAX, 0));
+        }
+    }
+
+    #[test]
+    fn default_deadzone_is_valid() {
+        assert!(Deadzone::default().is_valid());
+        assert!(!deadzone(DeadzoneShape::Axial, AXIS_MAX).is_valid());
+    }
+
+    #[test]
+    fn huge_inner_and_outer_are_invalid() {
+        let dz = Deadzone {
+            shape: DeadzoneShape::Axial,
+            inner: 2_000_000_000,
+            outer: 2_000_000_000,
+            anti: 0,
+        };
+        assert!(!dz.is_valid());
+        assert!(!Deadzone { outer: 0, ..dz }.is_valid());
+    }
+}
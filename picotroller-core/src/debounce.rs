@@ -0,0 +1,160 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DebounceStrategy {
+    /// Reports a change on the first edge, then ignores the input for the window. Lowest
+    /// latency, but a glitch on the line shows up as a press.
+    Eager,
+    /// Reports a change once the input has been stable for the window.
+    Deferred,
+}
+
+/// Debounces a single button from the raw edges seen in the GPIO interrupt. Times are in
+/// microseconds of the `Timer`.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Debouncer {
+    strategy: DebounceStrategy,
+    window: u64,
+    pressed: bool,
+    raw: bool,
+    last_edge: u64,
+    locked_until: u64,
+}
+
+impl Debouncer {
+    pub const fn new(strategy: DebounceStrategy, window: u64) -> Self {
+        Self {
+            strategy,
+            window,
+            pressed: false,
+            raw: false,
+            last_edge: 0,
+            locked_until: 0,
+        }
+    }
+
+    /// Records the level of the input after an edge at `now`.
+    pub fn edge(&mut self, pressed: bool, now: u64) {
+        self.raw = pressed;
+        self.last_edge = now;
+        if self.strategy == DebounceStrategy::Eager {
+            self.settle_eager(now);
+        }
+    }
+
+    /// Returns the debounced state of the input at `now`.
+    pub fn poll(&mut self, now: u64) -> bool {
+        match self.strategy {
+            DebounceStrategy::Eager => self.settle_eager(now),
+            DebounceStrategy::Deferred => {
+                if self.raw != self.pressed && now.wrapping_sub(self.last_edge) >= self.window {
+                    self.pressed = self.raw;
+                }
+            }
+        }
+        self.pressed
+    }
+
+    fn settle_eager(&mut self, now: u64) {
+        if self.raw != self.pressed && now >= self.locked_until {
+            self.pressed = self.raw;
+            self.locked_until = now + self.window;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 5_000;
+
+    /// Feeds `(time, level)` edges and returns the debounced state at each poll time.
+    fn run(strategy: DebounceStrategy, edges: &[(u64, bool)], polls: &[u64]) -> Vec<bool> {
+        let mut debouncer = Debouncer::new(strategy, WINDOW);
+        let mut edges = edges.iter().peekable();
+        polls
+            .iter()
+            .map(|&now| {
+                while let Some((time, pressed)) = edges.next_if(|(time, _)| *time <= now) {
+                    debouncer.edge(*pressed, *time);
+                }
+                debouncer.poll(now)
+            })
+            .collect()
+    }
+
+    /// A press bouncing for 2ms, held, then a release bouncing for 2ms.
+    const BOUNCY_PRESS: [(u64, bool); 10] = [
+        (1_000, true),
+        (1_300, false),
+        (1_700, true),
+        (2_200, false),
+        (3_000, true),
+        (20_000, false),
+        (20_400, true),
+        (21_000, false),
+        (21_500, true),
+        (22_000, false),
+    ];
+
+    #[test]
+    fn eager_reports_first_edge_and_ignores_bounce() {
+        let states = run(
+            DebounceStrategy::Eager,
+            &BOUNCY_PRESS,
+            &[
+                1_000, 1_300, 2_200, 5_999, 6_000, 19_000, 20_000, 21_500, 25_000, 30_000,
+            ],
+        );
+        assert_eq!(
+            states,
+            [true, true, true, true, true, true, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn eager_catches_up_with_release_during_window() {
+        let states = run(
+            DebounceStrategy::Eager,
+            &[(1_000, true), (2_000, false)],
+            &[1_000, 2_000, 5_999, 6_000],
+        );
+        assert_eq!(states, [true, true, true, false]);
+    }
+
+    #[test]
+    fn deferred_waits_for_stable_input() {
+        let states = run(
+            DebounceStrategy::Deferred,
+            &BOUNCY_PRESS,
+            &[
+                1_000, 2_200, 3_000, 7_999, 8_000, 20_000, 22_000, 26_999, 27_000,
+            ],
+        );
+        assert_eq!(
+            states,
+            [false, false, false, false, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn deferred_ignores_glitch_shorter_than_window() {
+        let states = run(
+            DebounceStrategy::Deferred,
+            &[(1_000, true), (1_200, false)],
+            &[1_000, 1_200, 6_000, 10_000],
+        );
+        assert_eq!(states, [false, false, false, false]);
+    }
+
+    #[test]
+    fn eager_reports_glitch_as_press() {
+        let states = run(
+            DebounceStrategy::Eager,
+            &[(1_000, true), (1_200, false)],
+            &[1_000, 1_200, 6_000, 10_000],
+        );
+        assert_eq!(states, [true, true, false, false]);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::platform::{HostCommand, HostLed, Rumble};
+use packed_struct::prelude::*;
+
+/// Hat switch value of a centered D-pad, outside of the logical range.
+pub const HAT_CENTER: u8 = 8;
+
+#[rustfmt::skip]
+pub const JOYSTICK_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x05, // Usage (Gamepad 0x05, Joystick 0x04)
+
+    0xA1, 0x01, // Collection (Application)
+        0x09, 0x01, //   Usage Page (Pointer)
+        0xA1, 0x00, //   Collection (Physical)
+            0x09, 0x30, //     Usage (X)
+            0x09, 0x31, //     Usage (Y)
+            0x09, 0x32, //     Usage (Z) - Left trigger, 0 when released
+            0x09, 0x33, //     Usage (RX) - Second joystick
+            0x09, 0x34, //     Usage (RY) - Second joystick
+            0x09, 0x35, //     Usage (RZ) - Right trigger, 0 when released
+            0x15, 0x81, //     Logical Minimum (-127)
+            0x25, 0x7f, //     Logical Maximum (127)
+            0x75, 0x08, //     Report Size
+            0x95, 0x06, //     Report count
+            0x81, 0x02, //     Input (Data, Variable, Absolute)
+        0xC0,       //   End Collection
+
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (0)
+        0x29, 0x10, //   Usage Maximum (16)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x10, //   Report Count (16)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+
+        0x05, 0x01, //   Usage Page (Generic Desktop)
+        0x09, 0x39, //   Usage (Hat switch) - D-pad, 8 when centered
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x07, //   Logical Maximum (7)
+        0x35, 0x00, //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14, //   Unit (Degrees)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x42, //   Input (Data, Variable, Absolute, Null State)
+        0x65, 0x00, //   Unit (None)
+        0x45, 0x00, //   Physical Maximum (0) - Back to the logical range
+
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x11, //   Usage Minimum (17) - D-pad up, down, left, right
+        0x29, 0x14, //   Usage Maximum (20)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x04, //   Report Count (4)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0x75, 0x04, //   Report Size (4) - Padding
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x03, //   Input (Constant)
+
+        0x06, 0x00, 0xFF, //   Usage Page (Vendor Defined)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x09, 0x01, //   Usage (0x01) - Rumble, heavy left and light right motor
+        0x95, 0x02, //   Report Count (2)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+        0x09, 0x02, //   Usage (0x02) - LED red, green and blue
+        0x95, 0x03, //   Report Count (3)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+        0x09, 0x03, //   Usage (0x03) - Player number, 0 for the LED colour
+        0x95, 0x01, //   Report Count (1)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xC0,       // End Collection
+];
+
+#[rustfmt::skip]
+pub const JOYSTICK_HIRES_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x05, // Usage (Gamepad 0x05, Joystick 0x04)
+
+    0xA1, 0x01, // Collection (Application)
+        0x09, 0x01, //   Usage Page (Pointer)
+        0xA1, 0x00, //   Collection (Physical)
+            0x09, 0x30, //     Usage (X)
+            0x09, 0x31, //     Usage (Y)
+            0x09, 0x32, //     Usage (Z) - Left trigger, 0 when released
+            0x09, 0x33, //     Usage (RX) - Second joystick
+            0x09, 0x34, //     Usage (RY) - Second joystick
+            0x09, 0x35, //     Usage (RZ) - Right trigger, 0 when released
+            0x16, 0x01, 0x80, //     Logical Minimum (-32767)
+            0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+            0x75, 0x10, //     Report Size (16)
+            0x95, 0x06, //     Report count
+            0x81, 0x02, //     Input (Data, Variable, Absolute)
+        0xC0,       //   End Collection
+
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (0)
+        0x29, 0x10, //   Usage Maximum (16)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x10, //   Report Count (16)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+
+        0x05, 0x01, //   Usage Page (Generic Desktop)
+        0x09, 0x39, //   Usage (Hat switch) - D-pad, 8 when centered
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x07, //   Logical Maximum (7)
+        0x35, 0x00, //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14, //   Unit (Degrees)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x42, //   Input (Data, Variable, Absolute, Null State)
+        0x65, 0x00, //   Unit (None)
+        0x45, 0x00, //   Physical Maximum (0) - Back to the logical range
+
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x11, //   Usage Minimum (17) - D-pad up, down, left, right
+        0x29, 0x14, //   Usage Maximum (20)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x04, //   Report Count (4)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0x75, 0x04, //   Report Size (4) - Padding
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x03, //   Input (Constant)
+
+        0x06, 0x00, 0xFF, //   Usage Page (Vendor Defined)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x09, 0x01, //   Usage (0x01) - Rumble, heavy left and light right motor
+        0x95, 0x02, //   Report Count (2)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+        0x09, 0x02, //   Usage (0x02) - LED red, green and blue
+        0x95, 0x03, //   Report Count (3)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+        0x09, 0x03, //   Usage (0x03) - Player number, 0 for the LED colour
+        0x95, 0x01, //   Report Count (1)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xC0,       // End Collection
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReportResolution {
+    /// 8 bit axes, understood by anything that takes a generic gamepad
+    Low = 0,
+    /// 16 bit axes, keeping the full ADC resolution for flight sims and precise aiming
+    High = 1,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "10")]
+pub struct JoystickReport {
+    #[packed_field]
+    pub ly: i8,
+    #[packed_field]
+    pub lx: i8,
+    #[packed_field]
+    pub lz: i8,
+    #[packed_field]
+    pub ry: i8,
+    #[packed_field]
+    pub rx: i8,
+    #[packed_field]
+    pub rz: i8,
+    #[packed_field]
+    pub buttons: u16,
+    /// 0 to 7 clockwise from up, `HAT_CENTER` when centered
+    #[packed_field]
+    pub hat: u8,
+    /// D-pad buttons up, down, left and right from the lowest bit
+    #[packed_field]
+    pub dpad: u8,
+}
+
+impl Default for JoystickReport {
+    fn default() -> Self {
+        Self {
+            ly: 0,
+            lx: 0,
+            lz: 0,
+            ry: 0,
+            rx: 0,
+            rz: 0,
+            buttons: 0,
+            hat: HAT_CENTER,
+            dpad: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "16")]
+pub struct JoystickHiResReport {
+    #[packed_field]
+    pub ly: i16,
+    #[packed_field]
+    pub lx: i16,
+    #[packed_field]
+    pub lz: i16,
+    #[packed_field]
+    pub ry: i16,
+    #[packed_field]
+    pub rx: i16,
+    #[packed_field]
+    pub rz: i16,
+    #[packed_field]
+    pub buttons: u16,
+    /// 0 to 7 clockwise from up, `HAT_CENTER` when centered
+    #[packed_field]
+    pub hat: u8,
+    /// D-pad buttons up, down, left and right from the lowest bit
+    #[packed_field]
+    pub dpad: u8,
+}
+
+impl Default for JoystickHiResReport {
+    fn default() -> Self {
+        Self {
+            ly: 0,
+            lx: 0,
+            lz: 0,
+            ry: 0,
+            rx: 0,
+            rz: 0,
+            buttons: 0,
+            hat: HAT_CENTER,
+            dpad: 0,
+        }
+    }
+}
+
+pub const JOYSTICK_OUTPUT_SIZE: usize = 6;
+
+/// Output report the host sends in HID mode, the same for both resolutions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "6")]
+pub struct JoystickOutputReport {
+    #[packed_field]
+    pub rumble_left: u8,
+    #[packed_field]
+    pub rumble_right: u8,
+    #[packed_field]
+    pub red: u8,
+    #[packed_field]
+    pub green: u8,
+    #[packed_field]
+    pub blue: u8,
+    /// Player number from 1, shown in place of the colour
+    #[packed_field]
+    pub player: u8,
+}
+
+impl From<JoystickOutputReport> for HostCommand {
+    fn from(report: JoystickOutputReport) -> Self {
+        let led = match report {
+            JoystickOutputReport { player: 1.., .. } => HostLed::Player(report.player),
+            JoystickOutputReport {
+                red: 0,
+                green: 0,
+                blue: 0,
+                ..
+            } => HostLed::Off,
+            _ => HostLed::Colour(report.red, report.green, report.blue),
+        };
+        Self {
+            rumble: Some(Rumble {
+                left: report.rumble_left,
+                right: report.rumble_right,
+            }),
+            led: Some(led),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_report_sets_rumble_and_led() {
+        let report = JoystickOutputReport::unpack(&[200, 10, 0, 0, 0, 0]).unwrap();
+        let rumble = Some(Rumble {
+            left: 200,
+            right: 10,
+        });
+        assert_eq!(
+            HostCommand::from(report),
+            HostCommand {
+                rumble,
+                led: Some(HostLed::Off)
+            }
+        );
+        let report = JoystickOutputReport::unpack(&[0, 0, 255, 128, 0, 0]).unwrap();
+        assert_eq!(
+            HostCommand::from(report).led,
+            Some(HostLed::Colour(255, 128, 0))
+        );
+        // The player number wins over the colour
+        let report = JoystickOutputReport::unpack(&[0, 0, 255, 128, 0, 2]).unwrap();
+        assert_eq!(HostCommand::from(report).led, Some(HostLed::Player(2)));
+    }
+}
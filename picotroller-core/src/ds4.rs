@@ -0,0 +1,303 @@
+use crate::platform::{HostCommand, HostLed, Rumble};
+
+pub const DS4_REPORT_SIZE: usize = 64;
+
+pub const REPORT_ID_INPUT: u8 = 0x01;
+pub const REPORT_ID_OUTPUT: u8 = 0x05;
+pub const REPORT_ID_CALIBRATION: u8 = 0x02;
+pub const REPORT_ID_PAIRING: u8 = 0x12;
+pub const REPORT_ID_FIRMWARE: u8 = 0xa3;
+
+/// Report descriptor of the DualShock 4, cut down to the input report, the output report and
+/// the feature reports hosts query when the controller is plugged in.
+#[rustfmt::skip]
+pub const DS4_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x05,       // Usage (Gamepad)
+    0xA1, 0x01,       // Collection (Application)
+        0x85, REPORT_ID_INPUT, // Report ID (1)
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x09, 0x32,       //   Usage (Z)
+        0x09, 0x35,       //   Usage (RZ)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, 0x04,       //   Report Count (4)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x09, 0x39,       //   Usage (Hat Switch)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x07,       //   Logical Maximum (7)
+        0x35, 0x00,       //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14,       //   Unit (Degrees)
+        0x75, 0x04,       //   Report Size (4)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x42,       //   Input (Data, Variable, Absolute, Null State)
+        0x65, 0x00,       //   Unit (None)
+
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x0E,       //   Usage Maximum (14)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x0E,       //   Report Count (14)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x06, 0x00, 0xFF, //   Usage Page (Vendor Defined)
+        0x09, 0x20,       //   Usage (0x20) Report counter
+        0x75, 0x06,       //   Report Size (6)
+        0x95, 0x01,       //   Report Count (1)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x3F,       //   Logical Maximum (63)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x09, 0x33,       //   Usage (RX) Left trigger
+        0x09, 0x34,       //   Usage (RY) Right trigger
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, 0x02,       //   Report Count (2)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x06, 0x00, 0xFF, //   Usage Page (Vendor Defined)
+        0x09, 0x21,       //   Usage (0x21) Timestamp, battery, motion and touchpad
+        0x95, 0x36,       //   Report Count (54)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x85, REPORT_ID_OUTPUT, // Report ID (5)
+        0x09, 0x22,       //   Usage (0x22) Rumble and light bar
+        0x95, 0x1F,       //   Report Count (31)
+        0x91, 0x02,       //   Output (Data, Variable, Absolute)
+
+        0x85, REPORT_ID_CALIBRATION, // Report ID (2)
+        0x09, 0x24,       //   Usage (0x24) Motion sensor calibration
+        0x95, 0x24,       //   Report Count (36)
+        0xB1, 0x02,       //   Feature (Data, Variable, Absolute)
+
+        0x85, REPORT_ID_PAIRING, // Report ID (18)
+        0x06, 0x02, 0xFF, //   Usage Page (Vendor Defined 2)
+        0x09, 0x21,       //   Usage (0x21) Pairing info
+        0x95, 0x0F,       //   Report Count (15)
+        0xB1, 0x02,       //   Feature (Data, Variable, Absolute)
+
+        0x85, REPORT_ID_FIRMWARE, // Report ID (163)
+        0x06, 0x80, 0xFF, //   Usage Page (Vendor Defined 80)
+        0x09, 0x20,       //   Usage (0x20) Firmware info
+        0x95, 0x30,       //   Report Count (48)
+        0xB1, 0x02,       //   Feature (Data, Variable, Absolute)
+    0xC0,             // End Collection
+];
+
+/// Button bits of `Ds4Report`, in the order they are packed after the hat.
+#[allow(unused)]
+pub mod buttons {
+    pub const SQUARE: u16 = 1 << 0;
+    pub const CROSS: u16 = 1 << 1;
+    pub const CIRCLE: u16 = 1 << 2;
+    pub const TRIANGLE: u16 = 1 << 3;
+    pub const L1: u16 = 1 << 4;
+    pub const R1: u16 = 1 << 5;
+    pub const L2: u16 = 1 << 6;
+    pub const R2: u16 = 1 << 7;
+    pub const SHARE: u16 = 1 << 8;
+    pub const OPTIONS: u16 = 1 << 9;
+    pub const L3: u16 = 1 << 10;
+    pub const R3: u16 = 1 << 11;
+    pub const PS: u16 = 1 << 12;
+    pub const TOUCHPAD: u16 = 1 << 13;
+}
+
+/// Hat value with no direction pressed, directions count clockwise from 0 for up.
+pub const HAT_CENTER: u8 = 8;
+
+/// Stick value of a centered axis, axes run from 0 to 255 with Y pointing down.
+pub const AXIS_CENTER: u8 = 0x80;
+
+/// Battery full while plugged in, upper nibble flags the cable.
+const BATTERY_CABLE_FULL: u8 = 0x1b;
+/// Set in the first byte of a touch point when no finger is on the touchpad.
+const TOUCH_INACTIVE: u8 = 0x80;
+/// Flags of the output report saying which of its parts to apply.
+const OUTPUT_RUMBLE: u8 = 0x01;
+const OUTPUT_LIGHT_BAR: u8 = 0x02;
+
+/// Controls of the DS4 input report. The hat shares a byte with the face buttons and the
+/// report counter, so it is packed by hand rather than with packed_struct.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ds4Report {
+    pub lx: u8,
+    pub ly: u8,
+    pub rx: u8,
+    pub ry: u8,
+    pub hat: u8,
+    pub buttons: u16,
+    pub lt: u8,
+    pub rt: u8,
+}
+
+impl Default for Ds4Report {
+    fn default() -> Self {
+        Self {
+            lx: AXIS_CENTER,
+            ly: AXIS_CENTER,
+            rx: AXIS_CENTER,
+            ry: AXIS_CENTER,
+            hat: HAT_CENTER,
+            buttons: 0,
+            lt: 0,
+            rt: 0,
+        }
+    }
+}
+
+impl Ds4Report {
+    /// Packs the full input report, `counter` is incremented by the caller for every report.
+    pub fn pack(&self, counter: u8) -> [u8; DS4_REPORT_SIZE] {
+        let mut data = [0u8; DS4_REPORT_SIZE];
+        data[0] = REPORT_ID_INPUT;
+        data[1] = self.lx;
+        data[2] = self.ly;
+        data[3] = self.rx;
+        data[4] = self.ry;
+        data[5] = (self.hat & 0x0f) | (self.buttons << 4) as u8;
+        data[6] = (self.buttons >> 4) as u8;
+        data[7] = ((self.buttons >> 12) & 0x03) as u8 | counter << 2;
+        data[8] = self.lt;
+        data[9] = self.rt;
+        data[30] = BATTERY_CABLE_FULL;
+        data[35] = TOUCH_INACTIVE;
+        data[39] = TOUCH_INACTIVE;
+        data
+    }
+}
+
+/// Reads the rumble and light bar of an output report, a black light bar hands the LED back
+/// to the controller status.
+pub fn parse_output(data: &[u8]) -> Option<HostCommand> {
+    let [REPORT_ID_OUTPUT, flags, _, _, right, left, red, green, blue, ..] = *data else {
+        return None;
+    };
+    let led = match (red, green, blue) {
+        (0, 0, 0) => HostLed::Off,
+        _ => HostLed::Colour(red, green, blue),
+    };
+    Some(HostCommand {
+        rumble: (flags & OUTPUT_RUMBLE != 0).then_some(Rumble { left, right }),
+        led: (flags & OUTPUT_LIGHT_BAR != 0).then_some(led),
+    })
+}
+
+/// Gyro biases, the range of each gyro and accelerometer axis, in the order hosts read them.
+/// Values are those of a typical controller, there are no motion sensors to calibrate.
+pub fn calibration_report() -> [u8; 37] {
+    const GYRO: i16 = 8_800;
+    const GYRO_SPEED: i16 = 540;
+    const ACCEL: i16 = 8_192;
+    #[rustfmt::skip]
+    let values: [i16; 17] = [
+        0, 0, 0,                               // gyro pitch, yaw and roll bias
+        GYRO, GYRO, GYRO, -GYRO, -GYRO, -GYRO, // gyro plus and minus of each axis
+        GYRO_SPEED, GYRO_SPEED,                // gyro speed plus and minus
+        ACCEL, -ACCEL, ACCEL, -ACCEL, ACCEL, -ACCEL, // accelerometer plus and minus
+    ];
+    let mut data = [0u8; 37];
+    data[0] = REPORT_ID_CALIBRATION;
+    for (bytes, value) in data[1..].chunks_exact_mut(2).zip(values) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+/// Bluetooth address of the controller and of the host it is paired with, there is no radio
+/// so the controller address is a fixed locally administered one.
+pub fn pairing_report() -> [u8; 16] {
+    let mut data = [0u8; 16];
+    data[0] = REPORT_ID_PAIRING;
+    data[1..7].copy_from_slice(&[0x01, 0x00, 0x50, 0x43, 0x49, 0x02]);
+    data[7..10].copy_from_slice(&[0x08, 0x25, 0x00]);
+    data
+}
+
+/// Build date and hardware / firmware versions.
+pub fn firmware_report() -> [u8; 49] {
+    let mut data = [0u8; 49];
+    data[0] = REPORT_ID_FIRMWARE;
+    data[1..12].copy_from_slice(b"Jan  1 2024");
+    data[17..25].copy_from_slice(b"00:00:00");
+    data[35..37].copy_from_slice(&0x0100u16.to_le_bytes());
+    data[41..43].copy_from_slice(&0x0100u16.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_controls_around_the_hat() {
+        let report = Ds4Report {
+            lx: 0x00,
+            ly: 0x80,
+            rx: 0xff,
+            ry: 0x7f,
+            hat: HAT_CENTER,
+            buttons: buttons::CROSS | buttons::R1 | buttons::PS,
+            lt: 0xff,
+            rt: 0x00,
+        };
+        let data = report.pack(5);
+        assert_eq!(data.len(), DS4_REPORT_SIZE);
+        assert_eq!(
+            data[..10],
+            [0x01, 0x00, 0x80, 0xff, 0x7f, 0x28, 0x02, 0x15, 0xff, 0x00]
+        );
+        assert_eq!(data[30], BATTERY_CABLE_FULL);
+    }
+
+    #[test]
+    fn counter_wraps_within_six_bits() {
+        let data = Ds4Report::default().pack(0x3f);
+        assert_eq!(data[7], 0xfc);
+    }
+
+    #[test]
+    fn feature_reports_match_descriptor_sizes() {
+        assert_eq!(calibration_report().len(), 1 + 0x24);
+        assert_eq!(pairing_report().len(), 1 + 0x0f);
+        assert_eq!(firmware_report().len(), 1 + 0x30);
+        assert_eq!(&calibration_report()[7..9], &8_800i16.to_le_bytes());
+    }
+
+    #[test]
+    fn output_report_sets_flagged_parts() {
+        let mut data = [0u8; 32];
+        data[..9].copy_from_slice(&[0x05, 0x03, 0x00, 0x00, 0x40, 0xc0, 0x00, 0x00, 0xff]);
+        assert_eq!(
+            parse_output(&data),
+            Some(HostCommand {
+                rumble: Some(Rumble {
+                    left: 0xc0,
+                    right: 0x40
+                }),
+                led: Some(HostLed::Colour(0, 0, 0xff)),
+            })
+        );
+        data[1] = OUTPUT_LIGHT_BAR;
+        data[8] = 0;
+        assert_eq!(
+            parse_output(&data),
+            Some(HostCommand {
+                rumble: None,
+                led: Some(HostLed::Off),
+            })
+        );
+        data[0] = REPORT_ID_INPUT;
+        assert_eq!(parse_output(&data), None);
+        assert_eq!(parse_output(&[REPORT_ID_OUTPUT, 0x03]), None);
+    }
+}
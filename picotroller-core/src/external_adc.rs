@@ -0,0 +1,451 @@
+//! Analog inputs beyond the four ADC pins of the RP2040, read from an external ADC over I2C
+//! or SPI. The converters sample on their own schedule, polled from the main loop, and the
+//! latest readings are taken on each tick like the built-in ADC.
+
+use crate::platform::{AnalogSource, StickAxis, Trigger};
+use core::convert::Infallible;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Most channels of a supported ADC.
+pub const MAX_CHANNELS: usize = 8;
+
+/// An ADC converting one channel at a time.
+pub trait ExternalAdc {
+    type Error;
+    const CHANNELS: usize;
+
+    /// Moves sampling on to the next channel in the `scan` mask without waiting, returning
+    /// the channel and its reading, scaled to 12 bits, once a conversion has finished.
+    fn poll(&mut self, scan: u8) -> Result<Option<(usize, u16)>, Self::Error>;
+}
+
+/// The channel after `last` that is in `scan`, wrapping around.
+fn next_channel(last: Option<usize>, scan: u8, count: usize) -> Option<usize> {
+    let start = last.map_or(0, |channel| channel + 1);
+    (start..start + count)
+        .map(|channel| channel % count)
+        .find(|channel| scan & 1 << channel != 0)
+}
+
+/// What a channel of an external ADC is wired to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AnalogInput {
+    Stick(StickAxis),
+    Trigger(Trigger),
+}
+
+/// An `AnalogSource` taking the inputs wired to an external ADC from it, and the rest from
+/// `base`. Until a channel has been read its input also comes from `base`.
+pub struct ExternalAnalog<S, A> {
+    base: S,
+    adc: A,
+    channels: [Option<AnalogInput>; MAX_CHANNELS],
+    readings: [Option<u16>; MAX_CHANNELS],
+}
+
+impl<S: AnalogSource, A: ExternalAdc> ExternalAnalog<S, A> {
+    /// `channels` gives the input wired to each channel of `adc`, only those are sampled.
+    pub fn new(base: S, adc: A, channels: [Option<AnalogInput>; MAX_CHANNELS]) -> Self {
+        Self {
+            base,
+            adc,
+            channels,
+            readings: [None; MAX_CHANNELS],
+        }
+    }
+
+    /// Collects finished conversions and starts the next, as often as the main loop runs.
+    pub fn sample(&mut self) -> Result<(), A::Error> {
+        let scan = self.channels[..A::CHANNELS]
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| input.is_some())
+            .fold(0, |scan, (channel, _)| scan | 1 << channel);
+        // At most one round, so a fast ADC can't hold up the main loop
+        for _ in 0..A::CHANNELS {
+            match self.adc.poll(scan)? {
+                Some((channel, value)) => self.readings[channel] = Some(value),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn reading(&self, input: AnalogInput) -> Option<u16> {
+        let channel = self.channels.iter().position(|c| *c == Some(input))?;
+        self.readings[channel]
+    }
+}
+
+impl<S: AnalogSource, A: ExternalAdc> AnalogSource for ExternalAnalog<S, A> {
+    fn read(&mut self, axis: StickAxis) -> u16 {
+        match self.reading(AnalogInput::Stick(axis)) {
+            Some(value) => value,
+            None => self.base.read(axis),
+        }
+    }
+
+    fn read_trigger(&mut self, trigger: Trigger) -> Option<u16> {
+        match self.reading(AnalogInput::Trigger(trigger)) {
+            Some(value) => Some(value),
+            None => self.base.read_trigger(trigger),
+        }
+    }
+}
+
+/// I2C address with the ADDR pin tied to ground.
+pub const ADS1115_ADDRESS: u8 = 0x48;
+
+mod ads1115 {
+    pub const REG_CONVERSION: u8 = 0x00;
+    pub const REG_CONFIG: u8 = 0x01;
+    /// Starts a conversion when written, reads back set once it has finished
+    pub const CONFIG_OS: u16 = 1 << 15;
+    /// Input multiplexer on AIN0 against ground, the next channels follow
+    pub const CONFIG_MUX_AIN0: u16 = 0b100 << 12;
+    /// Full scale of ±4.096V, the smallest range covering 3.3V
+    pub const CONFIG_PGA_4V096: u16 = 0b001 << 9;
+    pub const CONFIG_SINGLE_SHOT: u16 = 1 << 8;
+    /// 860 samples per second, about 1.2ms a conversion
+    pub const CONFIG_860SPS: u16 = 0b111 << 5;
+    pub const CONFIG_COMPARATOR_OFF: u16 = 0b11;
+    /// Reading at 3.3V, which the sticks and triggers are powered from
+    pub const FULL_SCALE_3V3: i32 = 26400;
+}
+
+/// TI ADS1115, a 16 bit ADC with 4 channels over I2C. Each channel is converted in single
+/// shot mode, started on one poll and read on the first poll after it has finished.
+pub struct Ads1115<I> {
+    i2c: I,
+    address: u8,
+    converting: Option<usize>,
+}
+
+impl<I, E> Ads1115<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            converting: None,
+        }
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u16, E> {
+        let mut data = [0; 2];
+        self.i2c.write_read(self.address, &[register], &mut data)?;
+        Ok(u16::from_be_bytes(data))
+    }
+
+    fn start(&mut self, channel: usize) -> Result<(), E> {
+        use ads1115::*;
+        let config = CONFIG_OS
+            | (CONFIG_MUX_AIN0 + ((channel as u16) << 12))
+            | CONFIG_PGA_4V096
+            | CONFIG_SINGLE_SHOT
+            | CONFIG_860SPS
+            | CONFIG_COMPARATOR_OFF;
+        let [high, low] = config.to_be_bytes();
+        self.i2c.write(self.address, &[REG_CONFIG, high, low])?;
+        self.converting = Some(channel);
+        Ok(())
+    }
+}
+
+impl<I, E> ExternalAdc for Ads1115<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+    const CHANNELS: usize = 4;
+
+    fn poll(&mut self, scan: u8) -> Result<Option<(usize, u16)>, E> {
+        let Some(channel) = self.converting else {
+            if let Some(next) = next_channel(None, scan, Self::CHANNELS) {
+                self.start(next)?;
+            }
+            return Ok(None);
+        };
+        if self.read_register(ads1115::REG_CONFIG)? & ads1115::CONFIG_OS == 0 {
+            return Ok(None);
+        }
+        let raw = self.read_register(ads1115::REG_CONVERSION)? as i16 as i32;
+        self.converting = None;
+        if let Some(next) = next_channel(Some(channel), scan, Self::CHANNELS) {
+            self.start(next)?;
+        }
+        let full_scale = ads1115::FULL_SCALE_3V3;
+        let value = raw.clamp(0, full_scale) * 4095 / full_scale;
+        Ok(Some((channel, value as u16)))
+    }
+}
+
+/// Microchip MCP3208, a 12 bit ADC with 8 channels over SPI, read with a single transfer
+/// per channel. The SPI bus should be in mode 0 at up to 1MHz for 3.3V.
+pub struct Mcp3208<S, P> {
+    spi: S,
+    cs: P,
+    last: Option<usize>,
+}
+
+impl<S, P> Mcp3208<S, P>
+where
+    S: Transfer<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    pub fn new(spi: S, mut cs: P) -> Self {
+        cs.set_high().unwrap();
+        Self {
+            spi,
+            cs,
+            last: None,
+        }
+    }
+}
+
+impl<S, P> ExternalAdc for Mcp3208<S, P>
+where
+    S: Transfer<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    type Error = S::Error;
+    const CHANNELS: usize = 8;
+
+    fn poll(&mut self, scan: u8) -> Result<Option<(usize, u16)>, S::Error> {
+        let Some(channel) = next_channel(self.last, scan, Self::CHANNELS) else {
+            return Ok(None);
+        };
+        // Start bit and single ended, then the channel, with the reading clocked out after
+        let mut data = [0x06 | (channel >> 2) as u8, ((channel & 0x3) << 6) as u8, 0];
+        self.cs.set_low().unwrap();
+        let result = self
+            .spi
+            .transfer(&mut data)
+            .map(|data| ((data[1] as u16 & 0x0f) << 8) | data[2] as u16);
+        self.cs.set_high().unwrap();
+        self.last = Some(channel);
+        Ok(Some((channel, result?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_channel_wraps_through_scan() {
+        let scan = 0b1010_0010;
+        assert_eq!(next_channel(None, scan, 8), Some(1));
+        assert_eq!(next_channel(Some(1), scan, 8), Some(5));
+        assert_eq!(next_channel(Some(5), scan, 8), Some(7));
+        assert_eq!(next_channel(Some(7), scan, 8), Some(1));
+        assert_eq!(next_channel(Some(1), 0b10, 8), Some(1));
+        assert_eq!(next_channel(None, 0, 8), None);
+        // Channels past the count are never scanned
+        assert_eq!(next_channel(None, 0b1_0000, 4), None);
+    }
+
+    /// An ADS1115 whose conversions take `busy` polls of the config register.
+    struct FakeAds1115 {
+        inputs: [i16; 4],
+        busy: u32,
+        config: u16,
+        remaining: u32,
+        pointer: u8,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl FakeAds1115 {
+        fn new(inputs: [i16; 4], busy: u32) -> Self {
+            Self {
+                inputs,
+                busy,
+                config: 0,
+                remaining: 0,
+                pointer: 0,
+                writes: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for FakeAds1115 {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            assert_eq!(address, ADS1115_ADDRESS);
+            self.writes.push(bytes.to_vec());
+            if let [ads1115::REG_CONFIG, high, low] = *bytes {
+                self.config = u16::from_be_bytes([high, low]) & !ads1115::CONFIG_OS;
+                self.remaining = self.busy;
+            }
+            Ok(())
+        }
+    }
+
+    impl WriteRead for FakeAds1115 {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            self.pointer = bytes[0];
+            let value = match self.pointer {
+                ads1115::REG_CONFIG => {
+                    if self.remaining == 0 {
+                        self.config | ads1115::CONFIG_OS
+                    } else {
+                        self.remaining -= 1;
+                        self.config
+                    }
+                }
+                _ => {
+                    let channel = (self.config >> 12 & 0b111) - 0b100;
+                    self.inputs[channel as usize] as u16
+                }
+            };
+            buffer.copy_from_slice(&value.to_be_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ads1115_converts_scanned_channels_in_turn() {
+        let i2c = FakeAds1115::new([0, 13200, -50, 30000], 2);
+        let mut adc = Ads1115::new(i2c, ADS1115_ADDRESS);
+        let scan = 0b1110;
+        let mut readings = Vec::new();
+        for _ in 0..12 {
+            readings.extend(adc.poll(scan).unwrap());
+        }
+        // Started on the first poll, busy for two more, then read on the next
+        assert_eq!(readings, [(1, 2047), (2, 0), (3, 4095)]);
+        assert_eq!(
+            adc.i2c.writes[0],
+            [ads1115::REG_CONFIG, 0b1101_0011, 0b1110_0011]
+        );
+    }
+
+    /// An MCP3208 answering each transfer with the reading of the requested channel.
+    struct FakeMcp3208 {
+        inputs: [u16; 8],
+        selected: bool,
+    }
+
+    impl Transfer<u8> for FakeMcp3208 {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            assert!(self.selected);
+            assert_eq!(words[0] & 0xf8, 0, "start bit at the end of the first byte");
+            assert_eq!(words[0] & 0x06, 0x06, "start bit and single ended");
+            let channel = ((words[0] & 1) << 2 | words[1] >> 6) as usize;
+            let [high, low] = self.inputs[channel].to_be_bytes();
+            words.copy_from_slice(&[0xff, 0xe0 | high, low]);
+            Ok(words)
+        }
+    }
+
+    struct ChipSelect<'a>(&'a core::cell::Cell<bool>);
+
+    impl OutputPin for ChipSelect<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+    }
+
+    /// Ties the fake chip select to the fake bus, selected while low.
+    struct SelectedMcp3208<'a> {
+        bus: FakeMcp3208,
+        cs: &'a core::cell::Cell<bool>,
+    }
+
+    impl Transfer<u8> for SelectedMcp3208<'_> {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            self.bus.selected = self.cs.get();
+            self.bus.transfer(words)
+        }
+    }
+
+    #[test]
+    fn mcp3208_reads_a_channel_per_poll() {
+        let cs = core::cell::Cell::new(true);
+        let bus = SelectedMcp3208 {
+            bus: FakeMcp3208 {
+                inputs: [0, 1, 2, 3, 4, 5, 4000, 4095],
+                selected: false,
+            },
+            cs: &cs,
+        };
+        let mut adc = Mcp3208::new(bus, ChipSelect(&cs));
+        assert!(!cs.get());
+        let readings: Vec<_> = (0..4).map(|_| adc.poll(0b1100_0001).unwrap()).collect();
+        assert_eq!(
+            readings,
+            [Some((0, 0)), Some((6, 4000)), Some((7, 4095)), Some((0, 0))]
+        );
+        assert!(!cs.get());
+        assert_eq!(adc.poll(0).unwrap(), None);
+    }
+
+    /// Centered sticks on the built-in ADC, without triggers.
+    struct Centered;
+
+    impl AnalogSource for Centered {
+        fn read(&mut self, _axis: StickAxis) -> u16 {
+            2048
+        }
+    }
+
+    /// Reads every scanned channel on each poll, as the channel number times 100.
+    struct Instant {
+        last: Option<usize>,
+        polls: u32,
+    }
+
+    impl ExternalAdc for Instant {
+        type Error = ();
+        const CHANNELS: usize = 4;
+
+        fn poll(&mut self, scan: u8) -> Result<Option<(usize, u16)>, ()> {
+            self.polls += 1;
+            let channel = next_channel(self.last, scan, Self::CHANNELS);
+            self.last = channel;
+            Ok(channel.map(|channel| (channel, channel as u16 * 100)))
+        }
+    }
+
+    #[test]
+    fn external_channels_replace_inputs() {
+        let mut channels = [None; MAX_CHANNELS];
+        channels[1] = Some(AnalogInput::Trigger(Trigger::Right));
+        channels[3] = Some(AnalogInput::Stick(StickAxis::LeftY));
+        let adc = Instant {
+            last: None,
+            polls: 0,
+        };
+        let mut analog = ExternalAnalog::new(Centered, adc, channels);
+        // Not read yet
+        assert_eq!(analog.read(StickAxis::LeftY), 2048);
+        assert_eq!(analog.read_trigger(Trigger::Right), None);
+
+        analog.sample().unwrap();
+        assert_eq!(analog.adc.polls, 4);
+        assert_eq!(analog.read(StickAxis::LeftX), 2048);
+        assert_eq!(analog.read(StickAxis::LeftY), 300);
+        assert_eq!(analog.read_trigger(Trigger::Left), None);
+        assert_eq!(analog.read_trigger(Trigger::Right), Some(100));
+    }
+}
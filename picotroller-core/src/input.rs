@@ -0,0 +1,402 @@
+use crate::controller::{Button, Control, Controller, Dpad};
+use crate::debounce::{DebounceStrategy, Debouncer};
+use crate::keyboard::Direction;
+use crate::platform::DigitalInput;
+
+// Cherry MX switches bounce for up to 5ms, the stick buttons are cheap tactile switches
+pub const DEBOUNCE_SWITCH: Debouncer = Debouncer::new(DebounceStrategy::Eager, 5_000);
+pub const DEBOUNCE_STICK: Debouncer = Debouncer::new(DebounceStrategy::Deferred, 10_000);
+
+/// GPIO level of a pressed button.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    Low,
+    High,
+}
+
+/// One entry of the pin map without its GPIO, so the path from pin edges to `Controller` can
+/// run on the host.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ButtonInput {
+    pub active: Level,
+    pub control: Control,
+    debouncer: Debouncer,
+}
+
+impl ButtonInput {
+    pub const fn new(active: Level, control: Control, debouncer: Debouncer) -> Self {
+        Self {
+            active,
+            control,
+            debouncer,
+        }
+    }
+
+    /// Whether the pin reading `high` means the button is pressed.
+    #[inline]
+    pub fn is_active(&self, high: bool) -> bool {
+        high == (self.active == Level::High)
+    }
+
+    /// Records the level of the pin after an edge at `now`.
+    pub fn edge(&mut self, high: bool, now: u64) {
+        self.debouncer.edge(self.is_active(high), now);
+    }
+}
+
+impl DigitalInput for ButtonInput {
+    fn control(&self) -> Control {
+        self.control
+    }
+
+    fn poll(&mut self, now: u64) -> bool {
+        self.debouncer.poll(now)
+    }
+}
+
+/// The debounced buttons and D-pad of one tick, read from every group of inputs. A control
+/// wired to several inputs, e.g. a GPIO and a key of an expander, is pressed while any of
+/// them is.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ButtonSet {
+    wired: [bool; Control::COUNT],
+    pressed: [bool; Control::COUNT],
+}
+
+impl ButtonSet {
+    /// Adds the state of `inputs` at `now`.
+    pub fn read<'a, I: DigitalInput + 'a>(
+        &mut self,
+        inputs: impl IntoIterator<Item = &'a mut I>,
+        now: u64,
+    ) {
+        for input in inputs {
+            let index = input.control().index();
+            self.wired[index] = true;
+            self.pressed[index] |= input.poll(now);
+        }
+    }
+
+    pub fn is_pressed(&self, control: Control) -> bool {
+        self.pressed[control.index()]
+    }
+
+    /// Copies the buttons and D-pad into the controller, leaving buttons no input is wired
+    /// to, and the D-pad if none of its directions are.
+    pub fn apply(&self, controller: &mut Controller) {
+        for button in Button::ALL {
+            let control = Control::Button(button);
+            if self.wired[control.index()] {
+                controller.set_button(button, self.is_pressed(control));
+            }
+        }
+        let directions = Direction::ALL.map(Control::Dpad);
+        if directions.iter().any(|control| self.wired[control.index()]) {
+            let mut held = Dpad::default();
+            for direction in Direction::ALL {
+                held.set(direction, self.is_pressed(Control::Dpad(direction)));
+            }
+            controller.set_dpad(held);
+        }
+    }
+}
+
+/// Copies the debounced state of every input into the controller.
+pub fn capture<'a, I: DigitalInput + 'a>(
+    inputs: impl IntoIterator<Item = &'a mut I>,
+    controller: &mut Controller,
+    now: u64,
+) {
+    let mut buttons = ButtonSet::default();
+    buttons.read(inputs, now);
+    buttons.apply(controller);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::buttons;
+    use crate::device::{JoystickHiResReport, JoystickReport};
+    use crate::ds4::{self, Ds4Report};
+    use crate::keyboard::{keys, KeyboardReport};
+    use crate::switch::{self, SwitchReport};
+    use crate::xinput::{self, XInputReport};
+
+    const WINDOW: u64 = 5_000;
+
+    /// A physical button, how the pin map wires it, and what each report shows while only
+    /// that button is held with the default profile.
+    struct ButtonCase {
+        button: Button,
+        active: Level,
+        hid: u16,
+        xinput: u16,
+        switch: u16,
+        ds4: u16,
+        key: u8,
+    }
+
+    #[rustfmt::skip]
+    const BUTTON_CASES: [ButtonCase; Button::COUNT] = [
+        ButtonCase { button: Button::ThumbL, active: Level::Low, hid: buttons::BTN_THUMBL, xinput: xinput::buttons::THUMB_L, switch: switch::buttons::THUMB_L, ds4: ds4::buttons::L3, key: keys::LEFT_SHIFT },
+        ButtonCase { button: Button::ThumbR, active: Level::Low, hid: buttons::BTN_THUMBR, xinput: xinput::buttons::THUMB_R, switch: switch::buttons::THUMB_R, ds4: ds4::buttons::R3, key: keys::MOUSE_MIDDLE },
+        ButtonCase { button: Button::UnderL, active: Level::High, hid: buttons::BTN_WEST, xinput: xinput::buttons::X, switch: switch::buttons::Y, ds4: ds4::buttons::SQUARE, key: keys::Q },
+        ButtonCase { button: Button::UnderR, active: Level::High, hid: buttons::BTN_NORTH, xinput: xinput::buttons::Y, switch: switch::buttons::X, ds4: ds4::buttons::TRIANGLE, key: keys::E },
+        ButtonCase { button: Button::FrontL, active: Level::High, hid: buttons::BTN_EAST, xinput: xinput::buttons::B, switch: switch::buttons::A, ds4: ds4::buttons::CIRCLE, key: keys::MOUSE_RIGHT },
+        ButtonCase { button: Button::FrontR, active: Level::High, hid: buttons::BTN_SOUTH, xinput: xinput::buttons::A, switch: switch::buttons::B, ds4: ds4::buttons::CROSS, key: keys::MOUSE_LEFT },
+        ButtonCase { button: Button::Start, active: Level::High, hid: buttons::BTN_START, xinput: xinput::buttons::START, switch: switch::buttons::PLUS, ds4: ds4::buttons::OPTIONS, key: keys::ESCAPE },
+        ButtonCase { button: Button::Select, active: Level::High, hid: buttons::BTN_SELECT, xinput: xinput::buttons::BACK, switch: switch::buttons::MINUS, ds4: ds4::buttons::SHARE, key: keys::TAB },
+    ];
+
+    /// Inputs wired like the pin map, each idle at the level opposite to its active one.
+    fn pin_map() -> Vec<ButtonInput> {
+        BUTTON_CASES
+            .iter()
+            .map(|case| {
+                let debouncer = Debouncer::new(DebounceStrategy::Eager, WINDOW);
+                ButtonInput::new(case.active, Control::Button(case.button), debouncer)
+            })
+            .collect()
+    }
+
+    fn centered_controller() -> Controller {
+        let mut controller = Controller::default();
+        for joy in [&mut controller.joy_l, &mut controller.joy_r] {
+            joy.x = 2048;
+            joy.y = 2048;
+        }
+        controller
+    }
+
+    /// Drives the pin of `button` to `pressed` and captures every input into `controller`.
+    fn set_pin(
+        inputs: &mut [ButtonInput],
+        controller: &mut Controller,
+        button: Button,
+        pressed: bool,
+        now: u64,
+    ) {
+        let input = inputs
+            .iter_mut()
+            .find(|input| input.control == Control::Button(button))
+            .unwrap();
+        let high = (input.active == Level::High) == pressed;
+        input.edge(high, now);
+        capture(inputs.iter_mut(), controller, now);
+    }
+
+    #[test]
+    fn cases_cover_every_button_once() {
+        for button in Button::ALL {
+            let count = BUTTON_CASES
+                .iter()
+                .filter(|case| case.button == button)
+                .count();
+            assert_eq!(count, 1, "{:?}", button);
+        }
+        for (index, case) in BUTTON_CASES.iter().enumerate() {
+            for other in &BUTTON_CASES[index + 1..] {
+                let overlap = (case.hid & other.hid)
+                    | (case.xinput & other.xinput)
+                    | (case.switch & other.switch)
+                    | (case.ds4 & other.ds4);
+                assert_eq!(overlap, 0, "{:?} {:?}", case.button, other.button);
+                assert_ne!(case.key, other.key, "{:?} {:?}", case.button, other.button);
+            }
+        }
+    }
+
+    #[test]
+    fn active_level_follows_pull() {
+        for case in &BUTTON_CASES {
+            let input = ButtonInput::new(
+                case.active,
+                Control::Button(case.button),
+                Debouncer::new(DebounceStrategy::Eager, WINDOW),
+            );
+            assert_eq!(input.is_active(true), case.active == Level::High);
+            assert_eq!(input.is_active(false), case.active == Level::Low);
+        }
+    }
+
+    #[test]
+    fn each_button_reaches_only_its_own_report_bits() {
+        for case in &BUTTON_CASES {
+            let mut inputs = pin_map();
+            let mut controller = centered_controller();
+            set_pin(&mut inputs, &mut controller, case.button, true, 1_000);
+
+            for button in Button::ALL {
+                assert_eq!(
+                    controller.is_pressed(button),
+                    button == case.button,
+                    "{:?} held, {:?}",
+                    case.button,
+                    button
+                );
+            }
+
+            let mut hid = JoystickReport::default();
+            controller.hid_report(&mut hid);
+            assert_eq!(hid.buttons, case.hid, "{:?}", case.button);
+            let mut hires = JoystickHiResReport::default();
+            controller.hid_report_hires(&mut hires);
+            assert_eq!(hires.buttons, case.hid, "{:?}", case.button);
+            let mut xinput = XInputReport::default();
+            controller.xinput_report(&mut xinput);
+            assert_eq!(xinput.buttons, case.xinput, "{:?}", case.button);
+            assert_eq!((xinput.lt, xinput.rt), (0, 0), "{:?}", case.button);
+            let mut switch = SwitchReport::default();
+            controller.switch_report(&mut switch);
+            assert_eq!(switch.buttons, case.switch, "{:?}", case.button);
+            let mut ds4 = Ds4Report::default();
+            controller.ds4_report(&mut ds4);
+            assert_eq!(ds4.buttons, case.ds4, "{:?}", case.button);
+            let mut keyboard = KeyboardReport::default();
+            controller.keyboard_report(&mut keyboard);
+            let held: Vec<_> = keyboard
+                .keys
+                .iter()
+                .copied()
+                .filter(|key| *key != keys::NONE)
+                .collect();
+            assert_eq!(held, [case.key], "{:?}", case.button);
+
+            set_pin(
+                &mut inputs,
+                &mut controller,
+                case.button,
+                false,
+                1_000 + WINDOW,
+            );
+            let mut hid = JoystickReport::default();
+            controller.hid_report(&mut hid);
+            assert_eq!(hid.buttons, 0, "{:?} released", case.button);
+        }
+    }
+
+    #[test]
+    fn all_buttons_held_together() {
+        let mut inputs = pin_map();
+        let mut controller = centered_controller();
+        for case in &BUTTON_CASES {
+            set_pin(&mut inputs, &mut controller, case.button, true, 1_000);
+        }
+        let mut hid = JoystickReport::default();
+        controller.hid_report(&mut hid);
+        let all = BUTTON_CASES.iter().fold(0, |mask, case| mask | case.hid);
+        assert_eq!(hid.buttons, all);
+        let mut xinput = XInputReport::default();
+        controller.xinput_report(&mut xinput);
+        let all = BUTTON_CASES.iter().fold(0, |mask, case| mask | case.xinput);
+        assert_eq!(xinput.buttons, all);
+    }
+
+    /// A stick axis at one end of its travel, and the axes each report shows for it as
+    /// `[lx, ly, rx, ry]`.
+    struct StickCase {
+        name: &'static str,
+        set: fn(&mut Controller),
+        hid: [i8; 4],
+        xinput: [i16; 4],
+        switch: [u8; 4],
+    }
+
+    const MAX: i16 = i16::MAX;
+
+    #[rustfmt::skip]
+    const STICK_CASES: [StickCase; 8] = [
+        StickCase { name: "left x high", set: |c| c.joy_l.x = 4095, hid: [-128, 0, 0, 0], xinput: [0, MAX, 0, 0], switch: [0x80, 0x00, 0x80, 0x80] },
+        StickCase { name: "left x low", set: |c| c.joy_l.x = 0, hid: [127, 0, 0, 0], xinput: [0, -MAX, 0, 0], switch: [0x80, 0xff, 0x80, 0x80] },
+        StickCase { name: "left y high", set: |c| c.joy_l.y = 4095, hid: [0, 127, 0, 0], xinput: [MAX, 0, 0, 0], switch: [0xff, 0x80, 0x80, 0x80] },
+        StickCase { name: "left y low", set: |c| c.joy_l.y = 0, hid: [0, -128, 0, 0], xinput: [-MAX, 0, 0, 0], switch: [0x00, 0x80, 0x80, 0x80] },
+        StickCase { name: "right x high", set: |c| c.joy_r.x = 4095, hid: [0, 0, -128, 0], xinput: [0, 0, 0, MAX], switch: [0x80, 0x80, 0x80, 0x00] },
+        StickCase { name: "right x low", set: |c| c.joy_r.x = 0, hid: [0, 0, 127, 0], xinput: [0, 0, 0, -MAX], switch: [0x80, 0x80, 0x80, 0xff] },
+        StickCase { name: "right y high", set: |c| c.joy_r.y = 4095, hid: [0, 0, 0, 127], xinput: [0, 0, MAX, 0], switch: [0x80, 0x80, 0xff, 0x80] },
+        StickCase { name: "right y low", set: |c| c.joy_r.y = 0, hid: [0, 0, 0, -128], xinput: [0, 0, -MAX, 0], switch: [0x80, 0x80, 0x00, 0x80] },
+    ];
+
+    #[test]
+    fn each_stick_axis_reaches_only_its_own_report_axis() {
+        for case in &STICK_CASES {
+            let mut controller = centered_controller();
+            (case.set)(&mut controller);
+
+            let mut hid = JoystickReport::default();
+            controller.hid_report(&mut hid);
+            assert_eq!([hid.lx, hid.ly, hid.rx, hid.ry], case.hid, "{}", case.name);
+            assert_eq!(hid.buttons, 0, "{}", case.name);
+            let mut xinput = XInputReport::default();
+            controller.xinput_report(&mut xinput);
+            assert_eq!(
+                [xinput.lx, xinput.ly, xinput.rx, xinput.ry],
+                case.xinput,
+                "{}",
+                case.name
+            );
+            let mut switch = SwitchReport::default();
+            controller.switch_report(&mut switch);
+            assert_eq!(
+                [switch.lx, switch.ly, switch.rx, switch.ry],
+                case.switch,
+                "{}",
+                case.name
+            );
+            let mut ds4 = Ds4Report::default();
+            controller.ds4_report(&mut ds4);
+            assert_eq!(
+                [ds4.lx, ds4.ly, ds4.rx, ds4.ry],
+                case.switch,
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn centered_sticks_report_center() {
+        let controller = centered_controller();
+        let mut hid = JoystickReport::default();
+        controller.hid_report(&mut hid);
+        assert_eq!(hid, JoystickReport::default());
+        let mut xinput = XInputReport::default();
+        controller.xinput_report(&mut xinput);
+        assert_eq!(xinput, XInputReport::default());
+        let mut switch = SwitchReport::default();
+        controller.switch_report(&mut switch);
+        assert_eq!(switch, SwitchReport::default());
+    }
+
+    #[test]
+    fn inputs_of_one_button_merge() {
+        let debouncer = Debouncer::new(DebounceStrategy::Eager, WINDOW);
+        let start = Control::Button(Button::Start);
+        let mut pins = [ButtonInput::new(Level::High, start, debouncer)];
+        let mut keys = [
+            ButtonInput::new(Level::Low, start, debouncer),
+            ButtonInput::new(Level::Low, Control::Button(Button::Select), debouncer),
+        ];
+        let mut controller = centered_controller();
+        controller.set_button(Button::ThumbL, true);
+        let mut read = |pins: &mut [ButtonInput], keys: &mut [ButtonInput], now| {
+            let mut buttons = ButtonSet::default();
+            buttons.read(pins.iter_mut(), now);
+            buttons.read(keys.iter_mut(), now);
+            buttons.apply(&mut controller);
+            (
+                controller.is_pressed(Button::Start),
+                controller.is_pressed(Button::ThumbL),
+            )
+        };
+
+        keys[0].edge(false, 1_000);
+        assert_eq!(read(&mut pins, &mut keys, 1_000), (true, true));
+        pins[0].edge(true, 2_000);
+        keys[0].edge(true, 10_000);
+        assert_eq!(read(&mut pins, &mut keys, 10_000), (true, true));
+        pins[0].edge(false, 20_000);
+        assert_eq!(read(&mut pins, &mut keys, 20_000), (false, true));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::calibration::AXIS_MAX;
+use crate::controller::Button;
+use packed_struct::prelude::*;
+
+/// Directions of the left stick that press a key, after the buttons in `KeyMap`, or of a
+/// D-pad.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::Left => "left",
+            Direction::Right => "right",
+        }
+    }
+}
+
+/// An input that emits a key, one of the buttons or a direction of the left stick.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyInput {
+    Button(Button),
+    Stick(Direction),
+}
+
+impl KeyInput {
+    fn index(&self) -> usize {
+        match self {
+            KeyInput::Button(button) => *button as usize,
+            KeyInput::Stick(direction) => Button::COUNT + *direction as usize,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Button::from_name(name).map(KeyInput::Button).or_else(|| {
+            Direction::ALL
+                .into_iter()
+                .find(|direction| direction.name() == name)
+                .map(KeyInput::Stick)
+        })
+    }
+}
+
+pub const KEY_INPUTS: usize = Button::COUNT + Direction::ALL.len();
+
+/// HID keyboard usages, with codes past the last keyboard usage standing in for mouse buttons.
+#[allow(unused)]
+pub mod keys {
+    pub const NONE: u8 = 0x00;
+    pub const A: u8 = 0x04;
+    pub const D: u8 = 0x07;
+    pub const E: u8 = 0x08;
+    pub const Q: u8 = 0x14;
+    pub const R: u8 = 0x15;
+    pub const S: u8 = 0x16;
+    pub const W: u8 = 0x1a;
+    pub const ENTER: u8 = 0x28;
+    pub const ESCAPE: u8 = 0x29;
+    pub const BACKSPACE: u8 = 0x2a;
+    pub const TAB: u8 = 0x2b;
+    pub const SPACE: u8 = 0x2c;
+    pub const RIGHT: u8 = 0x4f;
+    pub const LEFT: u8 = 0x50;
+    pub const DOWN: u8 = 0x51;
+    pub const UP: u8 = 0x52;
+    pub const LEFT_CTRL: u8 = 0xe0;
+    pub const LEFT_SHIFT: u8 = 0xe1;
+    pub const LEFT_ALT: u8 = 0xe2;
+
+    pub const MOUSE_LEFT: u8 = 0xf0;
+    pub const MOUSE_RIGHT: u8 = 0xf1;
+    pub const MOUSE_MIDDLE: u8 = 0xf2;
+
+    /// Keys known by name besides letters, digits and F keys.
+    pub const NAMES: [(&str, u8); 16] = [
+        ("enter", ENTER),
+        ("esc", ESCAPE),
+        ("backspace", BACKSPACE),
+        ("tab", TAB),
+        ("space", SPACE),
+        ("right", RIGHT),
+        ("left", LEFT),
+        ("down", DOWN),
+        ("up", UP),
+        ("ctrl", LEFT_CTRL),
+        ("shift", LEFT_SHIFT),
+        ("alt", LEFT_ALT),
+        ("mouse_left", MOUSE_LEFT),
+        ("mouse_right", MOUSE_RIGHT),
+        ("mouse_middle", MOUSE_MIDDLE),
+        ("none", NONE),
+    ];
+
+    /// A key by name, a single letter or digit, `f1` to `f12`, or a HID usage number.
+    pub fn from_name(name: &str) -> Option<u8> {
+        if let Some((_, code)) = NAMES.iter().find(|(key, _)| *key == name) {
+            return Some(*code);
+        }
+        match name.as_bytes() {
+            [letter @ b'a'..=b'z'] => return Some(A + letter - b'a'),
+            [b'0'] => return Some(0x27),
+            [digit @ b'1'..=b'9'] => return Some(0x1e + digit - b'1'),
+            _ => {}
+        }
+        if let Some(Ok(number @ 1..=12)) = name.strip_prefix('f').map(str::parse::<u8>) {
+            return Some(0x3a + number - 1);
+        }
+        name.parse().ok()
+    }
+
+    pub fn is_mouse(code: u8) -> bool {
+        (MOUSE_LEFT..=MOUSE_MIDDLE).contains(&code)
+    }
+}
+
+/// Keys emitted by the buttons and left stick in the keyboard modes, and the mouse speed of
+/// the right stick.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "13")]
+pub struct KeyMap {
+    /// One code per `KeyInput`, packed_struct needs a literal `KEY_INPUTS`
+    #[packed_field]
+    keys: [u8; 12],
+    /// Pixels per report at full deflection of the right stick.
+    #[packed_field]
+    pub mouse_speed: u8,
+}
+
+const _: () = assert!(KEY_INPUTS == 12);
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut map = Self {
+            keys: [keys::NONE; KEY_INPUTS],
+            mouse_speed: 12,
+        };
+        for (input, key) in [
+            (KeyInput::Button(Button::ThumbL), keys::LEFT_SHIFT),
+            (KeyInput::Button(Button::ThumbR), keys::MOUSE_MIDDLE),
+            (KeyInput::Button(Button::UnderL), keys::Q),
+            (KeyInput::Button(Button::UnderR), keys::E),
+            (KeyInput::Button(Button::FrontL), keys::MOUSE_RIGHT),
+            (KeyInput::Button(Button::FrontR), keys::MOUSE_LEFT),
+            (KeyInput::Button(Button::Start), keys::ESCAPE),
+            (KeyInput::Button(Button::Select), keys::TAB),
+            (KeyInput::Stick(Direction::Up), keys::W),
+            (KeyInput::Stick(Direction::Down), keys::S),
+            (KeyInput::Stick(Direction::Left), keys::A),
+            (KeyInput::Stick(Direction::Right), keys::D),
+        ] {
+            map.set(input, key);
+        }
+        map
+    }
+}
+
+impl KeyMap {
+    #[inline]
+    pub fn get(&self, input: KeyInput) -> u8 {
+        self.keys[input.index()]
+    }
+
+    pub fn set(&mut self, input: KeyInput, key: u8) {
+        self.keys[input.index()] = key;
+    }
+}
+
+/// Keys and mouse state for the keyboard modes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyboardReport {
+    /// Codes of the held keys, `keys::NONE` for inputs that are not held
+    pub keys: [u8; KEY_INPUTS],
+    /// Bit 0 left, bit 1 right, bit 2 middle
+    pub mouse_buttons: u8,
+    /// Mouse movement per report in 1/256 pixels, X right and Y down
+    pub mouse: (i32, i32),
+}
+
+impl KeyboardReport {
+    /// Keyboard usages of the held keys, without the mouse buttons.
+    pub fn pressed_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys
+            .iter()
+            .copied()
+            .filter(|key| *key != keys::NONE && !keys::is_mouse(*key))
+    }
+}
+
+/// Fraction of `AXIS_MAX` the left stick has to travel to press a direction key.
+pub const STICK_KEY_THRESHOLD: i32 = AXIS_MAX / 2;
+
+/// Mouse movement for a stick position, growing with the square of the deflection so small
+/// movements stay precise and full deflection moves quickly.
+pub fn mouse_velocity(speed: u8, (x, y): (i32, i32)) -> (i32, i32) {
+    let scale = |value: i32| {
+        let value = value as i64;
+        (speed as i64 * 256 * value * value.abs() / (AXIS_MAX as i64 * AXIS_MAX as i64)) as i32
+    };
+    (scale(x), scale(y))
+}
+
+/// Carries the fractions of a pixel between reports, so slow movements are not lost.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MouseMotion {
+    remainder: (i32, i32),
+}
+
+impl MouseMotion {
+    /// Whole pixels to move for a velocity in 1/256 pixels.
+    pub fn step(&mut self, (x, y): (i32, i32)) -> (i8, i8) {
+        let (dx, rx) = Self::whole_pixels(self.remainder.0 + x);
+        let (dy, ry) = Self::whole_pixels(self.remainder.1 + y);
+        self.remainder = (rx, ry);
+        (dx, dy)
+    }
+
+    fn whole_pixels(value: i32) -> (i8, i32) {
+        let pixels = (value / 256).clamp(i8::MIN as i32 + 1, i8::MAX as i32);
+        (pixels as i8, (value - pixels * 256).clamp(-255, 255))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names() {
+        assert_eq!(keys::from_name("w"), Some(keys::W));
+        assert_eq!(keys::from_name("0"), Some(0x27));
+        assert_eq!(keys::from_name("1"), Some(0x1e));
+        assert_eq!(keys::from_name("f12"), Some(0x45));
+        assert_eq!(keys::from_name("mouse_left"), Some(keys::MOUSE_LEFT));
+        assert_eq!(keys::from_name("44"), Some(keys::SPACE));
+        assert_eq!(keys::from_name("f13"), None);
+        assert_eq!(keys::from_name("shiftt"), None);
+    }
+
+    #[test]
+    fn key_inputs() {
+        let map = KeyMap::default();
+        assert_eq!(map.get(KeyInput::from_name("start").unwrap()), keys::ESCAPE);
+        assert_eq!(map.get(KeyInput::from_name("left").unwrap()), keys::A);
+        assert_eq!(KeyInput::from_name("sideways"), None);
+    }
+
+    #[test]
+    fn mouse_velocity_is_quadratic() {
+        assert_eq!(mouse_velocity(10, (0, 0)), (0, 0));
+        assert_eq!(mouse_velocity(10, (AXIS_MAX, -AXIS_MAX)), (2560, -2560));
+        let (half, _) = mouse_velocity(10, (AXIS_MAX / 2, 0));
+        assert!((half - 640).abs() <= 1);
+    }
+
+    #[test]
+    fn mouse_motion_keeps_fractions() {
+        let mut motion = MouseMotion::default();
+        let moves: Vec<_> = (0..4).map(|_| motion.step((100, -100))).collect();
+        assert_eq!(moves, [(0, 0), (0, 0), (1, -1), (0, 0)]);
+        assert_eq!(motion.step((600, 0)), (2, 0));
+    }
+
+    #[test]
+    fn mouse_motion_clamps_to_report_range() {
+        let mut motion = MouseMotion::default();
+        assert_eq!(motion.step((256 * 1000, -256 * 1000)), (127, -127));
+        assert_eq!(motion.step((0, 0)), (0, 0));
+    }
+}
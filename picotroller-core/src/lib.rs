@@ -0,0 +1,47 @@
+//! Controller logic of the Picotroller, independent of the RP2040 and its USB stack.
+//!
+//! The firmware implements the traits in [`platform`] for its pins, ADC, LED and USB classes
+//! and drives a [`pipeline::Pipeline`] with them. Without the `defmt` feature the crate
+//! builds for the host, where the tests run against mock hardware.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Logs through defmt when the feature is enabled, and compiles to nothing otherwise.
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::info!($($arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::warn!($($arg)*);
+    }};
+}
+
+pub mod calibration;
+pub mod console;
+pub mod controller;
+pub mod curve;
+pub mod deadzone;
+pub mod debounce;
+pub mod device;
+pub mod ds4;
+pub mod external_adc;
+pub mod input;
+pub mod keyboard;
+pub mod macros;
+pub mod mode;
+pub mod pipeline;
+pub mod platform;
+pub mod profile;
+pub mod remap;
+pub mod scanner;
+pub mod settings;
+pub mod store;
+pub mod switch;
+pub mod trigger;
+pub mod turbo;
+pub mod xinput;
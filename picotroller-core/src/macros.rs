@@ -0,0 +1,516 @@
+//! Button macros: sequences of controller states recorded from a live session, played back
+//! in place of the inputs when their trigger button is pressed.
+
+use crate::controller::{Button, Controller, Dpad};
+use crate::trigger::TriggerState;
+use packed_struct::prelude::*;
+
+pub const MACRO_COUNT: usize = 2;
+/// Steps of a macro, each a state held for up to 255 ticks. As many as fit the settings
+/// record next to the profiles.
+pub const MACRO_STEPS: usize = 24;
+/// Packed size of a `MacroStep`.
+pub const MACRO_STEP_SIZE: usize = 9;
+/// Packed size of the trigger, flags and length before the steps of a `Macro`.
+pub const MACRO_HEADER_SIZE: usize = 3;
+/// Packed size of a `Macro`.
+pub const MACRO_SIZE: usize = MACRO_HEADER_SIZE + MACRO_STEPS * MACRO_STEP_SIZE;
+
+/// Change in a stored stick or trigger reading still recorded as the same step, so ADC
+/// noise on a resting stick doesn't use up the steps.
+const STICK_TOLERANCE: u8 = 4;
+const NO_TRIGGER: u8 = 0xff;
+const FLAG_LOOP: u8 = 1 << 0;
+const FLAG_CANCEL: u8 = 1 << 1;
+
+/// The buttons, D-pad, sticks and triggers of the controller, held for a number of ticks.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "9")]
+pub struct MacroStep {
+    /// Bit per held button, indexed like `RemapTable`
+    #[packed_field]
+    pub buttons: u8,
+    /// Raw readings in `StickAxis` order, the top 8 of their 12 bits
+    #[packed_field]
+    pub sticks: [u8; 4],
+    #[packed_field]
+    pub ticks: u8,
+    /// Directions of the cleaned D-pad, as `Dpad::bits`. Added with the triggers in
+    /// settings version 9.
+    #[packed_field]
+    pub dpad: u8,
+    /// Raw trigger readings in `Trigger` order like the sticks, 0 while not wired
+    #[packed_field]
+    pub triggers: [u8; 2],
+}
+
+impl MacroStep {
+    /// The state of `controller`, for a single tick.
+    pub fn capture(controller: &Controller) -> Self {
+        let buttons = Button::ALL
+            .into_iter()
+            .filter(|&button| controller.is_pressed(button))
+            .fold(0, |mask, button| mask | 1 << button as u8);
+        let raw = [
+            controller.joy_l.x,
+            controller.joy_l.y,
+            controller.joy_r.x,
+            controller.joy_r.y,
+        ];
+        let triggers = [controller.trigger_l.raw, controller.trigger_r.raw];
+        Self {
+            buttons,
+            sticks: raw.map(stored_reading),
+            ticks: 1,
+            dpad: controller.dpad.bits(),
+            triggers: triggers.map(|raw| raw.map_or(0, stored_reading)),
+        }
+    }
+
+    /// Replaces the inputs of `controller` with this state, taking each stick and trigger
+    /// reading to the middle of the range it was stored from. Triggers that are not wired
+    /// stay that way.
+    pub fn apply(&self, controller: &mut Controller) {
+        for button in Button::ALL {
+            controller.set_button(button, self.buttons & 1 << button as u8 != 0);
+        }
+        // Already cleaned when recorded
+        controller.dpad = Dpad::from_bits(self.dpad);
+        let [lx, ly, rx, ry] = self.sticks.map(replayed_reading);
+        controller.joy_l.x = lx;
+        controller.joy_l.y = ly;
+        controller.joy_r.x = rx;
+        controller.joy_r.y = ry;
+        let triggers = [&mut controller.trigger_l, &mut controller.trigger_r];
+        for (trigger, value) in triggers.into_iter().zip(self.triggers) {
+            replay_trigger(trigger, value);
+        }
+        // Played back as recorded, turbo applies to the live buttons only
+        controller.turbo_released = 0;
+    }
+
+    /// Whether `other` is the same state, give or take stick noise.
+    fn matches(&self, other: &Self) -> bool {
+        self.buttons == other.buttons
+            && self.dpad == other.dpad
+            && self
+                .sticks
+                .iter()
+                .chain(&self.triggers)
+                .zip(other.sticks.iter().chain(&other.triggers))
+                .all(|(a, b)| a.abs_diff(*b) <= STICK_TOLERANCE)
+    }
+}
+
+/// Top 8 bits of a 12 bit reading.
+fn stored_reading(value: u16) -> u8 {
+    (value >> 4).min(0xff) as u8
+}
+
+/// The middle of the range of readings stored as `value`.
+fn replayed_reading(value: u8) -> u16 {
+    (value as u16) << 4 | 0x8
+}
+
+fn replay_trigger(trigger: &mut TriggerState, value: u8) {
+    if trigger.raw.is_some() {
+        trigger.update(Some(replayed_reading(value)));
+    }
+}
+
+/// A recorded sequence, its trigger button and how it plays back.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "219")]
+pub struct Macro {
+    /// Index of the button starting playback, or `NO_TRIGGER`
+    #[packed_field]
+    trigger: u8,
+    #[packed_field]
+    flags: u8,
+    #[packed_field]
+    len: u8,
+    // packed_struct needs a literal `MACRO_STEPS`
+    #[packed_field(element_size_bytes = "9")]
+    steps: [MacroStep; 24],
+}
+
+const _: () = assert!(MACRO_STEPS == 24 && MACRO_STEP_SIZE == 9);
+
+impl Default for Macro {
+    fn default() -> Self {
+        Self {
+            trigger: NO_TRIGGER,
+            flags: FLAG_CANCEL,
+            len: 0,
+            steps: [MacroStep::default(); MACRO_STEPS],
+        }
+    }
+}
+
+impl Macro {
+    pub fn trigger(&self) -> Option<Button> {
+        Button::ALL.get(self.trigger as usize).copied()
+    }
+
+    pub fn set_trigger(&mut self, trigger: Option<Button>) {
+        self.trigger = trigger.map_or(NO_TRIGGER, |button| button as u8);
+    }
+
+    /// Whether playback starts over at the end, until stopped.
+    pub fn is_looping(&self) -> bool {
+        self.flags & FLAG_LOOP != 0
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.set_flag(FLAG_LOOP, looping);
+    }
+
+    /// Whether pressing any button stops playback, otherwise only the trigger does.
+    pub fn cancels_on_press(&self) -> bool {
+        self.flags & FLAG_CANCEL != 0
+    }
+
+    pub fn set_cancel_on_press(&mut self, cancel: bool) {
+        self.set_flag(FLAG_CANCEL, cancel);
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    pub fn steps(&self) -> &[MacroStep] {
+        &self.steps[..self.len as usize]
+    }
+
+    /// Length of the recording in ticks.
+    pub fn ticks(&self) -> u32 {
+        self.steps().iter().map(|step| step.ticks as u32).sum()
+    }
+
+    /// Drops the recording, keeping the trigger and playback options.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.steps = [MacroStep::default(); MACRO_STEPS];
+    }
+
+    /// Adds a tick of `state` to the recording, extending the last step if the state has not
+    /// changed. Returns false if the macro is full.
+    pub fn push(&mut self, state: MacroStep) -> bool {
+        let len = self.len as usize;
+        if let Some(last) = self.steps[..len].last_mut() {
+            if last.matches(&state) && last.ticks < u8::MAX {
+                last.ticks += 1;
+                return true;
+            }
+        }
+        if len == MACRO_STEPS {
+            return false;
+        }
+        self.steps[len] = MacroStep { ticks: 1, ..state };
+        self.len += 1;
+        true
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (self.trigger == NO_TRIGGER || self.trigger().is_some())
+            && self.flags & !(FLAG_LOOP | FLAG_CANCEL) == 0
+            && self.len as usize <= MACRO_STEPS
+            && self.steps().iter().all(|step| step.ticks > 0)
+    }
+}
+
+/// Records the controller state into a macro every tick, from the first tick it differs
+/// from the state recording started in.
+#[derive(Clone, Copy, Debug)]
+pub struct Recorder {
+    pub index: usize,
+    rest: Option<MacroStep>,
+    started: bool,
+}
+
+impl Recorder {
+    /// Starts recording into macro `index`, which should have been cleared.
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            rest: None,
+            started: false,
+        }
+    }
+
+    /// Records a tick of `controller`, returns false once `sequence` is full.
+    pub fn update(&mut self, sequence: &mut Macro, controller: &Controller) -> bool {
+        let state = MacroStep::capture(controller);
+        let rest = *self.rest.get_or_insert(state);
+        self.started |= !state.matches(&rest);
+        !self.started || sequence.push(state)
+    }
+}
+
+/// Plays a macro back into the controller, a step at a time.
+#[derive(Clone, Copy, Debug)]
+pub struct Player {
+    pub index: usize,
+    step: usize,
+    tick: u8,
+}
+
+impl Player {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            step: 0,
+            tick: 0,
+        }
+    }
+
+    /// Replaces the inputs of `controller` with the state of this tick, returns false
+    /// without touching them once `sequence` has ended.
+    pub fn update(&mut self, sequence: &Macro, controller: &mut Controller) -> bool {
+        // The sequence may have been shortened under a running player
+        if self.step >= sequence.steps().len() {
+            if !sequence.is_looping() || sequence.steps().is_empty() {
+                return false;
+            }
+            self.step = 0;
+        }
+        let step = sequence.steps()[self.step];
+        step.apply(controller);
+        self.tick += 1;
+        if self.tick >= step.ticks {
+            self.tick = 0;
+            self.step += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(buttons: &[Button], lx: u16) -> MacroStep {
+        let mut controller = Controller::default();
+        for &button in buttons {
+            controller.set_button(button, true);
+        }
+        controller.joy_l.x = lx;
+        MacroStep::capture(&controller)
+    }
+
+    #[test]
+    fn captures_and_applies_state() {
+        let mut controller = Controller::default();
+        controller.set_button(Button::FrontR, true);
+        controller.set_button(Button::ThumbL, true);
+        controller.joy_l.x = 4095;
+        controller.joy_l.y = 0;
+        controller.joy_r.x = 2048;
+        controller.joy_r.y = 1000;
+        controller.dpad.up = true;
+        controller.dpad.right = true;
+        controller.trigger_l.update(Some(4095));
+        controller.trigger_r.update(Some(1000));
+        let step = MacroStep::capture(&controller);
+        assert_eq!(step.sticks, [0xff, 0x00, 0x80, 0x3e]);
+        assert_eq!(step.triggers, [0xff, 0x3e]);
+        assert_eq!(Dpad::from_bits(step.dpad), controller.dpad);
+
+        let mut replayed = Controller::default();
+        replayed.set_button(Button::Start, true);
+        replayed.dpad.down = true;
+        replayed.trigger_l.update(Some(0));
+        replayed.trigger_r.update(Some(0));
+        replayed.turbo_released = 0xff;
+        step.apply(&mut replayed);
+        for button in Button::ALL {
+            assert_eq!(
+                replayed.is_pressed(button),
+                controller.is_pressed(button),
+                "{:?}",
+                button
+            );
+        }
+        assert_eq!(replayed.turbo_released, 0);
+        let sticks = [
+            replayed.joy_l.x,
+            replayed.joy_l.y,
+            replayed.joy_r.x,
+            replayed.joy_r.y,
+        ];
+        assert_eq!(sticks, [4088, 8, 2056, 0x3e8]);
+        assert_eq!(replayed.dpad, controller.dpad);
+        assert_eq!(replayed.trigger_l.raw, Some(4088));
+        assert_eq!(replayed.trigger_r.raw, Some(0x3e8));
+        assert!(replayed.trigger_l.is_pressed());
+
+        // Triggers that are not wired are left alone
+        let mut unwired = Controller::default();
+        step.apply(&mut unwired);
+        assert_eq!(unwired.trigger_l.raw, None);
+    }
+
+    #[test]
+    fn merges_unchanged_ticks() {
+        let mut sequence = Macro::default();
+        assert!(sequence.push(state(&[], 2048)));
+        // Noise on the stick is not a new step
+        assert!(sequence.push(state(&[], 2048 + 16 * STICK_TOLERANCE as u16)));
+        assert!(sequence.push(state(&[Button::FrontR], 2048)));
+        assert!(sequence.push(state(&[Button::FrontR], 3000)));
+        let ticks: Vec<_> = sequence.steps().iter().map(|step| step.ticks).collect();
+        assert_eq!(ticks, [2, 1, 1]);
+        assert_eq!(sequence.ticks(), 4);
+    }
+
+    #[test]
+    fn long_holds_take_more_steps() {
+        let mut sequence = Macro::default();
+        for _ in 0..300 {
+            assert!(sequence.push(state(&[Button::FrontR], 2048)));
+        }
+        let ticks: Vec<_> = sequence.steps().iter().map(|step| step.ticks).collect();
+        assert_eq!(ticks, [255, 45]);
+    }
+
+    #[test]
+    fn stops_when_full() {
+        let mut sequence = Macro::default();
+        for index in 0..MACRO_STEPS {
+            let buttons: &[Button] = if index % 2 == 0 {
+                &[Button::Start]
+            } else {
+                &[]
+            };
+            assert!(sequence.push(state(buttons, 2048)));
+        }
+        assert!(!sequence.push(state(&[Button::Start], 2048)));
+        // The last step can still be held longer
+        assert!(sequence.push(state(&[], 2048)));
+        assert_eq!(sequence.steps().len(), MACRO_STEPS);
+    }
+
+    #[test]
+    fn recording_starts_at_first_change() {
+        let mut sequence = Macro::default();
+        let mut recorder = Recorder::new(0);
+        let mut controller = Controller::default();
+        for _ in 0..10 {
+            assert!(recorder.update(&mut sequence, &controller));
+        }
+        assert!(sequence.steps().is_empty());
+        controller.set_button(Button::UnderL, true);
+        recorder.update(&mut sequence, &controller);
+        controller.set_button(Button::UnderL, false);
+        recorder.update(&mut sequence, &controller);
+        recorder.update(&mut sequence, &controller);
+        let steps: Vec<_> = sequence
+            .steps()
+            .iter()
+            .map(|step| (step.buttons, step.ticks))
+            .collect();
+        assert_eq!(steps, [(1 << Button::UnderL as u8, 1), (0, 2)]);
+    }
+
+    /// The buttons held at each tick of playing `sequence` back, until it ends.
+    fn play(sequence: &Macro, ticks: usize) -> Vec<bool> {
+        let mut player = Player::new(0);
+        let mut controller = Controller::default();
+        let mut held = Vec::new();
+        while held.len() < ticks && player.update(sequence, &mut controller) {
+            held.push(controller.is_pressed(Button::FrontR));
+        }
+        held
+    }
+
+    #[test]
+    fn plays_steps_for_their_ticks() {
+        let mut sequence = Macro::default();
+        for pressed in [true, true, false, true] {
+            let buttons: &[Button] = if pressed { &[Button::FrontR] } else { &[] };
+            sequence.push(state(buttons, 2048));
+        }
+        assert_eq!(play(&sequence, 100), [true, true, false, true]);
+
+        sequence.set_looping(true);
+        assert_eq!(
+            play(&sequence, 9),
+            [true, true, false, true, true, true, false, true, true]
+        );
+        // Nothing recorded, nothing to loop
+        assert!(play(&Macro::default(), 1).is_empty());
+    }
+
+    #[test]
+    fn shortened_sequence_ends_playback() {
+        let mut sequence = Macro::default();
+        for pressed in [true, false, true] {
+            let buttons: &[Button] = if pressed { &[Button::FrontR] } else { &[] };
+            sequence.push(state(buttons, 2048));
+        }
+        sequence.set_looping(true);
+        let mut player = Player::new(0);
+        let mut controller = Controller::default();
+        for _ in 0..2 {
+            assert!(player.update(&sequence, &mut controller));
+        }
+        sequence.clear();
+        assert!(!player.update(&sequence, &mut controller));
+    }
+
+    #[test]
+    fn validates_stored_macro() {
+        let mut sequence = Macro::default();
+        assert!(sequence.is_valid());
+        sequence.set_trigger(Some(Button::Select));
+        sequence.set_looping(true);
+        sequence.push(state(&[Button::Start], 2048));
+        assert!(sequence.is_valid());
+        assert_eq!(Macro::unpack(&sequence.pack().unwrap()), Ok(sequence));
+
+        let invalid = [
+            Macro {
+                trigger: 8,
+                ..sequence
+            },
+            Macro {
+                flags: 0x4,
+                ..sequence
+            },
+            Macro {
+                len: MACRO_STEPS as u8 + 1,
+                ..sequence
+            },
+            Macro { len: 2, ..sequence },
+        ];
+        for sequence in invalid {
+            assert!(!sequence.is_valid(), "{:?}", sequence);
+        }
+    }
+
+    #[test]
+    fn options_keep_each_other() {
+        let mut sequence = Macro::default();
+        assert_eq!(sequence.trigger(), None);
+        assert!(sequence.cancels_on_press());
+        assert!(!sequence.is_looping());
+        sequence.set_looping(true);
+        sequence.set_cancel_on_press(false);
+        sequence.set_trigger(Some(Button::ThumbR));
+        assert!(sequence.is_looping());
+        assert!(!sequence.cancels_on_press());
+        assert_eq!(sequence.trigger(), Some(Button::ThumbR));
+        sequence.push(state(&[], 0));
+        sequence.clear();
+        assert!(sequence.steps().is_empty());
+        assert!(sequence.is_looping());
+        sequence.set_trigger(None);
+        assert_eq!(sequence.trigger(), None);
+    }
+}
@@ -0,0 +1,40 @@
+use packed_struct::prelude::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbMode {
+    /// Generic HID gamepad
+    Hid = 0,
+    /// Wired Xbox 360 controller
+    XInput = 1,
+    /// HORI Pokken pad, for the Nintendo Switch
+    Switch = 2,
+    /// Sony DualShock 4
+    Ds4 = 3,
+    /// Keyboard and mouse
+    Keyboard = 4,
+    /// Keyboard and mouse next to the generic HID gamepad
+    KeyboardJoystick = 5,
+}
+
+impl UsbMode {
+    /// USB vendor and product ID of the mode.
+    pub fn vid_pid(&self) -> (u16, u16) {
+        match self {
+            // pid.codes test PID, the Microsoft IDs would make Windows expect XInput
+            Self::Hid => (0x1209, 0x0001),
+            Self::XInput => (0x045e, 0x028e),
+            Self::Switch => (0x0f0d, 0x0092),
+            Self::Ds4 => (0x054c, 0x09cc),
+            // Hosts cache the interfaces of a VID/PID, so each layout gets its own PID
+            Self::Keyboard => (0x1209, 0x0002),
+            Self::KeyboardJoystick => (0x1209, 0x0003),
+        }
+    }
+
+    /// Whether the serial console is available. The Windows XInput driver only binds to a
+    /// device with the Xbox 360 layout, so it is not added next to the XInput interfaces.
+    pub fn has_console(&self) -> bool {
+        matches!(self, Self::Hid | Self::Keyboard | Self::KeyboardJoystick)
+    }
+}
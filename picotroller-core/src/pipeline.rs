@@ -0,0 +1,1061 @@
+//! One tick of the controller, from reading the inputs to sending the report, with the
+//! button holds that start calibration and switch profiles, and macro playback in between.
+
+use crate::calibration::{CalibrationStep, Calibrator, StickCalibration};
+use crate::controller::{Button, Controller};
+use crate::input::ButtonSet;
+use crate::macros::{Player, Recorder, MACRO_COUNT};
+use crate::platform::{
+    AnalogSource, DigitalInput, HostLed, ReportError, ReportSink, Status, StickAxis, Trigger,
+};
+use crate::profile::PROFILE_COUNT;
+use crate::settings::Settings;
+use crate::trigger::TriggerCalibration;
+use crate::turbo::TurboClock;
+
+/// Ticks both stick buttons need to be held to start calibration, 3s at the 10ms tick.
+const CALIBRATION_HOLD_TICKS: u32 = 300;
+/// Ticks start and select need to be held before letting go switches to the next profile.
+const PROFILE_HOLD_TICKS: u32 = 100;
+/// Ticks the status shows a new profile or turbo setting.
+const FLASH_TICKS: u32 = 50;
+/// Buttons whose turbo can't be toggled by pressing them with start and select held, as they
+/// are the hotkey itself.
+const TURBO_HOTKEY_EXCLUDED: [Button; 2] = [Button::Start, Button::Select];
+/// Ticks after the last turbo toggle before the settings are saved, so toggling a few buttons
+/// in a row writes the flash once.
+const TURBO_SAVE_TICKS: u32 = 200;
+
+/// Something that happened during a tick. All but `CalibrationStarted`,
+/// `CalibrationRejected` and `TurboToggled` leave settings that should be saved.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    CalibrationStarted,
+    Calibrated,
+    /// The sticks did not travel far enough, the previous calibration is kept.
+    CalibrationRejected,
+    ProfileSwitched(usize),
+    /// Turbo on a button was toggled by the hotkey, saved by `TurboSettled` once the toggles
+    /// stop.
+    TurboToggled(Button, bool),
+    /// No turbo toggles for `TURBO_SAVE_TICKS` after the last one.
+    TurboSettled,
+    /// Recording into the macro at this index ended
+    MacroRecorded(usize),
+}
+
+impl Event {
+    /// Whether the settings changed and should be saved.
+    pub fn changes_settings(&self) -> bool {
+        !matches!(
+            self,
+            Event::CalibrationStarted | Event::CalibrationRejected | Event::TurboToggled(..)
+        )
+    }
+}
+
+/// Most events a tick can raise: starting and ending calibration, a profile switch, a
+/// turbo toggle per button, the end of a recording and the turbo save.
+const EVENTS_MAX: usize = 5 + Button::COUNT;
+
+/// The events raised during a tick, in the order they happened.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Events {
+    events: [Option<Event>; EVENTS_MAX],
+    len: usize,
+}
+
+impl Events {
+    fn push(&mut self, event: Event) {
+        self.events[self.len] = Some(event);
+        self.len += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether any of the events changed the settings.
+    pub fn changes_settings(&self) -> bool {
+        (*self).into_iter().any(|event| event.changes_settings())
+    }
+}
+
+impl IntoIterator for Events {
+    type Item = Event;
+    type IntoIter = core::iter::Flatten<core::array::IntoIter<Option<Event>, EVENTS_MAX>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.into_iter().flatten()
+    }
+}
+
+/// The controller state and settings, updated once per tick from the hardware traits.
+pub struct Pipeline {
+    pub controller: Controller,
+    pub settings: Settings,
+    calibrator: Option<Calibrator>,
+    calibration_hold: u32,
+    profile_hold: u32,
+    /// Whether turbo was toggled while start and select were held, which then don't switch
+    /// profiles when let go
+    hotkey_used: bool,
+    turbo_save: u32,
+    turbo_clock: TurboClock,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    last_pressed: [bool; Button::COUNT],
+    flash: u32,
+    flash_status: Status,
+    report: Result<(), ReportError>,
+    usb_idle: bool,
+    host_led: Option<HostLed>,
+}
+
+impl Pipeline {
+    pub fn new(settings: Settings) -> Self {
+        let mut controller = Controller::default();
+        settings.apply(&mut controller);
+        Self {
+            controller,
+            settings,
+            calibrator: None,
+            calibration_hold: 0,
+            profile_hold: 0,
+            hotkey_used: false,
+            turbo_save: 0,
+            turbo_clock: TurboClock::default(),
+            recorder: None,
+            player: None,
+            last_pressed: [false; Button::COUNT],
+            flash: 0,
+            flash_status: Status::Ready,
+            report: Ok(()),
+            usb_idle: false,
+            host_led: None,
+        }
+    }
+
+    /// Replaces the settings, e.g. with the ones reloaded from flash.
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.settings.apply(&mut self.controller);
+        self.recorder = None;
+        self.player = None;
+    }
+
+    pub fn read_buttons<I: DigitalInput>(&mut self, inputs: &mut [I], now: u64) {
+        let mut buttons = ButtonSet::default();
+        buttons.read(inputs.iter_mut(), now);
+        self.set_buttons(&buttons, now);
+    }
+
+    /// Takes the buttons read from several groups of inputs, such as the GPIO pins and an
+    /// expander.
+    pub fn set_buttons(&mut self, buttons: &ButtonSet, now: u64) {
+        buttons.apply(&mut self.controller);
+        self.turbo_clock.update(&mut self.controller, now);
+    }
+
+    /// Reads the sticks, and the triggers if `source` has them wired.
+    pub fn read_sticks(&mut self, source: &mut impl AnalogSource) {
+        for axis in StickAxis::ALL {
+            let value = source.read(axis);
+            match axis {
+                StickAxis::LeftX => self.controller.joy_l.x = value,
+                StickAxis::LeftY => self.controller.joy_l.y = value,
+                StickAxis::RightX => self.controller.joy_r.x = value,
+                StickAxis::RightY => self.controller.joy_r.y = value,
+            }
+        }
+        for trigger in Trigger::ALL {
+            let value = source.read_trigger(trigger);
+            match trigger {
+                Trigger::Left => self.controller.trigger_l.update(value),
+                Trigger::Right => self.controller.trigger_r.update(value),
+            }
+        }
+    }
+
+    /// Runs the button holds, macros and calibration on the inputs read this tick, then
+    /// reports the controller state to `sink`. A macro being played back replaces the inputs
+    /// in the report. No reports are sent while calibrating, as the sticks are swept to their
+    /// extents.
+    pub fn update(&mut self, sink: &mut impl ReportSink) -> Events {
+        let mut events = Events::default();
+
+        if self.turbo_save > 0 {
+            self.turbo_save -= 1;
+            if self.turbo_save == 0 {
+                events.push(Event::TurboSettled);
+            }
+        }
+
+        if self.controller.joy_l.button && self.controller.joy_r.button {
+            self.calibration_hold += 1;
+        } else {
+            self.calibration_hold = 0;
+        }
+        if self.calibration_hold == CALIBRATION_HOLD_TICKS && self.calibrator.is_none() {
+            self.calibrator = Some(Calibrator::default());
+            events.push(Event::CalibrationStarted);
+        }
+
+        if self.turbo_hotkey() {
+            self.profile_hold = self.profile_hold.saturating_add(1);
+        } else {
+            if self.profile_hold >= PROFILE_HOLD_TICKS && !self.hotkey_used {
+                let next = (self.settings.active_profile() + 1) % PROFILE_COUNT;
+                self.switch_profile(next);
+                events.push(Event::ProfileSwitched(next));
+            }
+            self.profile_hold = 0;
+            self.hotkey_used = false;
+        }
+
+        let mut new_presses = [false; Button::COUNT];
+        for (index, button) in Button::ALL.into_iter().enumerate() {
+            let pressed = self.controller.is_pressed(button);
+            let was_pressed = core::mem::replace(&mut self.last_pressed[index], pressed);
+            new_presses[index] = pressed && !was_pressed;
+            if new_presses[index] && self.turbo_hotkey() && !TURBO_HOTKEY_EXCLUDED.contains(&button)
+            {
+                let enabled = !self.settings.profile().turbo.is_enabled(button);
+                self.set_turbo(button, enabled);
+                self.turbo_save = TURBO_SAVE_TICKS;
+                self.hotkey_used = true;
+                events.push(Event::TurboToggled(button, enabled));
+            }
+        }
+        self.flash = self.flash.saturating_sub(1);
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            let sequence = &mut self.settings.macros[recorder.index];
+            if !recorder.update(sequence, &self.controller) {
+                if let Some(event) = self.stop_macro() {
+                    events.push(event);
+                }
+            }
+        }
+        self.trigger_macros(&new_presses);
+        if let Some(player) = self.player.as_mut() {
+            if !player.update(&self.settings.macros[player.index], &mut self.controller) {
+                self.player = None;
+            }
+        }
+
+        if let Some(calibrator) = self.calibrator.as_mut() {
+            if let Some(sticks) = calibrator.update(&self.controller) {
+                let triggers = calibrator.triggers();
+                self.calibrator = None;
+                if sticks.iter().all(StickCalibration::is_valid) {
+                    self.settings.calibration = sticks;
+                    for (stored, trigger) in
+                        self.settings.trigger_calibration.iter_mut().zip(triggers)
+                    {
+                        if let Some(trigger) = trigger.filter(TriggerCalibration::is_valid) {
+                            *stored = trigger;
+                        }
+                    }
+                    self.settings.apply(&mut self.controller);
+                    events.push(Event::Calibrated);
+                } else {
+                    events.push(Event::CalibrationRejected);
+                }
+            }
+        } else {
+            self.report = sink.write_report(&self.controller);
+        }
+        events
+    }
+
+    /// Whether start and select are held, pressing another button then toggles its turbo.
+    fn turbo_hotkey(&self) -> bool {
+        self.controller.start && self.controller.select
+    }
+
+    /// Activates profile `index`, and shows it on the status for a moment.
+    pub fn switch_profile(&mut self, index: usize) {
+        self.settings.select_profile(index);
+        self.settings.apply(&mut self.controller);
+        self.show(Status::Profile(self.settings.active_profile()));
+    }
+
+    /// Turns turbo on `button` in the active profile on or off, and shows it on the status.
+    pub fn set_turbo(&mut self, button: Button, enabled: bool) {
+        self.settings
+            .profile_mut()
+            .turbo
+            .set_enabled(button, enabled);
+        self.settings.apply(&mut self.controller);
+        self.show(Status::Turbo(enabled));
+    }
+
+    /// Starts or stops playback on the buttons pressed this tick. The trigger of the macro
+    /// being played stops it, as does any button if it cancels on press. Presses with the
+    /// turbo hotkey held toggle turbo instead of starting a macro.
+    fn trigger_macros(&mut self, new_presses: &[bool; Button::COUNT]) {
+        let pressed = |button: Button| new_presses[button as usize];
+        if let Some(player) = self.player {
+            let sequence = &self.settings.macros[player.index];
+            if sequence.trigger().is_some_and(pressed)
+                || (sequence.cancels_on_press() && Button::ALL.into_iter().any(pressed))
+            {
+                self.player = None;
+            }
+            return;
+        }
+        if self.recorder.is_some() || self.turbo_hotkey() {
+            return;
+        }
+        if let Some(index) = self.settings.macros.iter().position(|sequence| {
+            sequence.trigger().is_some_and(pressed) && !sequence.steps().is_empty()
+        }) {
+            self.player = Some(Player::new(index));
+        }
+    }
+
+    /// Starts recording the inputs into macro `index` from the next tick they change,
+    /// replacing what it held before. Stops any playback.
+    pub fn record_macro(&mut self, index: usize) {
+        if index < MACRO_COUNT {
+            self.player = None;
+            self.settings.macros[index].clear();
+            self.recorder = Some(Recorder::new(index));
+        }
+    }
+
+    /// Plays macro `index` back from the start, returns false if it is empty or a macro is
+    /// being recorded.
+    pub fn play_macro(&mut self, index: usize) -> bool {
+        let playable = self.recorder.is_none()
+            && self
+                .settings
+                .macros
+                .get(index)
+                .is_some_and(|sequence| !sequence.steps().is_empty());
+        if playable {
+            self.player = Some(Player::new(index));
+        }
+        playable
+    }
+
+    /// Drops the recording of macro `index`, stopping it first if it is being recorded or
+    /// played.
+    pub fn clear_macro(&mut self, index: usize) {
+        if index >= MACRO_COUNT {
+            return;
+        }
+        if self
+            .recorder
+            .is_some_and(|recorder| recorder.index == index)
+        {
+            self.recorder = None;
+        }
+        if self.player.is_some_and(|player| player.index == index) {
+            self.player = None;
+        }
+        self.settings.macros[index].clear();
+    }
+
+    /// Stops recording or playing a macro. Ending a recording gives `MacroRecorded`, as the
+    /// settings then hold the new macro.
+    pub fn stop_macro(&mut self) -> Option<Event> {
+        self.player = None;
+        self.recorder
+            .take()
+            .map(|recorder| Event::MacroRecorded(recorder.index))
+    }
+
+    fn show(&mut self, status: Status) {
+        self.flash = FLASH_TICKS;
+        self.flash_status = status;
+    }
+
+    /// Sets whether the USB device is unconfigured or suspended, from its state rather than
+    /// from whether the last poll had anything to do.
+    pub fn set_usb_idle(&mut self, idle: bool) {
+        self.usb_idle = idle;
+    }
+
+    /// Shows the colour or player number the host sent in place of `Ready` and `Busy`, until
+    /// it hands the LED back with `HostLed::Off`.
+    pub fn set_host_led(&mut self, led: HostLed) {
+        self.host_led = (led != HostLed::Off).then_some(led);
+    }
+
+    /// What the controller is doing, the calibration, profile, turbo and macro states first,
+    /// as they need the player to act or confirm what they did.
+    pub fn status(&self) -> Status {
+        if self.flash > 0 {
+            return self.flash_status;
+        }
+        match self.calibrator.as_ref().map(Calibrator::step) {
+            Some(CalibrationStep::Release) | Some(CalibrationStep::Center) => {
+                Status::CalibrateCenter
+            }
+            Some(CalibrationStep::Extents) => Status::CalibrateExtents,
+            None if self.recorder.is_some() => Status::MacroRecording,
+            None if self.player.is_some() => Status::MacroPlaying,
+            None if self.usb_idle => Status::UsbIdle,
+            None => match (self.report, self.host_led) {
+                (Err(ReportError::Failed), _) => Status::Error,
+                (_, Some(led)) => Status::Host(led),
+                (Ok(()), None) => Status::Ready,
+                (Err(ReportError::WouldBlock), None) => Status::Busy,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::AXIS_MAX;
+    use crate::controller::{Button, Control};
+    use crate::macros::MACRO_STEPS;
+
+    /// Sticks held at fixed raw readings.
+    struct Sticks([u16; 4]);
+
+    impl AnalogSource for Sticks {
+        fn read(&mut self, axis: StickAxis) -> u16 {
+            self.0[axis as usize]
+        }
+    }
+
+    /// Sticks and the left trigger at fixed raw readings, the right trigger not wired.
+    struct Triggers([u16; 4], u16);
+
+    impl AnalogSource for Triggers {
+        fn read(&mut self, axis: StickAxis) -> u16 {
+            self.0[axis as usize]
+        }
+
+        fn read_trigger(&mut self, trigger: Trigger) -> Option<u16> {
+            (trigger == Trigger::Left).then_some(self.1)
+        }
+    }
+
+    /// A button held or released without bouncing.
+    struct Switch {
+        button: Button,
+        pressed: bool,
+    }
+
+    impl DigitalInput for Switch {
+        fn control(&self) -> Control {
+            Control::Button(self.button)
+        }
+
+        fn poll(&mut self, _now: u64) -> bool {
+            self.pressed
+        }
+    }
+
+    /// The raw sticks and reported buttons of a report.
+    struct Report {
+        sticks: [u16; 4],
+        buttons: Vec<Button>,
+    }
+
+    /// Records the reports, failing them with `result` if set.
+    #[derive(Default)]
+    struct Host {
+        reports: Vec<Report>,
+        result: Option<ReportError>,
+    }
+
+    impl ReportSink for Host {
+        fn write_report(&mut self, controller: &Controller) -> Result<(), ReportError> {
+            self.reports.push(Report {
+                sticks: [
+                    controller.joy_l.x,
+                    controller.joy_l.y,
+                    controller.joy_r.x,
+                    controller.joy_r.y,
+                ],
+                buttons: Button::ALL
+                    .into_iter()
+                    .filter(|&button| controller.is_reported(button))
+                    .collect(),
+            });
+            self.result.map_or(Ok(()), Err)
+        }
+    }
+
+    fn switches(buttons: &[Button]) -> Vec<Switch> {
+        Button::ALL
+            .iter()
+            .map(|&button| Switch {
+                button,
+                pressed: buttons.contains(&button),
+            })
+            .collect()
+    }
+
+    /// Reads the inputs and updates `ticks` times, returning the events in order.
+    fn run(
+        pipeline: &mut Pipeline,
+        buttons: &[Button],
+        sticks: [u16; 4],
+        host: &mut Host,
+        ticks: u32,
+    ) -> Vec<Event> {
+        run_analog(pipeline, buttons, &mut Sticks(sticks), host, ticks)
+    }
+
+    fn run_analog(
+        pipeline: &mut Pipeline,
+        buttons: &[Button],
+        source: &mut impl AnalogSource,
+        host: &mut Host,
+        ticks: u32,
+    ) -> Vec<Event> {
+        let mut inputs = switches(buttons);
+        let mut events = Vec::new();
+        for tick in 0..ticks {
+            pipeline.read_buttons(&mut inputs, tick as u64 * 10_000);
+            pipeline.read_sticks(source);
+            events.extend(pipeline.update(host));
+        }
+        events
+    }
+
+    const CENTER: [u16; 4] = [2048; 4];
+
+    #[test]
+    fn reports_inputs_every_tick() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let events = run(
+            &mut pipeline,
+            &[Button::FrontR],
+            [100, 200, 300, 400],
+            &mut host,
+            3,
+        );
+        assert!(events.is_empty());
+        assert_eq!(host.reports.len(), 3);
+        let report = &host.reports[2];
+        assert_eq!(report.buttons, [Button::FrontR]);
+        assert_eq!(report.sticks, [100, 200, 300, 400]);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn status_follows_last_report() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host {
+            result: Some(ReportError::WouldBlock),
+            ..Host::default()
+        };
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Busy);
+        host.result = Some(ReportError::Failed);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Error);
+        host.result = None;
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn host_led_stays_until_handed_back() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        pipeline.set_host_led(HostLed::Player(2));
+        run(&mut pipeline, &[], CENTER, &mut host, 100);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Player(2)));
+
+        // A busy host keeps it, a profile switch only shows over it for a moment
+        host.result = Some(ReportError::WouldBlock);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Player(2)));
+        host.result = None;
+        pipeline.switch_profile(1);
+        assert_eq!(pipeline.status(), Status::Profile(1));
+        run(&mut pipeline, &[], CENTER, &mut host, FLASH_TICKS);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Player(2)));
+
+        // Failed reports and an idle bus still show
+        host.result = Some(ReportError::Failed);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Error);
+        host.result = None;
+        pipeline.set_usb_idle(true);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::UsbIdle);
+        pipeline.set_usb_idle(false);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Player(2)));
+
+        pipeline.set_host_led(HostLed::Off);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn holding_start_and_select_cycles_profiles() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let held = [Button::Start, Button::Select];
+
+        // Too short a hold does nothing
+        run(
+            &mut pipeline,
+            &held,
+            CENTER,
+            &mut host,
+            PROFILE_HOLD_TICKS - 1,
+        );
+        assert!(run(&mut pipeline, &[], CENTER, &mut host, 1).is_empty());
+
+        // Holding them longer only switches once let go
+        let events = run(
+            &mut pipeline,
+            &held,
+            CENTER,
+            &mut host,
+            3 * PROFILE_HOLD_TICKS,
+        );
+        assert!(events.is_empty());
+        let events = run(&mut pipeline, &[Button::Start], CENTER, &mut host, 1);
+        assert_eq!(events, [Event::ProfileSwitched(1)]);
+        assert_eq!(pipeline.settings.active_profile(), 1);
+        assert_eq!(pipeline.status(), Status::Profile(1));
+
+        for _ in 0..PROFILE_COUNT - 1 {
+            run(&mut pipeline, &held, CENTER, &mut host, PROFILE_HOLD_TICKS);
+            run(&mut pipeline, &[], CENTER, &mut host, 1);
+        }
+        assert_eq!(pipeline.settings.active_profile(), 0);
+    }
+
+    /// Holds both stick buttons until calibration starts, then releases them.
+    fn start_calibration(pipeline: &mut Pipeline, host: &mut Host) {
+        let held = [Button::ThumbL, Button::ThumbR];
+        let events = run(pipeline, &held, CENTER, host, CALIBRATION_HOLD_TICKS);
+        assert_eq!(events, [Event::CalibrationStarted]);
+        assert_eq!(pipeline.status(), Status::CalibrateCenter);
+        run(pipeline, &[], CENTER, host, 51);
+        assert_eq!(pipeline.status(), Status::CalibrateExtents);
+    }
+
+    #[test]
+    fn calibration_records_sticks_without_reporting() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        start_calibration(&mut pipeline, &mut host);
+        let reports = host.reports.len();
+
+        run(&mut pipeline, &[], [0; 4], &mut host, 1);
+        run(&mut pipeline, &[], [4095; 4], &mut host, 1);
+        let events = run(&mut pipeline, &[Button::ThumbL], CENTER, &mut host, 1);
+        assert_eq!(events, [Event::Calibrated]);
+        assert_eq!(host.reports.len(), reports);
+
+        let calibration = pipeline.settings.calibration;
+        assert_eq!(pipeline.controller.joy_l.calibration, calibration[0]);
+        assert_eq!(pipeline.controller.joy_r.calibration, calibration[1]);
+        assert_eq!((calibration[0].x.min, calibration[0].x.max), (0, 4095));
+
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(host.reports.len(), reports + 1);
+    }
+
+    #[test]
+    fn calibration_records_wired_triggers() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let held = [Button::ThumbL, Button::ThumbR];
+        run_analog(
+            &mut pipeline,
+            &held,
+            &mut Triggers(CENTER, 3000),
+            &mut host,
+            CALIBRATION_HOLD_TICKS,
+        );
+        run_analog(
+            &mut pipeline,
+            &[],
+            &mut Triggers(CENTER, 3000),
+            &mut host,
+            51,
+        );
+        assert_eq!(pipeline.status(), Status::CalibrateExtents);
+
+        // Sticks swept, the hall effect trigger reads lower as it is pulled
+        run_analog(
+            &mut pipeline,
+            &[],
+            &mut Triggers([0; 4], 1200),
+            &mut host,
+            1,
+        );
+        run_analog(
+            &mut pipeline,
+            &[],
+            &mut Triggers([4095; 4], 2000),
+            &mut host,
+            1,
+        );
+        let events = run_analog(
+            &mut pipeline,
+            &[Button::ThumbL],
+            &mut Triggers(CENTER, 3000),
+            &mut host,
+            1,
+        );
+        assert_eq!(events, [Event::Calibrated]);
+
+        let calibration = pipeline.settings.trigger_calibration;
+        assert_eq!(
+            (calibration[0].released, calibration[0].pressed),
+            (3000, 1200)
+        );
+        assert_eq!(calibration[1], TriggerCalibration::default());
+        assert_eq!(pipeline.controller.trigger_l.calibration, calibration[0]);
+        run_analog(
+            &mut pipeline,
+            &[],
+            &mut Triggers(CENTER, 1200),
+            &mut host,
+            1,
+        );
+        assert_eq!(pipeline.controller.trigger_l.value(), AXIS_MAX);
+    }
+
+    #[test]
+    fn calibration_without_travel_is_rejected() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let before = pipeline.settings.calibration;
+        start_calibration(&mut pipeline, &mut host);
+
+        let events = run(&mut pipeline, &[Button::ThumbR], CENTER, &mut host, 1);
+        assert_eq!(events, [Event::CalibrationRejected]);
+        assert_eq!(pipeline.settings.calibration, before);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn calibration_status_shows_over_host_led_and_idle() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        pipeline.set_host_led(HostLed::Player(1));
+        pipeline.set_usb_idle(true);
+        start_calibration(&mut pipeline, &mut host);
+
+        run(&mut pipeline, &[], [0; 4], &mut host, 1);
+        run(&mut pipeline, &[], [4095; 4], &mut host, 1);
+        assert_eq!(pipeline.status(), Status::CalibrateExtents);
+        run(&mut pipeline, &[Button::ThumbL], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::UsbIdle);
+        pipeline.set_usb_idle(false);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Player(1)));
+    }
+
+    #[test]
+    fn start_select_and_button_toggles_turbo() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let chord = [Button::Start, Button::Select];
+        run(&mut pipeline, &chord, CENTER, &mut host, 1);
+        let held = [Button::Start, Button::Select, Button::FrontR];
+        let events = run(&mut pipeline, &held, CENTER, &mut host, 5);
+        assert_eq!(events, [Event::TurboToggled(Button::FrontR, true)]);
+        assert!(pipeline.settings.profile().turbo.is_enabled(Button::FrontR));
+        assert_eq!(pipeline.status(), Status::Turbo(true));
+
+        run(&mut pipeline, &chord, CENTER, &mut host, 1);
+        let events = run(&mut pipeline, &held, CENTER, &mut host, 1);
+        assert_eq!(events, [Event::TurboToggled(Button::FrontR, false)]);
+        assert_eq!(pipeline.status(), Status::Turbo(false));
+
+        // Holding the chord on after a toggle does not switch profiles
+        run(&mut pipeline, &chord, CENTER, &mut host, PROFILE_HOLD_TICKS);
+        assert!(run(&mut pipeline, &[], CENTER, &mut host, 1).is_empty());
+        assert_eq!(pipeline.settings.active_profile(), 0);
+    }
+
+    #[test]
+    fn long_held_chord_still_toggles_turbo() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let chord = [Button::Start, Button::Select];
+        let events = run(
+            &mut pipeline,
+            &chord,
+            CENTER,
+            &mut host,
+            2 * PROFILE_HOLD_TICKS,
+        );
+        assert!(events.is_empty());
+        let held = [Button::Start, Button::Select, Button::UnderL];
+        let events = run(&mut pipeline, &held, CENTER, &mut host, 1);
+        assert_eq!(events, [Event::TurboToggled(Button::UnderL, true)]);
+        assert!(run(&mut pipeline, &[], CENTER, &mut host, 1).is_empty());
+        assert_eq!(pipeline.settings.active_profile(), 0);
+        assert!(pipeline.settings.profile().turbo.is_enabled(Button::UnderL));
+    }
+
+    #[test]
+    fn select_alone_does_not_toggle_turbo() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        run(&mut pipeline, &[Button::Select], CENTER, &mut host, 1);
+        let held = [Button::Select, Button::FrontR];
+        assert!(run(&mut pipeline, &held, CENTER, &mut host, 5).is_empty());
+        // Nor does a button pressed before the chord
+        let held = [Button::Start, Button::Select, Button::FrontR];
+        assert!(run(&mut pipeline, &held, CENTER, &mut host, 5).is_empty());
+        assert!(!pipeline.settings.profile().turbo.is_enabled(Button::FrontR));
+    }
+
+    #[test]
+    fn turbo_toggles_settle_once() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        let chord = [Button::Start, Button::Select];
+        let mut events = Vec::new();
+        for button in [Button::FrontR, Button::FrontL, Button::FrontR] {
+            events.extend(run(&mut pipeline, &chord, CENTER, &mut host, 10));
+            let held = [Button::Start, Button::Select, button];
+            events.extend(run(&mut pipeline, &held, CENTER, &mut host, 1));
+        }
+        assert_eq!(
+            events,
+            [
+                Event::TurboToggled(Button::FrontR, true),
+                Event::TurboToggled(Button::FrontL, true),
+                Event::TurboToggled(Button::FrontR, false),
+            ]
+        );
+        let events = run(&mut pipeline, &[], CENTER, &mut host, TURBO_SAVE_TICKS - 1);
+        assert!(events.is_empty());
+        let events = run(&mut pipeline, &[], CENTER, &mut host, TURBO_SAVE_TICKS);
+        assert_eq!(events, [Event::TurboSettled]);
+    }
+
+    #[test]
+    fn turbo_button_toggles_in_reports() {
+        let mut settings = Settings::default();
+        settings
+            .profile_mut()
+            .turbo
+            .set_enabled(Button::FrontR, true);
+        let mut pipeline = Pipeline::new(settings);
+        let mut host = Host::default();
+        run(&mut pipeline, &[Button::FrontR], CENTER, &mut host, 20);
+        let held: Vec<_> = host
+            .reports
+            .iter()
+            .map(|report| !report.buttons.is_empty())
+            .collect();
+        // 10 presses a second at half duty, 5 ticks held and 5 released
+        assert_eq!(
+            held[..10],
+            [true, true, true, true, true, false, false, false, false, false]
+        );
+        assert_eq!(held[10..], held[..10]);
+    }
+
+    /// Records FrontR held for 3 ticks then released for 2 into macro 0, with the sticks
+    /// pushed left, triggered by UnderL.
+    fn record_macro(pipeline: &mut Pipeline, host: &mut Host) {
+        pipeline.record_macro(0);
+        run(pipeline, &[], CENTER, host, 5);
+        assert_eq!(pipeline.status(), Status::MacroRecording);
+        let left = [0, 2048, 2048, 2048];
+        run(pipeline, &[Button::FrontR], left, host, 3);
+        run(pipeline, &[], left, host, 2);
+        assert_eq!(pipeline.stop_macro(), Some(Event::MacroRecorded(0)));
+        assert_eq!(pipeline.stop_macro(), None);
+        pipeline.settings.macros[0].set_trigger(Some(Button::UnderL));
+    }
+
+    /// The reported buttons of the reports from `start`.
+    fn reported(host: &Host, start: usize) -> Vec<&[Button]> {
+        host.reports[start..]
+            .iter()
+            .map(|report| report.buttons.as_slice())
+            .collect()
+    }
+
+    #[test]
+    fn trigger_plays_recorded_macro() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        record_macro(&mut pipeline, &mut host);
+        let steps = pipeline.settings.macros[0].steps();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(pipeline.settings.macros[0].ticks(), 5);
+
+        let start = host.reports.len();
+        run(&mut pipeline, &[Button::UnderL], CENTER, &mut host, 7);
+        assert_eq!(
+            reported(&host, start),
+            [
+                &[Button::FrontR][..],
+                &[Button::FrontR],
+                &[Button::FrontR],
+                &[],
+                &[],
+                &[Button::UnderL],
+                &[Button::UnderL],
+            ]
+        );
+        assert_eq!(host.reports[start].sticks, [8, 2056, 2056, 2056]);
+        assert_eq!(host.reports[start + 5].sticks, CENTER);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn presses_cancel_playback() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        record_macro(&mut pipeline, &mut host);
+        pipeline.settings.macros[0].set_looping(true);
+
+        // Looping until another button is pressed, which is reported straight away
+        run(&mut pipeline, &[Button::UnderL], CENTER, &mut host, 20);
+        assert_eq!(pipeline.status(), Status::MacroPlaying);
+        let start = host.reports.len();
+        run(
+            &mut pipeline,
+            &[Button::UnderL, Button::Start],
+            CENTER,
+            &mut host,
+            1,
+        );
+        assert_eq!(
+            reported(&host, start),
+            [&[Button::UnderL, Button::Start][..]]
+        );
+
+        // Without cancel on press only the trigger stops it
+        pipeline.settings.macros[0].set_cancel_on_press(false);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        run(&mut pipeline, &[Button::UnderL], CENTER, &mut host, 1);
+        run(
+            &mut pipeline,
+            &[Button::UnderL, Button::Start],
+            CENTER,
+            &mut host,
+            1,
+        );
+        assert_eq!(pipeline.status(), Status::MacroPlaying);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(pipeline.status(), Status::MacroPlaying);
+        let start = host.reports.len();
+        run(&mut pipeline, &[Button::UnderL], CENTER, &mut host, 1);
+        assert_eq!(reported(&host, start), [&[Button::UnderL][..]]);
+        assert_eq!(pipeline.status(), Status::Ready);
+    }
+
+    #[test]
+    fn macros_play_from_console_only_when_recorded() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        assert!(!pipeline.play_macro(0));
+        record_macro(&mut pipeline, &mut host);
+        assert!(!pipeline.play_macro(MACRO_COUNT));
+        assert!(pipeline.play_macro(0));
+        let start = host.reports.len();
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        assert_eq!(reported(&host, start), [&[Button::FrontR][..]]);
+
+        // Recording stops playback, and can't be played over
+        pipeline.record_macro(1);
+        assert!(!pipeline.play_macro(0));
+        assert_eq!(pipeline.status(), Status::MacroRecording);
+    }
+
+    #[test]
+    fn macro_status_shows_over_host_led_and_idle() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        pipeline.set_host_led(HostLed::Colour(0, 0, 255));
+        pipeline.set_usb_idle(true);
+        record_macro(&mut pipeline, &mut host);
+        assert_eq!(pipeline.status(), Status::UsbIdle);
+
+        pipeline.set_usb_idle(false);
+        assert!(pipeline.play_macro(0));
+        run(&mut pipeline, &[], CENTER, &mut host, 3);
+        assert_eq!(pipeline.status(), Status::MacroPlaying);
+        run(&mut pipeline, &[], CENTER, &mut host, 5);
+        assert_eq!(pipeline.status(), Status::Host(HostLed::Colour(0, 0, 255)));
+    }
+
+    #[test]
+    fn clearing_a_playing_macro_stops_it() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        record_macro(&mut pipeline, &mut host);
+        pipeline.settings.macros[0].set_looping(true);
+        assert!(pipeline.play_macro(0));
+        run(&mut pipeline, &[], CENTER, &mut host, 4);
+
+        pipeline.clear_macro(0);
+        assert!(pipeline.settings.macros[0].steps().is_empty());
+        let start = host.reports.len();
+        run(&mut pipeline, &[], CENTER, &mut host, 5);
+        assert_eq!(reported(&host, start), [&[][..]; 5]);
+        assert_eq!(pipeline.status(), Status::Ready);
+
+        // Clearing another macro leaves a recording going
+        pipeline.record_macro(0);
+        pipeline.clear_macro(1);
+        assert_eq!(pipeline.status(), Status::MacroRecording);
+    }
+
+    #[test]
+    fn full_recording_ends_itself() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        pipeline.record_macro(1);
+        let mut events = run(&mut pipeline, &[], CENTER, &mut host, 1);
+        for _ in 0..MACRO_STEPS / 2 {
+            events.extend(run(&mut pipeline, &[Button::FrontL], CENTER, &mut host, 1));
+            events.extend(run(&mut pipeline, &[], CENTER, &mut host, 1));
+        }
+        assert!(events.is_empty());
+        let events = run(&mut pipeline, &[Button::FrontL], CENTER, &mut host, 1);
+        assert_eq!(events, [Event::MacroRecorded(1)]);
+        assert_eq!(pipeline.settings.macros[1].steps().len(), MACRO_STEPS);
+    }
+
+    #[test]
+    fn events_of_a_tick_are_all_kept() {
+        let mut pipeline = Pipeline::new(Settings::default());
+        let mut host = Host::default();
+        pipeline.record_macro(1);
+        run(&mut pipeline, &[], CENTER, &mut host, 1);
+        for step in 0..MACRO_STEPS - 1 {
+            let buttons: &[Button] = if step % 2 == 0 {
+                &[Button::FrontL]
+            } else {
+                &[]
+            };
+            run(&mut pipeline, buttons, CENTER, &mut host, 1);
+        }
+        let chord = [Button::Start, Button::Select];
+        assert!(run(&mut pipeline, &chord, CENTER, &mut host, 1).is_empty());
+
+        // The toggle fills the recording in the same tick
+        let mut inputs = switches(&[Button::Start, Button::Select, Button::FrontL]);
+        pipeline.read_buttons(&mut inputs, 0);
+        pipeline.read_sticks(&mut Sticks(CENTER));
+        let events = pipeline.update(&mut host);
+        assert_eq!(
+            events.into_iter().collect::<Vec<_>>(),
+            [
+                Event::TurboToggled(Button::FrontL, true),
+                Event::MacroRecorded(1)
+            ]
+        );
+        assert!(events.changes_settings());
+    }
+}
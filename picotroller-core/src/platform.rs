@@ -0,0 +1,124 @@
+//! The hardware around the controller logic, implemented by the firmware for the RP2040 and
+//! by mocks in the tests.
+
+use crate::controller::{Control, Controller};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StickAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl StickAxis {
+    pub const ALL: [StickAxis; 4] = [Self::LeftX, Self::LeftY, Self::RightX, Self::RightY];
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    Left,
+    Right,
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 2] = [Self::Left, Self::Right];
+}
+
+/// Raw readings of the stick axes and analog triggers, scaled to the 12 bit range of the
+/// RP2040 ADC.
+pub trait AnalogSource {
+    fn read(&mut self, axis: StickAxis) -> u16;
+
+    /// Reading of an analog trigger, `None` if the source has no trigger wired for it.
+    fn read_trigger(&mut self, _trigger: Trigger) -> Option<u16> {
+        None
+    }
+}
+
+/// An input wired to one of the controller buttons or D-pad directions, debounced by the
+/// implementation.
+pub trait DigitalInput {
+    fn control(&self) -> Control;
+    /// Whether the input is pressed at `now`, in microseconds.
+    fn poll(&mut self, now: u64) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReportError {
+    /// The host has not taken the previous report yet
+    WouldBlock,
+    /// The report could not be sent
+    Failed,
+}
+
+/// Where the controller state goes, one of the USB classes in the firmware.
+pub trait ReportSink {
+    /// Sends the controller state, implementations may skip reports that did not change.
+    fn write_report(&mut self, controller: &Controller) -> Result<(), ReportError>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    /// The last report was sent
+    Ready,
+    /// The host has not taken the last report yet
+    Busy,
+    /// Sending the last report failed
+    Error,
+    /// The host has not configured the device, or suspended it
+    UsbIdle,
+    /// Calibrating, waiting for the sticks to rest at their center
+    CalibrateCenter,
+    /// Calibrating, waiting for the sticks to be swept to their extents
+    CalibrateExtents,
+    /// Just switched to the profile with this index
+    Profile(usize),
+    /// Just turned turbo on or off for a button
+    Turbo(bool),
+    /// Recording a macro
+    MacroRecording,
+    /// Playing a macro back
+    MacroPlaying,
+    /// Reports are going out and the host set the LED, never `HostLed::Off`
+    Host(HostLed),
+}
+
+/// Shows the state of the controller to the player, the NeoPixel on the board.
+pub trait StatusIndicator {
+    fn show(&mut self, status: Status);
+}
+
+/// Strength of the rumble motors, 0 for off.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rumble {
+    /// The heavy, low frequency motor
+    pub left: u8,
+    /// The light, high frequency motor
+    pub right: u8,
+}
+
+/// What the host shows on the LED instead of the controller status.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HostLed {
+    /// Hands the LED back to the controller status
+    Off,
+    Colour(u8, u8, u8),
+    /// Player number, from 1
+    Player(u8),
+}
+
+/// Rumble and LED sent by the host in an output report, `None` for what the report leaves
+/// as it is. The firmware acts on it in its main loop.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HostCommand {
+    pub rumble: Option<Rumble>,
+    pub led: Option<HostLed>,
+}
@@ -0,0 +1,136 @@
+use crate::controller::{Controller, Deadzone, DpadMode, SocdMode};
+use crate::curve::Curve;
+use crate::keyboard::KeyMap;
+use crate::remap::RemapTable;
+use crate::trigger::TriggerSettings;
+use crate::turbo::Turbo;
+use packed_struct::prelude::*;
+
+pub const PROFILE_COUNT: usize = 4;
+pub const PROFILE_NAME_SIZE: usize = 12;
+pub const PROFILE_SIZE: usize = 118;
+
+/// Settings that change how `Controller` state is reported, switched as a whole per game.
+///
+/// New fields go at the end, stored profiles are extended with their defaults when loaded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "118")]
+pub struct Profile {
+    /// ASCII, padded with zeros, packed_struct needs a literal `PROFILE_NAME_SIZE`
+    #[packed_field]
+    name: [u8; 12],
+    #[packed_field(element_size_bytes = "16")]
+    pub remap: RemapTable,
+    #[packed_field(element_size_bytes = "13")]
+    pub deadzone: [Deadzone; 2],
+    #[packed_field(element_size_bytes = "20")]
+    pub curve: [Curve; 2],
+    #[packed_field(element_size_bytes = "13")]
+    pub keymap: KeyMap,
+    #[packed_field(element_size_bytes = "3")]
+    pub turbo: Turbo,
+    #[packed_field(element_size_bytes = "3")]
+    pub trigger: [TriggerSettings; 2],
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub socd: SocdMode,
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub dpad: DpadMode,
+}
+
+const _: () = assert!(PROFILE_NAME_SIZE == 12);
+
+impl Profile {
+    /// Default mapping and deadzones, named after its position in the list.
+    pub fn numbered(index: usize) -> Self {
+        let mut name = [0; PROFILE_NAME_SIZE];
+        name[..7].copy_from_slice(b"profile");
+        name[7] = b'1' + index as u8;
+        Self {
+            name,
+            remap: RemapTable::default(),
+            deadzone: [Deadzone::default(); 2],
+            curve: [Curve::default(); 2],
+            keymap: KeyMap::default(),
+            turbo: Turbo::default(),
+            trigger: [TriggerSettings::default(); 2],
+            socd: SocdMode::default(),
+            dpad: DpadMode::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(PROFILE_NAME_SIZE);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// Renames the profile, names must be printable ASCII of up to `PROFILE_NAME_SIZE` bytes.
+    pub fn set_name(&mut self, name: &str) -> bool {
+        let valid = !name.is_empty()
+            && name.len() <= PROFILE_NAME_SIZE
+            && name.bytes().all(|byte| byte.is_ascii_graphic());
+        if valid {
+            self.name = [0; PROFILE_NAME_SIZE];
+            self.name[..name.len()].copy_from_slice(name.as_bytes());
+        }
+        valid
+    }
+
+    pub fn apply(&self, controller: &mut Controller) {
+        controller.joy_l.deadzone = self.deadzone[0];
+        controller.joy_r.deadzone = self.deadzone[1];
+        controller.joy_l.curve = self.curve[0];
+        controller.joy_r.curve = self.curve[1];
+        controller.remap = self.remap;
+        controller.keymap = self.keymap;
+        controller.turbo = self.turbo;
+        controller.trigger_l.settings = self.trigger[0];
+        controller.trigger_r.settings = self.trigger[1];
+        controller.socd.mode = self.socd;
+        controller.dpad_mode = self.dpad;
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.deadzone.iter().all(Deadzone::is_valid)
+            && self.curve.iter().all(Curve::is_valid)
+            && self.turbo.is_valid()
+            && self.trigger.iter().all(TriggerSettings::is_valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_names() {
+        assert_eq!(Profile::numbered(0).name(), "profile1");
+        assert_eq!(Profile::numbered(3).name(), "profile4");
+    }
+
+    #[test]
+    fn rename() {
+        let mut profile = Profile::numbered(0);
+        assert!(profile.set_name("racing"));
+        assert_eq!(profile.name(), "racing");
+        assert!(profile.set_name("twelve_chars"));
+        assert_eq!(profile.name(), "twelve_chars");
+        assert!(!profile.set_name("thirteen_char"));
+        assert!(!profile.set_name("two words"));
+        assert!(!profile.set_name(""));
+        assert_eq!(profile.name(), "twelve_chars");
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut profile = Profile::numbered(1);
+        profile.set_name("fps");
+        let unpacked = Profile::unpack(&profile.pack().unwrap()).unwrap();
+        assert_eq!(unpacked, profile);
+        assert_eq!(unpacked.name(), "fps");
+    }
+}
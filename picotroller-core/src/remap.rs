@@ -0,0 +1,52 @@
+use crate::controller::{buttons, Button};
+use packed_struct::prelude::*;
+
+/// HID buttons reported for each physical button, as a mask of the bits in
+/// `controller::buttons`. A button can drive several HID buttons, or none to disable it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "16")]
+pub struct RemapTable {
+    // packed_struct needs a literal length, one entry per `Button`
+    #[packed_field]
+    buttons: [u16; 8],
+}
+
+impl Default for RemapTable {
+    fn default() -> Self {
+        let mut table = Self {
+            buttons: [0; Button::COUNT],
+        };
+        table.set(Button::ThumbL, buttons::BTN_THUMBL);
+        table.set(Button::ThumbR, buttons::BTN_THUMBR);
+        table.set(Button::UnderL, buttons::BTN_WEST);
+        table.set(Button::UnderR, buttons::BTN_NORTH);
+        table.set(Button::FrontL, buttons::BTN_EAST);
+        table.set(Button::FrontR, buttons::BTN_SOUTH);
+        table.set(Button::Start, buttons::BTN_START);
+        table.set(Button::Select, buttons::BTN_SELECT);
+        table
+    }
+}
+
+const _: () = assert!(Button::COUNT == 8);
+
+impl RemapTable {
+    #[inline]
+    pub fn get(&self, button: Button) -> u16 {
+        self.buttons[button as usize]
+    }
+
+    pub fn set(&mut self, button: Button, mask: u16) {
+        self.buttons[button as usize] = mask;
+    }
+
+    /// HID buttons for the physical buttons `is_pressed` reports as held.
+    #[inline]
+    pub fn apply(&self, is_pressed: impl Fn(Button) -> bool) -> u16 {
+        Button::ALL
+            .iter()
+            .filter(|button| is_pressed(**button))
+            .fold(0, |mask, button| mask | self.get(*button))
+    }
+}
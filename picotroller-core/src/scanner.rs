@@ -0,0 +1,464 @@
+//! Buttons beyond the GPIO pins, read from a diode key matrix, 74HC165 shift registers or an
+//! MCP23017 expander. The switches are scanned from the main loop instead of edge interrupts,
+//! and each mapped switch is debounced like a GPIO before merging into the logical buttons
+//! and D-pad.
+
+use crate::controller::{Button, Control};
+use crate::debounce::Debouncer;
+use crate::input::{ButtonInput, Level};
+use crate::platform::DigitalInput;
+use core::convert::Infallible;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// Switches read together over a bus or a matrix.
+pub trait SwitchScanner {
+    type Error;
+
+    /// Reads the level of every switch, high if bit `n` is set for switch `n`.
+    fn scan(&mut self) -> Result<u32, Self::Error>;
+}
+
+/// A switch of a scanner wired to a button or D-pad direction, one entry of its key map.
+#[derive(Clone, Copy, Debug)]
+pub struct ScannedButton {
+    pub switch: u8,
+    pub input: ButtonInput,
+}
+
+impl ScannedButton {
+    pub const fn new(switch: u8, active: Level, control: Control, debouncer: Debouncer) -> Self {
+        Self {
+            switch,
+            input: ButtonInput::new(active, control, debouncer),
+        }
+    }
+}
+
+impl DigitalInput for ScannedButton {
+    fn control(&self) -> Control {
+        self.input.control
+    }
+
+    fn poll(&mut self, now: u64) -> bool {
+        self.input.poll(now)
+    }
+}
+
+/// The buttons of a key map, fed with edges from the scans of `scanner`.
+pub struct ScannedButtons<S, const N: usize> {
+    scanner: S,
+    pub buttons: [ScannedButton; N],
+    /// Levels of the last scan, `None` until one succeeded
+    levels: Option<u32>,
+}
+
+impl<S: SwitchScanner, const N: usize> ScannedButtons<S, N> {
+    pub fn new(scanner: S, buttons: [ScannedButton; N]) -> Self {
+        Self {
+            scanner,
+            buttons,
+            levels: None,
+        }
+    }
+
+    /// Scans the switches, as often as the main loop runs, and records the edges at `now`.
+    pub fn scan(&mut self, now: u64) -> Result<(), S::Error> {
+        let levels = self.scanner.scan()?;
+        let changed = self.levels.map_or(u32::MAX, |last| last ^ levels);
+        for button in self.buttons.iter_mut() {
+            let bit = 1 << button.switch;
+            if changed & bit != 0 {
+                button.input.edge(levels & bit != 0, now);
+            }
+        }
+        self.levels = Some(levels);
+        Ok(())
+    }
+
+    /// Whether a switch mapped to `button` read pressed on the last scan, for checking buttons
+    /// held at boot.
+    pub fn is_held(&self, button: Button) -> bool {
+        let Some(levels) = self.levels else {
+            return false;
+        };
+        self.buttons.iter().any(|key| {
+            key.input.control == Control::Button(button)
+                && key.input.is_active(levels & 1 << key.switch != 0)
+        })
+    }
+}
+
+/// A diode matrix of switches, each closing between a row and a column. The columns are inputs
+/// pulled up, and the rows outputs driven low one at a time. With the diodes pointing from the
+/// columns to the rows, a pressed switch reads low on its column and only while its row is
+/// driven. Switch `row * COLS + col` reads the crossing of `row` and `col`.
+pub struct KeyMatrix<R, C, const ROWS: usize, const COLS: usize> {
+    rows: [R; ROWS],
+    cols: [C; COLS],
+    /// Row driven since the last scan, given that long to settle before reading
+    row: usize,
+    levels: u32,
+}
+
+impl<R, C, E, const ROWS: usize, const COLS: usize> KeyMatrix<R, C, ROWS, COLS>
+where
+    R: OutputPin<Error = E>,
+    C: InputPin<Error = E>,
+{
+    pub fn new(mut rows: [R; ROWS], cols: [C; COLS]) -> Result<Self, E> {
+        assert!(ROWS > 0 && ROWS * COLS <= 32, "matrix of up to 32 switches");
+        for (index, row) in rows.iter_mut().enumerate() {
+            if index == 0 {
+                row.set_low()?;
+            } else {
+                row.set_high()?;
+            }
+        }
+        Ok(Self {
+            rows,
+            cols,
+            row: 0,
+            levels: u32::MAX,
+        })
+    }
+}
+
+impl<R, C, E, const ROWS: usize, const COLS: usize> SwitchScanner for KeyMatrix<R, C, ROWS, COLS>
+where
+    R: OutputPin<Error = E>,
+    C: InputPin<Error = E>,
+{
+    type Error = E;
+
+    /// Reads one row and drives the next, so the whole matrix updates every `ROWS` scans.
+    fn scan(&mut self) -> Result<u32, E> {
+        for (col, pin) in self.cols.iter().enumerate() {
+            let bit = 1 << (self.row * COLS + col);
+            if pin.is_high()? {
+                self.levels |= bit;
+            } else {
+                self.levels &= !bit;
+            }
+        }
+        self.rows[self.row].set_high()?;
+        self.row = (self.row + 1) % ROWS;
+        self.rows[self.row].set_low()?;
+        Ok(self.levels)
+    }
+}
+
+/// A chain of 74HC165 parallel in, serial out shift registers, read over the MISO line of an
+/// SPI bus in mode 0. `load` drives the SH/LD pins of every chip. Inputs A to H of the chip
+/// nearest to MISO are switches 0 to 7, those of the next chip 8 to 15 and so on.
+pub struct ShiftRegisters<S, P, const CHIPS: usize> {
+    spi: S,
+    load: P,
+}
+
+impl<S, P, const CHIPS: usize> ShiftRegisters<S, P, CHIPS>
+where
+    S: Transfer<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    pub fn new(spi: S, mut load: P) -> Self {
+        assert!(CHIPS <= 4, "chain of up to 4 chips");
+        load.set_high().unwrap();
+        Self { spi, load }
+    }
+}
+
+impl<S, P, const CHIPS: usize> SwitchScanner for ShiftRegisters<S, P, CHIPS>
+where
+    S: Transfer<u8>,
+    P: OutputPin<Error = Infallible>,
+{
+    type Error = S::Error;
+
+    fn scan(&mut self) -> Result<u32, S::Error> {
+        // Latch the inputs, then shift them out input H first
+        self.load.set_low().unwrap();
+        self.load.set_high().unwrap();
+        let mut data = [0; CHIPS];
+        let data = self.spi.transfer(&mut data)?;
+        Ok(data.iter().enumerate().fold(0, |levels, (chip, &byte)| {
+            levels | (byte as u32) << (chip * 8)
+        }))
+    }
+}
+
+/// I2C address with A0 to A2 tied to ground.
+pub const MCP23017_ADDRESS: u8 = 0x20;
+
+mod mcp23017 {
+    // Register addresses in the default IOCON.BANK = 0 layout
+    pub const IODIRA: u8 = 0x00;
+    pub const GPPUA: u8 = 0x0c;
+    pub const GPIOA: u8 = 0x12;
+}
+
+/// Microchip MCP23017, 16 GPIOs over I2C with their pull ups enabled, for switches to ground.
+/// Port A is switches 0 to 7 and port B 8 to 15.
+pub struct Mcp23017<I> {
+    i2c: I,
+    address: u8,
+    configured: bool,
+}
+
+impl<I, E> Mcp23017<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            configured: false,
+        }
+    }
+}
+
+impl<I, E> SwitchScanner for Mcp23017<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn scan(&mut self) -> Result<u32, E> {
+        // Configured on the first scan, and again after an error in case it was power cycled
+        if !self.configured {
+            self.i2c
+                .write(self.address, &[mcp23017::IODIRA, 0xff, 0xff])?;
+            self.i2c
+                .write(self.address, &[mcp23017::GPPUA, 0xff, 0xff])?;
+            self.configured = true;
+        }
+        let mut ports = [0; 2];
+        let result = self
+            .i2c
+            .write_read(self.address, &[mcp23017::GPIOA], &mut ports);
+        self.configured = result.is_ok();
+        result?;
+        Ok(u16::from_le_bytes(ports) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::DebounceStrategy;
+    use core::cell::Cell;
+    use std::rc::Rc;
+
+    const WINDOW: u64 = 5_000;
+
+    fn debouncer() -> Debouncer {
+        Debouncer::new(DebounceStrategy::Eager, WINDOW)
+    }
+
+    /// Returns the levels it is given.
+    struct Levels(Rc<Cell<u32>>);
+
+    impl SwitchScanner for Levels {
+        type Error = ();
+
+        fn scan(&mut self) -> Result<u32, ()> {
+            Ok(self.0.get())
+        }
+    }
+
+    #[test]
+    fn scans_feed_mapped_buttons() {
+        let levels = Rc::new(Cell::new(u32::MAX));
+        let mut keys = ScannedButtons::new(
+            Levels(levels.clone()),
+            [
+                ScannedButton::new(3, Level::Low, Button::Start.into(), debouncer()),
+                ScannedButton::new(17, Level::Low, Button::FrontR.into(), debouncer()),
+            ],
+        );
+        assert!(!keys.is_held(Button::Start));
+        levels.set(!(1 << 17));
+        keys.scan(0).unwrap();
+        assert!(keys.is_held(Button::FrontR));
+        assert!(!keys.is_held(Button::Start));
+        let pressed =
+            |keys: &mut ScannedButtons<Levels, 2>, now| keys.buttons.map(|mut key| key.poll(now));
+        assert_eq!(pressed(&mut keys, 0), [false, true]);
+
+        levels.set(!(1 << 3));
+        keys.scan(10_000).unwrap();
+        assert_eq!(pressed(&mut keys, 10_000), [true, false]);
+        assert!(keys.is_held(Button::Start));
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    enum Pin {
+        Row(usize),
+        Col(usize),
+    }
+
+    /// Rows driven low, and the switches held, shared by the pins of a fake matrix.
+    #[derive(Default)]
+    struct Matrix {
+        driven: Cell<u8>,
+        held: Cell<u32>,
+    }
+
+    struct MatrixPin<'a>(&'a Matrix, Pin);
+
+    impl OutputPin for MatrixPin<'_> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            let Pin::Row(row) = self.1 else { panic!() };
+            self.0.driven.set(self.0.driven.get() | 1 << row);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            let Pin::Row(row) = self.1 else { panic!() };
+            self.0.driven.set(self.0.driven.get() & !(1 << row));
+            Ok(())
+        }
+    }
+
+    impl InputPin for MatrixPin<'_> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            let Pin::Col(col) = self.1 else { panic!() };
+            let driven = self.0.driven.get();
+            assert_eq!(driven.count_ones(), 1, "one row driven at a time");
+            let row = driven.trailing_zeros() as usize;
+            Ok(self.0.held.get() & 1 << (row * 3 + col) == 0)
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    #[test]
+    fn matrix_reads_a_row_per_scan() {
+        let matrix = Matrix::default();
+        let rows = [0, 1].map(|row| MatrixPin(&matrix, Pin::Row(row)));
+        let cols = [0, 1, 2].map(|col| MatrixPin(&matrix, Pin::Col(col)));
+        let mut scanner = KeyMatrix::new(rows, cols).unwrap();
+        let all = 0b11_1111;
+        // Row 1, column 0 and row 0, column 2
+        matrix.held.set(0b00_1100);
+        assert_eq!(scanner.scan().unwrap() & all, 0b11_1011);
+        assert_eq!(scanner.scan().unwrap() & all, 0b11_0011);
+        matrix.held.set(0);
+        assert_eq!(scanner.scan().unwrap() & all, 0b11_0111);
+        assert_eq!(scanner.scan().unwrap() & all, all);
+    }
+
+    /// A chain of shift registers, latched by the load pin.
+    struct Chain<'a> {
+        inputs: [u8; 2],
+        loaded: &'a Cell<Option<[u8; 2]>>,
+    }
+
+    impl Transfer<u8> for Chain<'_> {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            let latched = self.loaded.take().expect("latched before shifting");
+            words.copy_from_slice(&latched);
+            Ok(words)
+        }
+    }
+
+    struct Load<'a> {
+        inputs: [u8; 2],
+        loaded: &'a Cell<Option<[u8; 2]>>,
+    }
+
+    impl OutputPin for Load<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.loaded.set(Some(self.inputs));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shift_registers_chain_chips() {
+        let loaded = Cell::new(None);
+        let inputs = [0b1000_0001, 0b0100_0000];
+        let chain = Chain {
+            inputs,
+            loaded: &loaded,
+        };
+        let load = Load {
+            inputs: chain.inputs,
+            loaded: &loaded,
+        };
+        let mut scanner = ShiftRegisters::<_, _, 2>::new(chain, load);
+        assert_eq!(scanner.scan().unwrap(), 1 << 14 | 1 << 7 | 1);
+    }
+
+    /// An MCP23017 with port A and B reading `ports`, failing while `offline`.
+    struct FakeMcp23017 {
+        ports: [u8; 2],
+        registers: [u8; 0x16],
+        offline: bool,
+    }
+
+    impl Write for FakeMcp23017 {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            assert_eq!(address, MCP23017_ADDRESS);
+            if self.offline {
+                return Err(());
+            }
+            let start = bytes[0] as usize;
+            self.registers[start..start + bytes.len() - 1].copy_from_slice(&bytes[1..]);
+            Ok(())
+        }
+    }
+
+    impl WriteRead for FakeMcp23017 {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            if self.offline {
+                return Err(());
+            }
+            assert_eq!(bytes, [mcp23017::GPIOA]);
+            buffer.copy_from_slice(&self.ports);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn mcp23017_configures_pull_ups_and_reads_both_ports() {
+        let i2c = FakeMcp23017 {
+            ports: [0xfe, 0x7f],
+            registers: [0; 0x16],
+            offline: false,
+        };
+        let mut scanner = Mcp23017::new(i2c, MCP23017_ADDRESS);
+        assert_eq!(scanner.scan().unwrap(), 0x7ffe);
+        let registers = scanner.i2c.registers;
+        assert_eq!(registers[mcp23017::IODIRA as usize..][..2], [0xff, 0xff]);
+        assert_eq!(registers[mcp23017::GPPUA as usize..][..2], [0xff, 0xff]);
+
+        // Unplugged and reset, so the pull ups have to be enabled again
+        scanner.i2c.offline = true;
+        assert!(scanner.scan().is_err());
+        scanner.i2c.offline = false;
+        scanner.i2c.registers = [0; 0x16];
+        scanner.scan().unwrap();
+        assert_eq!(scanner.i2c.registers[mcp23017::GPPUA as usize], 0xff);
+    }
+}
@@ -0,0 +1,439 @@
+use crate::calibration::StickCalibration;
+use crate::controller::{Controller, Deadzone};
+use crate::device::ReportResolution;
+use crate::macros::{
+    Macro, MACRO_COUNT, MACRO_HEADER_SIZE, MACRO_SIZE, MACRO_STEPS, MACRO_STEP_SIZE,
+};
+use crate::mode::UsbMode;
+use crate::profile::{Profile, PROFILE_COUNT, PROFILE_SIZE};
+use crate::remap::RemapTable;
+use crate::store::{self, Flash};
+use crate::trigger::TriggerCalibration;
+use packed_struct::prelude::*;
+
+/// Bumped whenever the packed layout of `Settings` changes.
+const SETTINGS_VERSION: u16 = 9;
+const SETTINGS_SIZE: usize = 945;
+/// Packed size of the fields after the profiles, the macros added in version 6 followed
+/// by the trigger calibration added in version 7.
+const MACROS_SIZE: usize = MACRO_COUNT * MACRO_SIZE;
+const TRIGGER_CALIBRATION_SIZE: usize = 2 * 4;
+const TAIL_SIZE: usize = MACROS_SIZE + TRIGGER_CALIBRATION_SIZE;
+/// Packed size of the fields before the profiles.
+const GLOBAL_SIZE: usize = SETTINGS_SIZE - PROFILE_COUNT * PROFILE_SIZE - TAIL_SIZE;
+
+/// Macros before version 9, with more steps that had no D-pad or triggers.
+const MACRO_STEPS_V8: usize = 32;
+const MACRO_STEP_SIZE_V8: usize = 6;
+const MACRO_SIZE_V8: usize = MACRO_HEADER_SIZE + MACRO_STEPS_V8 * MACRO_STEP_SIZE_V8;
+const MACROS_SIZE_V8: usize = MACRO_COUNT * MACRO_SIZE_V8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "945")]
+pub struct Settings {
+    #[packed_field(element_size_bytes = "12")]
+    pub calibration: [StickCalibration; 2],
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub resolution: ReportResolution,
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub mode: UsbMode,
+    #[packed_field]
+    active: u8,
+    // packed_struct needs a literal `PROFILE_COUNT`
+    #[packed_field(element_size_bytes = "118")]
+    pub profiles: [Profile; 4],
+    // Likewise `MACRO_COUNT`
+    #[packed_field(element_size_bytes = "219")]
+    pub macros: [Macro; 2],
+    #[packed_field(element_size_bytes = "4")]
+    pub trigger_calibration: [TriggerCalibration; 2],
+}
+
+const _: () = assert!(PROFILE_COUNT == 4 && MACRO_COUNT == 2);
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            calibration: [StickCalibration::default(); 2],
+            resolution: ReportResolution::Low,
+            mode: UsbMode::XInput,
+            active: 0,
+            profiles: core::array::from_fn(Profile::numbered),
+            macros: [Macro::default(); MACRO_COUNT],
+            trigger_calibration: [TriggerCalibration::default(); 2],
+        }
+    }
+}
+
+/// Layout before profiles, its mapping and deadzones become the first profile.
+#[derive(PackedStruct)]
+#[packed_struct(endian = "lsb", size_bytes = "68")]
+struct SettingsV1 {
+    #[packed_field(element_size_bytes = "12")]
+    calibration: [StickCalibration; 2],
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    resolution: ReportResolution,
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    mode: UsbMode,
+    #[packed_field(element_size_bytes = "16")]
+    remap: RemapTable,
+    #[packed_field(element_size_bytes = "13")]
+    deadzone: [Deadzone; 2],
+}
+
+impl From<SettingsV1> for Settings {
+    fn from(v1: SettingsV1) -> Self {
+        let mut settings = Self {
+            calibration: v1.calibration,
+            resolution: v1.resolution,
+            mode: v1.mode,
+            ..Self::default()
+        };
+        settings.profiles[0].remap = v1.remap;
+        settings.profiles[0].deadzone = v1.deadzone;
+        settings
+    }
+}
+
+/// Copies a record from before version 9 into `buffer`, with the steps of its macros
+/// widened to the current layout. Recordings longer than `MACRO_STEPS` are cut short. The
+/// macros end `after` bytes before the end of `payload`.
+fn widen_macros<'a>(
+    payload: &[u8],
+    after: usize,
+    buffer: &'a mut [u8; SETTINGS_SIZE],
+) -> Option<&'a [u8]> {
+    let start = payload.len().checked_sub(MACROS_SIZE_V8 + after)?;
+    let len = start + MACROS_SIZE + after;
+    if len > SETTINGS_SIZE {
+        return None;
+    }
+    buffer[..start].copy_from_slice(&payload[..start]);
+    let stored = payload[start..start + MACROS_SIZE_V8].chunks_exact(MACRO_SIZE_V8);
+    let widened = buffer[start..start + MACROS_SIZE].chunks_exact_mut(MACRO_SIZE);
+    for (stored, widened) in stored.zip(widened) {
+        let (header, steps) = widened.split_at_mut(MACRO_HEADER_SIZE);
+        header.copy_from_slice(&stored[..MACRO_HEADER_SIZE]);
+        // The length follows the trigger and flags
+        header[2] = header[2].min(MACRO_STEPS as u8);
+        let stored_steps = stored[MACRO_HEADER_SIZE..].chunks_exact(MACRO_STEP_SIZE_V8);
+        for (stored, step) in stored_steps.zip(steps.chunks_exact_mut(MACRO_STEP_SIZE)) {
+            step[..MACRO_STEP_SIZE_V8].copy_from_slice(stored);
+            step[MACRO_STEP_SIZE_V8..].fill(0);
+        }
+    }
+    buffer[start + MACROS_SIZE..len].copy_from_slice(&payload[start + MACROS_SIZE_V8..]);
+    Some(&buffer[..len])
+}
+
+impl Settings {
+    /// Reads the newest settings record, falling back to defaults if none was saved or it
+    /// is not valid.
+    pub fn load(flash: &impl Flash) -> Self {
+        let mut payload = [0u8; store::PAYLOAD_MAX];
+        let record = match store::load(flash, &mut payload) {
+            Some(record) => record,
+            None => {
+                warn!("No settings stored, using defaults");
+                return Self::default();
+            }
+        };
+        match Self::from_record(record.version, &payload[..record.len]) {
+            Some(settings) if settings.is_valid() => {
+                info!("Loaded settings");
+                settings
+            }
+            _ => {
+                warn!(
+                    "Stored settings version {} not valid, using defaults",
+                    record.version
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, flash: &mut impl Flash) {
+        match self.pack() {
+            Ok(data) => store::save(flash, SETTINGS_VERSION, &data),
+            Err(_) => {
+                warn!("Error packing Settings");
+                return;
+            }
+        }
+        info!("Saved settings");
+    }
+
+    /// Unpacks a stored record, older versions are migrated here once the layout changes.
+    fn from_record(version: u16, payload: &[u8]) -> Option<Self> {
+        let mut widened = [0u8; SETTINGS_SIZE];
+        match version {
+            1 => SettingsV1::unpack_from_slice(payload).ok().map(Self::from),
+            // Only fields appended to `Profile` and after the profiles since, macros were
+            // added in version 6 and trigger calibration in 7, the D-pad to profiles in 8,
+            // and the D-pad and triggers to macro steps in 9
+            2..=5 => Self::unpack_extended(payload, 0),
+            6 => Self::unpack_extended(widen_macros(payload, 0, &mut widened)?, MACROS_SIZE),
+            7..=8 => Self::unpack_extended(
+                widen_macros(payload, TRIGGER_CALIBRATION_SIZE, &mut widened)?,
+                TAIL_SIZE,
+            ),
+            SETTINGS_VERSION => Self::unpack_extended(payload, TAIL_SIZE),
+            _ => None,
+        }
+    }
+
+    /// Unpacks settings whose profiles may be shorter than `PROFILE_SIZE`, taking the
+    /// missing fields from the default profile. The last `tail` bytes are the fields after
+    /// the profiles, any not stored are left at their defaults.
+    fn unpack_extended(payload: &[u8], tail: usize) -> Option<Self> {
+        let profiles_len = payload.len().checked_sub(GLOBAL_SIZE + tail)?;
+        let stored_size = profiles_len / PROFILE_COUNT;
+        if stored_size == 0
+            || stored_size > PROFILE_SIZE
+            || stored_size * PROFILE_COUNT != profiles_len
+        {
+            return None;
+        }
+        let mut data = Self::default().pack().ok()?;
+        data[..GLOBAL_SIZE].copy_from_slice(&payload[..GLOBAL_SIZE]);
+        let tail_start = GLOBAL_SIZE + PROFILE_COUNT * PROFILE_SIZE;
+        data[tail_start..tail_start + tail].copy_from_slice(&payload[payload.len() - tail..]);
+        let profiles = payload[GLOBAL_SIZE..GLOBAL_SIZE + profiles_len].chunks_exact(stored_size);
+        for (index, stored) in profiles.enumerate() {
+            let start = GLOBAL_SIZE + index * PROFILE_SIZE;
+            data[start..start + stored_size].copy_from_slice(stored);
+        }
+        Self::unpack(&data).ok()
+    }
+
+    /// Applies the settings that take effect immediately, the USB mode and report resolution
+    /// are only picked up at boot.
+    pub fn apply(&self, controller: &mut Controller) {
+        controller.joy_l.calibration = self.calibration[0];
+        controller.joy_r.calibration = self.calibration[1];
+        controller.trigger_l.calibration = self.trigger_calibration[0];
+        controller.trigger_r.calibration = self.trigger_calibration[1];
+        self.profile().apply(controller);
+    }
+
+    pub fn active_profile(&self) -> usize {
+        self.active as usize
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active_profile()]
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.active_profile()]
+    }
+
+    /// Makes profile `index` the active one, ignoring indexes past the last profile.
+    pub fn select_profile(&mut self, index: usize) {
+        if index < PROFILE_COUNT {
+            self.active = index as u8;
+        }
+    }
+
+    /// Index of the profile called `name`, or numbered `name` from 1.
+    pub fn find_profile(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(number @ 1..=PROFILE_COUNT) => Some(number - 1),
+            _ => self.profiles.iter().position(|p| p.name() == name),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.calibration.iter().all(StickCalibration::is_valid)
+            && self.active_profile() < PROFILE_COUNT
+            && self.profiles.iter().all(Profile::is_valid)
+            && self.macros.iter().all(Macro::is_valid)
+            && self
+                .trigger_calibration
+                .iter()
+                .all(TriggerCalibration::is_valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::MacroStep;
+
+    #[test]
+    fn defaults_round_trip() {
+        let settings = Settings::default();
+        let data = settings.pack().unwrap();
+        assert_eq!(data.len(), SETTINGS_SIZE);
+        let loaded = Settings::from_record(SETTINGS_VERSION, &data).unwrap();
+        assert_eq!(loaded, settings);
+        assert!(loaded.is_valid());
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let data = Settings::default().pack().unwrap();
+        assert_eq!(Settings::from_record(SETTINGS_VERSION + 1, &data), None);
+        assert_eq!(Settings::from_record(SETTINGS_VERSION, &data[1..]), None);
+    }
+
+    #[test]
+    fn migrates_v1_into_first_profile() {
+        let mut remap = RemapTable::default();
+        remap.set(crate::controller::Button::Start, 0);
+        let mut v1 = SettingsV1 {
+            calibration: [StickCalibration::default(); 2],
+            resolution: ReportResolution::High,
+            mode: UsbMode::Hid,
+            remap,
+            deadzone: [Deadzone::default(); 2],
+        };
+        v1.deadzone[1].inner = 100;
+        let settings = Settings::from_record(1, &v1.pack().unwrap()).unwrap();
+        assert_eq!(settings.resolution, ReportResolution::High);
+        assert_eq!(settings.mode, UsbMode::Hid);
+        assert_eq!(settings.active_profile(), 0);
+        assert_eq!(settings.profile().remap, remap);
+        assert_eq!(settings.profile().deadzone[1].inner, 100);
+        assert_eq!(settings.profiles[1], Profile::numbered(1));
+    }
+
+    #[test]
+    fn extends_shorter_profiles() {
+        let mut settings = Settings::default();
+        settings.select_profile(2);
+        settings.profiles[1].set_name("racing");
+        settings.profiles[1].deadzone[0].inner = 100;
+        let data = settings.pack().unwrap();
+        // Version 2 profiles end before the curves
+        let v2_size = 54;
+        let mut v2 = data[..GLOBAL_SIZE].to_vec();
+        let profiles = &data[GLOBAL_SIZE..GLOBAL_SIZE + PROFILE_COUNT * PROFILE_SIZE];
+        for profile in profiles.chunks(PROFILE_SIZE) {
+            v2.extend_from_slice(&profile[..v2_size]);
+        }
+        let loaded = Settings::from_record(2, &v2).unwrap();
+        assert_eq!(loaded, settings);
+        assert_eq!(Settings::from_record(2, &v2[1..]), None);
+    }
+
+    #[test]
+    fn macros_follow_profiles() {
+        let mut settings = Settings::default();
+        settings.profiles[3].set_name("last");
+        settings.macros[1].set_trigger(Some(crate::controller::Button::Start));
+        settings.macros[1].set_looping(true);
+        let data = settings.pack().unwrap();
+        assert_eq!(
+            Settings::from_record(SETTINGS_VERSION, &data),
+            Some(settings)
+        );
+
+        // Version 5 ends after the profiles
+        let v5 = &data[..SETTINGS_SIZE - TAIL_SIZE];
+        let loaded = Settings::from_record(5, v5).unwrap();
+        assert_eq!(loaded.profiles, settings.profiles);
+        assert_eq!(loaded.macros, [Macro::default(); MACRO_COUNT]);
+        assert_eq!(Settings::from_record(6, v5), None);
+    }
+
+    #[test]
+    fn extends_v6_with_triggers() {
+        let mut settings = Settings::default();
+        settings.macros[0].set_looping(true);
+        settings.profiles[2].set_name("fps");
+        let data = settings.pack().unwrap();
+        // Version 6 profiles end before the trigger settings, and the record before the
+        // trigger calibration
+        let v6_size = PROFILE_SIZE - 8;
+        let mut v6 = data[..GLOBAL_SIZE].to_vec();
+        let profiles = &data[GLOBAL_SIZE..GLOBAL_SIZE + PROFILE_COUNT * PROFILE_SIZE];
+        for profile in profiles.chunks(PROFILE_SIZE) {
+            v6.extend_from_slice(&profile[..v6_size]);
+        }
+        v6.extend(v8_macros(&settings.macros));
+        assert_eq!(Settings::from_record(6, &v6), Some(settings));
+    }
+
+    #[test]
+    fn extends_v7_with_dpad() {
+        let mut settings = Settings::default();
+        settings.profiles[0].set_name("fighter");
+        settings.trigger_calibration[1].released = 300;
+        let data = settings.pack().unwrap();
+        // Version 7 profiles end before the SOCD and D-pad modes
+        let v7_size = PROFILE_SIZE - 2;
+        let mut v7 = data[..GLOBAL_SIZE].to_vec();
+        let profiles = &data[GLOBAL_SIZE..GLOBAL_SIZE + PROFILE_COUNT * PROFILE_SIZE];
+        for profile in profiles.chunks(PROFILE_SIZE) {
+            v7.extend_from_slice(&profile[..v7_size]);
+        }
+        v7.extend(v8_macros(&settings.macros));
+        v7.extend_from_slice(&data[SETTINGS_SIZE - TRIGGER_CALIBRATION_SIZE..]);
+        assert_eq!(Settings::from_record(7, &v7), Some(settings));
+    }
+
+    /// Packs macros in their layout before version 9, with their steps cut before the
+    /// D-pad and triggers.
+    fn v8_macros(macros: &[Macro; MACRO_COUNT]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for sequence in macros {
+            let packed = sequence.pack().unwrap();
+            data.extend_from_slice(&packed[..MACRO_HEADER_SIZE]);
+            for step in packed[MACRO_HEADER_SIZE..].chunks(MACRO_STEP_SIZE) {
+                data.extend_from_slice(&step[..MACRO_STEP_SIZE_V8]);
+            }
+            data.resize(
+                data.len() + (MACRO_STEPS_V8 - MACRO_STEPS) * MACRO_STEP_SIZE_V8,
+                0,
+            );
+        }
+        assert_eq!(data.len(), MACROS_SIZE_V8);
+        data
+    }
+
+    #[test]
+    fn widens_v8_macro_steps() {
+        let mut settings = Settings::default();
+        settings.macros[1].set_trigger(Some(crate::controller::Button::UnderR));
+        for buttons in 0..MACRO_STEPS as u8 {
+            settings.macros[1].push(MacroStep {
+                buttons,
+                sticks: [0x80; 4],
+                ..MacroStep::default()
+            });
+        }
+        let data = settings.pack().unwrap();
+        let mut v8 = data[..SETTINGS_SIZE - TAIL_SIZE].to_vec();
+        v8.extend(v8_macros(&settings.macros));
+        v8.extend_from_slice(&data[SETTINGS_SIZE - TRIGGER_CALIBRATION_SIZE..]);
+        assert_eq!(Settings::from_record(8, &v8), Some(settings));
+
+        // Longer recordings are cut to the steps that fit
+        let second = GLOBAL_SIZE + PROFILE_COUNT * PROFILE_SIZE + MACRO_SIZE_V8;
+        v8[second + 2] = MACRO_STEPS_V8 as u8;
+        for step in MACRO_STEPS..MACRO_STEPS_V8 {
+            let start = second + MACRO_HEADER_SIZE + step * MACRO_STEP_SIZE_V8;
+            v8[start + 5] = 1;
+        }
+        let loaded = Settings::from_record(8, &v8).unwrap();
+        assert_eq!(loaded.macros[1], settings.macros[1]);
+        assert!(loaded.is_valid());
+        assert_eq!(Settings::from_record(8, &v8[1..]), None);
+    }
+
+    #[test]
+    fn switches_profiles() {
+        let mut settings = Settings::default();
+        settings.profiles[2].set_name("racing");
+        assert_eq!(settings.find_profile("racing"), Some(2));
+        assert_eq!(settings.find_profile("4"), Some(3));
+        assert_eq!(settings.find_profile("5"), None);
+        settings.select_profile(3);
+        assert_eq!(settings.active_profile(), 3);
+        settings.select_profile(PROFILE_COUNT);
+        assert_eq!(settings.active_profile(), 3);
+    }
+}
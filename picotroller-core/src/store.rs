@@ -0,0 +1,270 @@
+//! Record store in the flash sectors reserved at the end of `memory.x`.
+//!
+//! The region is split into fixed size slots, each holding at most one record. Every save goes
+//! to the slot after the newest record, so the sectors are erased in turn rather than the same
+//! one on every save, and the previous record stays intact until the new one is written.
+//! Loading picks the valid record with the highest sequence number.
+
+use packed_struct::prelude::*;
+
+pub const FLASH_SECTOR_SIZE: u32 = 4096;
+
+/// Sectors at the end of flash reserved for the store, keep in sync with `memory.x`.
+pub const STORE_SECTORS: u32 = 4;
+
+/// A multiple of the 256 byte flash page, the smallest unit that can be programmed.
+const SLOT_SIZE: usize = 1024;
+const SLOTS_PER_SECTOR: u32 = FLASH_SECTOR_SIZE / SLOT_SIZE as u32;
+const SLOT_COUNT: u32 = STORE_SECTORS * SLOTS_PER_SECTOR;
+
+const RECORD_MAGIC: u32 = 0x5049_434f; // "PICO"
+const HEADER_SIZE: usize = 16;
+/// Largest payload a record can hold.
+pub const PAYLOAD_MAX: usize = SLOT_SIZE - HEADER_SIZE;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[packed_struct(endian = "lsb", size_bytes = "16")]
+struct Header {
+    #[packed_field]
+    magic: u32,
+    #[packed_field]
+    version: u16,
+    #[packed_field]
+    len: u16,
+    #[packed_field]
+    sequence: u32,
+    /// CRC-32 of the version, length and sequence fields followed by the payload.
+    #[packed_field]
+    crc: u32,
+}
+
+impl Header {
+    fn checksum(&self, payload: &[u8]) -> u32 {
+        let packed = self.pack().unwrap_or_default();
+        !crc32(crc32(!0, &packed[4..12]), payload)
+    }
+}
+
+/// A record found in the store.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record {
+    /// Format version of the payload, chosen by the caller.
+    pub version: u16,
+    pub len: usize,
+}
+
+/// Flash the store is kept in, with offsets relative to the start of the store.
+pub trait Flash {
+    fn read(&self, offset: u32, data: &mut [u8]);
+    fn erase_sector(&mut self, offset: u32);
+    fn program(&mut self, offset: u32, data: &[u8]);
+}
+
+/// Reads the newest valid record into `payload`, which must hold `PAYLOAD_MAX` bytes.
+pub fn load(flash: &impl Flash, payload: &mut [u8]) -> Option<Record> {
+    let (slot, header) = newest(flash)?;
+    let len = header.len as usize;
+    flash.read(slot_offset(slot) + HEADER_SIZE as u32, &mut payload[..len]);
+    Some(Record {
+        version: header.version,
+        len,
+    })
+}
+
+/// Writes a record to the slot after the newest one, erasing its sector first if needed.
+pub fn save(flash: &mut impl Flash, version: u16, payload: &[u8]) {
+    let newest = newest(flash);
+    let mut slot = newest.map_or(0, |(slot, _)| (slot + 1) % SLOT_COUNT);
+    // A slot is only written once between erases, a used one left over from an interrupted
+    // save means skipping to the next sector, which never holds the newest record
+    if !slot.is_multiple_of(SLOTS_PER_SECTOR) && !is_blank(flash, slot) {
+        slot = (slot / SLOTS_PER_SECTOR + 1) % STORE_SECTORS * SLOTS_PER_SECTOR;
+    }
+    if slot.is_multiple_of(SLOTS_PER_SECTOR) {
+        flash.erase_sector(slot / SLOTS_PER_SECTOR * FLASH_SECTOR_SIZE);
+    }
+
+    let mut header = Header {
+        magic: RECORD_MAGIC,
+        version,
+        len: payload.len() as u16,
+        sequence: newest.map_or(0, |(_, header)| header.sequence.wrapping_add(1)),
+        crc: 0,
+    };
+    header.crc = header.checksum(payload);
+
+    let mut data = [0xffu8; SLOT_SIZE];
+    data[..HEADER_SIZE].copy_from_slice(&header.pack().unwrap_or_default());
+    data[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+    flash.program(slot_offset(slot), &data);
+}
+
+fn slot_offset(slot: u32) -> u32 {
+    slot * SLOT_SIZE as u32
+}
+
+fn is_blank(flash: &impl Flash, slot: u32) -> bool {
+    let mut data = [0u8; SLOT_SIZE];
+    flash.read(slot_offset(slot), &mut data);
+    data.iter().all(|byte| *byte == 0xff)
+}
+
+/// Slot and header of the valid record with the highest sequence number.
+fn newest(flash: &impl Flash) -> Option<(u32, Header)> {
+    let mut newest: Option<(u32, Header)> = None;
+    let mut data = [0u8; SLOT_SIZE];
+    for slot in 0..SLOT_COUNT {
+        flash.read(slot_offset(slot), &mut data);
+        let header = match read_header(&data) {
+            Some(header) => header,
+            None => continue,
+        };
+        let is_newer =
+            newest.is_none_or(|(_, best)| (header.sequence.wrapping_sub(best.sequence) as i32) > 0);
+        if is_newer {
+            newest = Some((slot, header));
+        }
+    }
+    newest
+}
+
+fn read_header(data: &[u8; SLOT_SIZE]) -> Option<Header> {
+    let header = Header::unpack_from_slice(&data[..HEADER_SIZE]).ok()?;
+    let len = header.len as usize;
+    if header.magic != RECORD_MAGIC || len > PAYLOAD_MAX {
+        return None;
+    }
+    let payload = &data[HEADER_SIZE..HEADER_SIZE + len];
+    (header.checksum(payload) == header.crc).then_some(header)
+}
+
+/// Bitwise CRC-32 (IEEE), slow but small and only run when loading or saving.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = (STORE_SECTORS * FLASH_SECTOR_SIZE) as usize;
+
+    /// Flash in RAM, checking that programmed bytes were erased first.
+    struct RamFlash {
+        data: Vec<u8>,
+        erases: [u32; STORE_SECTORS as usize],
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self {
+                data: vec![0xff; SIZE],
+                erases: [0; STORE_SECTORS as usize],
+            }
+        }
+    }
+
+    impl Flash for RamFlash {
+        fn read(&self, offset: u32, data: &mut [u8]) {
+            let offset = offset as usize;
+            data.copy_from_slice(&self.data[offset..offset + data.len()]);
+        }
+
+        fn erase_sector(&mut self, offset: u32) {
+            assert_eq!(offset % FLASH_SECTOR_SIZE, 0);
+            self.erases[(offset / FLASH_SECTOR_SIZE) as usize] += 1;
+            let offset = offset as usize;
+            self.data[offset..offset + FLASH_SECTOR_SIZE as usize].fill(0xff);
+        }
+
+        fn program(&mut self, offset: u32, data: &[u8]) {
+            let offset = offset as usize;
+            let target = &mut self.data[offset..offset + data.len()];
+            assert!(target.iter().all(|byte| *byte == 0xff), "not erased");
+            target.copy_from_slice(data);
+        }
+    }
+
+    fn load_vec(flash: &RamFlash) -> Option<(u16, Vec<u8>)> {
+        let mut payload = [0u8; PAYLOAD_MAX];
+        load(flash, &mut payload).map(|record| (record.version, payload[..record.len].to_vec()))
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn empty_store_has_no_record() {
+        assert_eq!(load_vec(&RamFlash::new()), None);
+    }
+
+    #[test]
+    fn loads_newest_record() {
+        let mut flash = RamFlash::new();
+        save(&mut flash, 1, b"first");
+        save(&mut flash, 2, b"second");
+        assert_eq!(load_vec(&flash), Some((2, b"second".to_vec())));
+    }
+
+    #[test]
+    fn saves_rotate_through_sectors() {
+        let mut flash = RamFlash::new();
+        let saves = SLOT_COUNT * 3 + 1;
+        for i in 0..saves {
+            save(&mut flash, 1, &i.to_le_bytes());
+        }
+        assert_eq!(
+            load_vec(&flash),
+            Some((1, (saves - 1).to_le_bytes().to_vec()))
+        );
+        assert_eq!(flash.erases, [4, 3, 3, 3]);
+    }
+
+    #[test]
+    fn corrupt_record_falls_back_to_previous() {
+        let mut flash = RamFlash::new();
+        save(&mut flash, 1, b"good");
+        save(&mut flash, 1, b"torn");
+        flash.data[slot_offset(1) as usize + HEADER_SIZE] ^= 0x01;
+        assert_eq!(load_vec(&flash), Some((1, b"good".to_vec())));
+    }
+
+    #[test]
+    fn save_after_corrupt_record_skips_used_slot() {
+        let mut flash = RamFlash::new();
+        save(&mut flash, 1, b"good");
+        save(&mut flash, 1, b"torn");
+        flash.data[slot_offset(1) as usize + HEADER_SIZE] ^= 0x01;
+        save(&mut flash, 1, b"next");
+        assert_eq!(load_vec(&flash), Some((1, b"next".to_vec())));
+        assert_eq!(flash.erases, [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        let mut flash = RamFlash::new();
+        save(&mut flash, 1, b"old");
+        let mut header = read_header(&slot(&flash, 0)).unwrap();
+        header.sequence = u32::MAX;
+        header.crc = header.checksum(b"old");
+        flash.data[..HEADER_SIZE].copy_from_slice(&header.pack().unwrap());
+        save(&mut flash, 1, b"new");
+        assert_eq!(read_header(&slot(&flash, 1)).unwrap().sequence, 0);
+        assert_eq!(load_vec(&flash), Some((1, b"new".to_vec())));
+    }
+
+    fn slot(flash: &RamFlash, slot: u32) -> [u8; SLOT_SIZE] {
+        let mut data = [0u8; SLOT_SIZE];
+        flash.read(slot_offset(slot), &mut data);
+        data
+    }
+}
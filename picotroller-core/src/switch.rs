@@ -0,0 +1,111 @@
+use packed_struct::prelude::*;
+
+/// Report descriptor of the HORI Pokken Tournament Pro Pad, which the Switch accepts as a
+/// wired controller without the handshake the Pro Controller needs.
+#[rustfmt::skip]
+pub const SWITCH_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x05, // Usage (Gamepad)
+    0xA1, 0x01, // Collection (Application)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x35, 0x00, //   Physical Minimum (0)
+        0x45, 0x01, //   Physical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x10, //   Report Count (16)
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (1)
+        0x29, 0x10, //   Usage Maximum (16)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x25, 0x07,       //   Logical Maximum (7)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x75, 0x04,       //   Report Size (4)
+        0x95, 0x01,       //   Report Count (1)
+        0x65, 0x14,       //   Unit (Degrees)
+        0x09, 0x39,       //   Usage (Hat Switch)
+        0x81, 0x42,       //   Input (Data, Variable, Absolute, Null State)
+        0x65, 0x00,       //   Unit (None)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x01,       //   Input (Constant)
+
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x46, 0xFF, 0x00, //   Physical Maximum (255)
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x09, 0x32,       //   Usage (Z)
+        0x09, 0x35,       //   Usage (RZ)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, 0x04,       //   Report Count (4)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x06, 0x00, 0xFF, //   Usage Page (Vendor Defined)
+        0x09, 0x20,       //   Usage (0x20)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x02,       //   Input (Data, Variable, Absolute)
+
+        0x0A, 0x21, 0x26, //   Usage (0x2621)
+        0x95, 0x08,       //   Report Count (8)
+        0x91, 0x02,       //   Output (Data, Variable, Absolute)
+    0xC0,       // End Collection
+];
+
+/// Button bits of the Switch report.
+#[allow(unused)]
+pub mod buttons {
+    pub const Y: u16 = 1 << 0;
+    pub const B: u16 = 1 << 1;
+    pub const A: u16 = 1 << 2;
+    pub const X: u16 = 1 << 3;
+    pub const L: u16 = 1 << 4;
+    pub const R: u16 = 1 << 5;
+    pub const ZL: u16 = 1 << 6;
+    pub const ZR: u16 = 1 << 7;
+    pub const MINUS: u16 = 1 << 8;
+    pub const PLUS: u16 = 1 << 9;
+    pub const THUMB_L: u16 = 1 << 10;
+    pub const THUMB_R: u16 = 1 << 11;
+    pub const HOME: u16 = 1 << 12;
+    pub const CAPTURE: u16 = 1 << 13;
+}
+
+/// Hat value with no direction pressed, directions count clockwise from 0 for up.
+pub const HAT_CENTER: u8 = 8;
+
+/// Stick value of a centered axis, axes run from 0 to 255 with Y pointing down.
+pub const AXIS_CENTER: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "8")]
+pub struct SwitchReport {
+    #[packed_field]
+    pub buttons: u16,
+    #[packed_field]
+    pub hat: u8,
+    #[packed_field]
+    pub lx: u8,
+    #[packed_field]
+    pub ly: u8,
+    #[packed_field]
+    pub rx: u8,
+    #[packed_field]
+    pub ry: u8,
+    #[packed_field]
+    vendor: u8,
+}
+
+impl Default for SwitchReport {
+    fn default() -> Self {
+        Self {
+            buttons: 0,
+            hat: HAT_CENTER,
+            lx: AXIS_CENTER,
+            ly: AXIS_CENTER,
+            rx: AXIS_CENTER,
+            ry: AXIS_CENTER,
+            vendor: 0,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::calibration::AXIS_MAX;
+use crate::controller::ADC_MAX_VALUE_3V3;
+use packed_struct::prelude::*;
+
+/// Minimum raw travel between released and pressed for a calibration to be accepted.
+const MIN_TRAVEL: u16 = 256;
+/// Largest hair trigger sensitivity, in percent of the travel.
+pub const HAIR_SENSITIVITY_MAX: u8 = 50;
+
+/// Raw readings of a trigger at rest and fully pulled. Hall effect sensors may read lower
+/// when pulled, so `pressed` can be either side of `released`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "4")]
+pub struct TriggerCalibration {
+    #[packed_field]
+    pub released: u16,
+    #[packed_field]
+    pub pressed: u16,
+}
+
+impl Default for TriggerCalibration {
+    fn default() -> Self {
+        Self {
+            released: 0,
+            pressed: ADC_MAX_VALUE_3V3 as u16,
+        }
+    }
+}
+
+impl TriggerCalibration {
+    pub fn is_valid(&self) -> bool {
+        self.released.abs_diff(self.pressed) >= MIN_TRAVEL
+    }
+
+    /// Maps a raw reading onto `0..=AXIS_MAX`, from released to pressed.
+    #[inline]
+    pub fn apply(&self, raw: u16) -> i32 {
+        let travel = self.pressed as i32 - self.released as i32;
+        if travel == 0 {
+            return 0;
+        }
+        let pulled = (raw as i32 - self.released as i32) * AXIS_MAX / travel;
+        pulled.clamp(0, AXIS_MAX)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PrimitiveEnum_u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerMode {
+    /// The digital button is pressed past a fixed point of the travel.
+    Threshold = 0,
+    /// The digital button is pressed as soon as the trigger moves in by the sensitivity, and
+    /// released as soon as it moves back out by as much, wherever along the travel.
+    Hair = 1,
+}
+
+/// How an analog trigger also presses its digital button, `BTN_TL2` or `BTN_TR2`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "3")]
+pub struct TriggerSettings {
+    #[packed_field(size_bytes = "1", ty = "enum")]
+    pub mode: TriggerMode,
+    /// Percent of the travel pressing the button in `Threshold` mode
+    #[packed_field]
+    pub threshold: u8,
+    /// Percent of the travel to move in or out to press or release in `Hair` mode
+    #[packed_field]
+    pub sensitivity: u8,
+}
+
+impl Default for TriggerSettings {
+    fn default() -> Self {
+        Self {
+            mode: TriggerMode::Threshold,
+            threshold: 50,
+            sensitivity: 5,
+        }
+    }
+}
+
+impl TriggerSettings {
+    pub fn is_valid(&self) -> bool {
+        (1..=100).contains(&self.threshold)
+            && (1..=HAIR_SENSITIVITY_MAX).contains(&self.sensitivity)
+    }
+}
+
+fn percent(value: u8) -> i32 {
+    AXIS_MAX * value as i32 / 100
+}
+
+/// An analog trigger, fed with a raw reading every tick.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TriggerState {
+    /// Raw reading, `None` while no trigger is wired
+    pub raw: Option<u16>,
+    pub calibration: TriggerCalibration,
+    pub settings: TriggerSettings,
+    pressed: bool,
+    /// Deepest travel since pressed, or shallowest since released, for the hair trigger
+    extreme: i32,
+}
+
+impl TriggerState {
+    /// Takes this tick's reading and works out whether the digital button is pressed.
+    pub fn update(&mut self, raw: Option<u16>) {
+        self.raw = raw;
+        let value = self.value();
+        let pressed = match self.settings.mode {
+            TriggerMode::Threshold => value >= percent(self.settings.threshold),
+            TriggerMode::Hair => {
+                let sensitivity = percent(self.settings.sensitivity);
+                if value < sensitivity {
+                    // Back at rest, so a press never gets stuck
+                    false
+                } else if self.pressed {
+                    value > self.extreme - sensitivity
+                } else {
+                    value >= self.extreme + sensitivity
+                }
+            }
+        };
+        self.extreme = if pressed != self.pressed {
+            value
+        } else if pressed {
+            self.extreme.max(value)
+        } else {
+            self.extreme.min(value)
+        };
+        self.pressed = pressed;
+    }
+
+    /// Travel within `0..=AXIS_MAX`, 0 while no trigger is wired.
+    #[inline]
+    pub fn value(&self) -> i32 {
+        self.raw.map_or(0, |raw| self.calibration.apply(raw))
+    }
+
+    /// Whether the digital button is pressed.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_maps_either_direction() {
+        let calibration = TriggerCalibration {
+            released: 1000,
+            pressed: 3000,
+        };
+        assert_eq!(calibration.apply(500), 0);
+        assert_eq!(calibration.apply(1000), 0);
+        assert_eq!(calibration.apply(2000), AXIS_MAX / 2);
+        assert_eq!(calibration.apply(3500), AXIS_MAX);
+
+        // A hall effect sensor reading lower as the trigger is pulled
+        let calibration = TriggerCalibration {
+            released: 3000,
+            pressed: 1000,
+        };
+        assert_eq!(calibration.apply(3100), 0);
+        assert_eq!(calibration.apply(2000), AXIS_MAX / 2);
+        assert_eq!(calibration.apply(0), AXIS_MAX);
+    }
+
+    #[test]
+    fn validates_travel() {
+        assert!(TriggerCalibration::default().is_valid());
+        let calibration = |released, pressed| TriggerCalibration { released, pressed };
+        assert!(calibration(2000, 2000 - MIN_TRAVEL).is_valid());
+        assert!(!calibration(2000, 2000 + MIN_TRAVEL - 1).is_valid());
+        assert!(!calibration(0, 0).is_valid());
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(TriggerSettings::default().is_valid());
+        let settings = |threshold, sensitivity| TriggerSettings {
+            mode: TriggerMode::Hair,
+            threshold,
+            sensitivity,
+        };
+        assert!(settings(100, HAIR_SENSITIVITY_MAX).is_valid());
+        assert!(!settings(0, 5).is_valid());
+        assert!(!settings(101, 5).is_valid());
+        assert!(!settings(50, 0).is_valid());
+        assert!(!settings(50, HAIR_SENSITIVITY_MAX + 1).is_valid());
+    }
+
+    /// Whether the button is pressed after each reading, in percent of the travel.
+    fn presses(settings: TriggerSettings, travel: &[u16]) -> Vec<bool> {
+        let mut trigger = TriggerState {
+            calibration: TriggerCalibration {
+                released: 0,
+                pressed: 1000,
+            },
+            settings,
+            ..TriggerState::default()
+        };
+        travel
+            .iter()
+            .map(|percent| {
+                trigger.update(Some(percent * 10));
+                trigger.is_pressed()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn threshold_presses_past_fixed_point() {
+        let settings = TriggerSettings {
+            threshold: 40,
+            ..TriggerSettings::default()
+        };
+        assert_eq!(
+            presses(settings, &[0, 39, 40, 100, 60, 39, 0]),
+            [false, false, true, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn hair_trigger_follows_direction() {
+        let settings = TriggerSettings {
+            mode: TriggerMode::Hair,
+            sensitivity: 10,
+            ..TriggerSettings::default()
+        };
+        assert_eq!(
+            presses(settings, &[0, 5, 10, 80, 71, 70, 50, 59, 60, 95, 90, 85]),
+            [false, false, true, true, true, false, false, false, true, true, true, false]
+        );
+        // Letting go always releases, even without moving back by the sensitivity
+        assert_eq!(
+            presses(settings, &[0, 12, 9, 0, 10]),
+            [false, true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn unwired_trigger_reads_zero() {
+        let mut trigger = TriggerState::default();
+        trigger.update(None);
+        assert_eq!(trigger.value(), 0);
+        assert!(!trigger.is_pressed());
+    }
+}
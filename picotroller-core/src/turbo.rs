@@ -0,0 +1,246 @@
+use crate::controller::{Button, Controller};
+use packed_struct::prelude::*;
+
+/// Fastest turbo rate, a press and a release need at least one 10ms report each.
+pub const TURBO_RATE_MAX: u8 = 30;
+/// Microseconds between reports, the shortest a press or release can last to be seen.
+const REPORT_PERIOD: u64 = 10_000;
+
+/// Rapid fire for held buttons: while held, a turbo button is reported pressed for `duty`
+/// percent of each period and released for the rest, starting pressed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "3")]
+pub struct Turbo {
+    /// Bit per button with turbo on, indexed like `RemapTable`
+    #[packed_field]
+    pub buttons: u8,
+    /// Presses per second
+    #[packed_field]
+    pub rate: u8,
+    /// Percent of each press period the button is reported held
+    #[packed_field]
+    pub duty: u8,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self {
+            buttons: 0,
+            rate: 10,
+            duty: 50,
+        }
+    }
+}
+
+impl Turbo {
+    pub fn is_enabled(&self, button: Button) -> bool {
+        self.buttons & button_bit(button) != 0
+    }
+
+    pub fn set_enabled(&mut self, button: Button, enabled: bool) {
+        if enabled {
+            self.buttons |= button_bit(button);
+        } else {
+            self.buttons &= !button_bit(button);
+        }
+    }
+
+    /// Whether the rate is in range and the duty leaves both the press and the release at
+    /// least one report at that rate.
+    pub fn is_valid(&self) -> bool {
+        if !(1..=TURBO_RATE_MAX).contains(&self.rate) || !(1..100).contains(&self.duty) {
+            return false;
+        }
+        let pressed = self.period() * self.duty as u64 / 100;
+        pressed >= REPORT_PERIOD && self.period() - pressed >= REPORT_PERIOD
+    }
+
+    /// Whether a turbo button held for `held` microseconds is reported pressed.
+    pub fn phase(&self, held: u64) -> bool {
+        held % self.period() < self.period() * self.duty as u64 / 100
+    }
+
+    /// Microseconds of each press and release.
+    fn period(&self) -> u64 {
+        1_000_000 / self.rate.max(1) as u64
+    }
+}
+
+fn button_bit(button: Button) -> u8 {
+    1 << button as u8
+}
+
+/// Times how long each turbo button has been held, fed with the captured buttons every tick.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TurboClock {
+    pressed_at: [Option<u64>; Button::COUNT],
+}
+
+impl TurboClock {
+    /// Marks the turbo buttons in their released phase at `now` to be left out of reports.
+    pub fn update(&mut self, controller: &mut Controller, now: u64) {
+        let mut released = 0;
+        for (pressed_at, button) in self.pressed_at.iter_mut().zip(Button::ALL) {
+            if !controller.is_pressed(button) || !controller.turbo.is_enabled(button) {
+                *pressed_at = None;
+                continue;
+            }
+            let held = now.wrapping_sub(*pressed_at.get_or_insert(now));
+            if !controller.turbo.phase(held) {
+                released |= button_bit(button);
+            }
+        }
+        controller.turbo_released = released;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::buttons;
+    use crate::device::JoystickReport;
+
+    #[test]
+    fn toggles_buttons() {
+        let mut turbo = Turbo::default();
+        turbo.set_enabled(Button::FrontR, true);
+        turbo.set_enabled(Button::UnderL, true);
+        assert!(turbo.is_enabled(Button::FrontR));
+        assert!(turbo.is_enabled(Button::UnderL));
+        assert!(!turbo.is_enabled(Button::FrontL));
+        turbo.set_enabled(Button::FrontR, false);
+        assert!(!turbo.is_enabled(Button::FrontR));
+        assert!(turbo.is_enabled(Button::UnderL));
+    }
+
+    #[test]
+    fn validates_rate_and_duty() {
+        assert!(Turbo::default().is_valid());
+        let turbo = |rate, duty| Turbo {
+            buttons: 0,
+            rate,
+            duty,
+        };
+        assert!(turbo(TURBO_RATE_MAX, 50).is_valid());
+        assert!(!turbo(0, 50).is_valid());
+        assert!(!turbo(TURBO_RATE_MAX + 1, 50).is_valid());
+        assert!(!turbo(10, 0).is_valid());
+        assert!(!turbo(10, 100).is_valid());
+    }
+
+    #[test]
+    fn duty_leaves_a_report_for_press_and_release() {
+        let turbo = |rate, duty| Turbo {
+            buttons: 0,
+            rate,
+            duty,
+        };
+        // 100ms period, 10ms each side at least
+        assert!(turbo(10, 10).is_valid());
+        assert!(turbo(10, 90).is_valid());
+        assert!(!turbo(10, 9).is_valid());
+        assert!(!turbo(10, 91).is_valid());
+        // 33.3ms period
+        assert!(turbo(TURBO_RATE_MAX, 31).is_valid());
+        assert!(turbo(TURBO_RATE_MAX, 70).is_valid());
+        assert!(!turbo(TURBO_RATE_MAX, 30).is_valid());
+        assert!(!turbo(TURBO_RATE_MAX, 71).is_valid());
+
+        // Each phase of the shortest valid duty shows in the 10ms reports
+        for (rate, duty) in [
+            (TURBO_RATE_MAX, 31),
+            (TURBO_RATE_MAX, 70),
+            (25, 25),
+            (25, 75),
+        ] {
+            let mut turbo = turbo(rate, duty);
+            turbo.set_enabled(Button::FrontR, true);
+            let reports = reported(turbo, Button::FrontR, 100);
+            let presses = reports
+                .windows(2)
+                .filter(|w| w[0] == 0 && w[1] != 0)
+                .count();
+            assert_eq!(presses, rate as usize - 1, "{} {}", rate, duty);
+        }
+    }
+
+    #[test]
+    fn phase_follows_duty() {
+        // 100ms period, held for the first 25ms of each
+        let turbo = Turbo {
+            buttons: 0,
+            rate: 10,
+            duty: 25,
+        };
+        for (held, pressed) in [
+            (0, true),
+            (24_999, true),
+            (25_000, false),
+            (99_999, false),
+            (100_000, true),
+            (125_000, false),
+        ] {
+            assert_eq!(turbo.phase(held), pressed, "{}", held);
+        }
+    }
+
+    /// The HID buttons reported at each 10ms tick while `button` is held from tick 0.
+    fn reported(turbo: Turbo, button: Button, ticks: u64) -> Vec<u16> {
+        let mut controller = Controller {
+            turbo,
+            ..Controller::default()
+        };
+        let mut clock = TurboClock::default();
+        controller.set_button(button, true);
+        (0..ticks)
+            .map(|tick| {
+                clock.update(&mut controller, tick * 10_000);
+                let mut report = JoystickReport::default();
+                controller.hid_report(&mut report);
+                report.buttons
+            })
+            .collect()
+    }
+
+    #[test]
+    fn held_turbo_button_toggles() {
+        let mut turbo = Turbo {
+            buttons: 0,
+            rate: 25,
+            duty: 50,
+        };
+        turbo.set_enabled(Button::FrontR, true);
+        let on = buttons::BTN_SOUTH;
+        assert_eq!(
+            reported(turbo, Button::FrontR, 10),
+            [on, on, 0, 0, on, on, 0, 0, on, on]
+        );
+        // Buttons without turbo stay held
+        assert_eq!(reported(turbo, Button::FrontL, 3), [buttons::BTN_EAST; 3]);
+    }
+
+    #[test]
+    fn press_restarts_phase() {
+        let mut turbo = Turbo::default();
+        turbo.set_enabled(Button::FrontR, true);
+        let mut controller = Controller {
+            turbo,
+            ..Controller::default()
+        };
+        let mut clock = TurboClock::default();
+        controller.set_button(Button::FrontR, true);
+        clock.update(&mut controller, 0);
+        clock.update(&mut controller, 60_000);
+        assert!(!controller.is_reported(Button::FrontR));
+        controller.set_button(Button::FrontR, false);
+        clock.update(&mut controller, 70_000);
+        controller.set_button(Button::FrontR, true);
+        clock.update(&mut controller, 80_000);
+        assert!(controller.is_reported(Button::FrontR));
+        // The button itself is still seen as held, for hotkeys
+        clock.update(&mut controller, 140_000);
+        assert!(controller.is_pressed(Button::FrontR));
+        assert!(!controller.is_reported(Button::FrontR));
+    }
+}
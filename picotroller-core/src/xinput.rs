@@ -0,0 +1,119 @@
+use crate::platform::{HostCommand, HostLed, Rumble};
+use packed_struct::prelude::*;
+
+pub const XINPUT_REPORT_SIZE: u8 = 20;
+
+const MESSAGE_RUMBLE: u8 = 0x00;
+const MESSAGE_LED: u8 = 0x01;
+
+#[allow(unused)]
+pub mod buttons {
+    pub const DPAD_UP: u16 = 1 << 0;
+    pub const DPAD_DOWN: u16 = 1 << 1;
+    pub const DPAD_LEFT: u16 = 1 << 2;
+    pub const DPAD_RIGHT: u16 = 1 << 3;
+    pub const START: u16 = 1 << 4;
+    pub const BACK: u16 = 1 << 5;
+    pub const THUMB_L: u16 = 1 << 6;
+    pub const THUMB_R: u16 = 1 << 7;
+    pub const SHOULDER_L: u16 = 1 << 8;
+    pub const SHOULDER_R: u16 = 1 << 9;
+    pub const GUIDE: u16 = 1 << 10;
+    pub const A: u16 = 1 << 12;
+    pub const B: u16 = 1 << 13;
+    pub const X: u16 = 1 << 14;
+    pub const Y: u16 = 1 << 15;
+}
+
+/// Input report of a wired Xbox 360 controller. Sticks have Y pointing up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PackedStruct)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packed_struct(endian = "lsb", size_bytes = "20")]
+pub struct XInputReport {
+    #[packed_field]
+    message_type: u8,
+    #[packed_field]
+    size: u8,
+    #[packed_field]
+    pub buttons: u16,
+    #[packed_field]
+    pub lt: u8,
+    #[packed_field]
+    pub rt: u8,
+    #[packed_field]
+    pub lx: i16,
+    #[packed_field]
+    pub ly: i16,
+    #[packed_field]
+    pub rx: i16,
+    #[packed_field]
+    pub ry: i16,
+    #[packed_field]
+    reserved: [u8; 6],
+}
+
+impl Default for XInputReport {
+    fn default() -> Self {
+        Self {
+            message_type: 0x00,
+            size: XINPUT_REPORT_SIZE,
+            buttons: 0,
+            lt: 0,
+            rt: 0,
+            lx: 0,
+            ly: 0,
+            rx: 0,
+            ry: 0,
+            reserved: [0; 6],
+        }
+    }
+}
+
+/// Output reports the host sends to the controller.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum XInputOutput {
+    Rumble {
+        left: u8,
+        right: u8,
+    },
+    /// One of the ring of light animations, 0x06 - 0x09 light up a single player quadrant
+    Led(u8),
+}
+
+impl XInputOutput {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        match data {
+            [MESSAGE_RUMBLE, 0x08, _, left, right, ..] => Some(Self::Rumble {
+                left: *left,
+                right: *right,
+            }),
+            [MESSAGE_LED, 0x03, pattern, ..] => Some(Self::Led(*pattern)),
+            _ => None,
+        }
+    }
+}
+
+impl From<XInputOutput> for HostCommand {
+    fn from(output: XInputOutput) -> Self {
+        match output {
+            XInputOutput::Rumble { left, right } => Self {
+                rumble: Some(Rumble { left, right }),
+                led: None,
+            },
+            // The quadrant of a player, flashing first then on, other animations turn it off
+            XInputOutput::Led(pattern @ 0x02..=0x05) => Self {
+                rumble: None,
+                led: Some(HostLed::Player(pattern - 0x01)),
+            },
+            XInputOutput::Led(pattern @ 0x06..=0x09) => Self {
+                rumble: None,
+                led: Some(HostLed::Player(pattern - 0x05)),
+            },
+            XInputOutput::Led(_) => Self {
+                rumble: None,
+                led: Some(HostLed::Off),
+            },
+        }
+    }
+}
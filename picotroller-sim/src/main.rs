@@ -0,0 +1,89 @@
+//! Feeds a script of raw inputs through the controller logic and prints the HID joystick
+//! reports the firmware would send, to try mapping and deadzone changes without a board.
+//!
+//! Usage: `picotroller-sim [--raw] [script]`, reading the script from stdin without a path.
+//! Reports go to stdout, console output to stderr.
+
+use packed_struct::prelude::*;
+use picotroller_core::settings::Settings;
+use sim::Simulator;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+mod script;
+mod sim;
+
+fn main() -> ExitCode {
+    let mut raw = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => {
+                eprintln!("usage: picotroller-sim [--raw] [script]");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let text = match path {
+        Some(path) => std::fs::read_to_string(&path),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map(|_| text)
+        }
+    };
+    let text = match text {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("error reading script: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let steps = match script::parse(&text) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("error in script, {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut simulator = Simulator::new(Settings::default());
+    let reports = match simulator.run(&steps, &mut io::stderr()) {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("error writing console output: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut out = io::stdout().lock();
+    for (time, report) in reports {
+        let ms = time as f64 / 1000.0;
+        let result = if raw {
+            let data = report.pack().unwrap_or_default();
+            let hex: Vec<_> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+            writeln!(out, "{:>9.1} {}", ms, hex.join(" "))
+        } else {
+            writeln!(
+                out,
+                "{:>9.1} lx {:>4} ly {:>4} lz {:>4} rx {:>4} ry {:>4} rz {:>4} buttons {:016b} hat {} dpad {:04b}",
+                ms,
+                report.lx,
+                report.ly,
+                report.lz,
+                report.rx,
+                report.ry,
+                report.rz,
+                report.buttons,
+                report.hat,
+                report.dpad
+            )
+        };
+        if result.is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
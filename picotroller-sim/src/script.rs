@@ -0,0 +1,208 @@
+//! Input scripts, one timed step per line:
+//!
+//! ```text
+//! # time in ms, then the step
+//! 0     stick lx 2048
+//! 10    trigger l 4095
+//! 20    press front_r
+//! 24.5  release front_r
+//! 100   console set deadzone l radial 2000 1000 0
+//! 500   end
+//! ```
+//!
+//! Sticks and triggers take raw 12 bit ADC readings, buttons the names used by the serial
+//! console or `dpad_up`, `dpad_down`, `dpad_left` and `dpad_right`, and `console` any console
+//! command. Triggers are not wired until their first step. Steps must be in time order, `end`
+//! keeps the simulation running until its time when nothing else happens towards the end.
+
+use picotroller_core::controller::Control;
+use picotroller_core::platform::{StickAxis, Trigger};
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    Stick(StickAxis, u16),
+    Trigger(Trigger, u16),
+    /// A button or D-pad edge, pressed or released
+    Button(Control, bool),
+    Console(String),
+    End,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    /// Microseconds since the start of the simulation
+    pub time: u64,
+    pub action: Action,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScriptError {
+    /// Line number, from 1
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Step>, ScriptError> {
+    let mut steps: Vec<Step> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message| ScriptError {
+            line: index + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let step = parse_step(line).map_err(error)?;
+        if steps.last().is_some_and(|last| step.time < last.time) {
+            return Err(error("steps must be in time order"));
+        }
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn parse_step(line: &str) -> Result<Step, &'static str> {
+    let mut args = line.split_whitespace();
+    let time = args.next().and_then(parse_time).ok_or("invalid time")?;
+    let action = match args.next().ok_or("missing step")? {
+        "stick" => {
+            let axis = match args.next().ok_or("missing axis")? {
+                "lx" => StickAxis::LeftX,
+                "ly" => StickAxis::LeftY,
+                "rx" => StickAxis::RightX,
+                "ry" => StickAxis::RightY,
+                _ => return Err("axis must be lx, ly, rx or ry"),
+            };
+            Action::Stick(axis, parse_reading(args.next())?)
+        }
+        "trigger" => {
+            let trigger = match args.next().ok_or("missing trigger")? {
+                "l" => Trigger::Left,
+                "r" => Trigger::Right,
+                _ => return Err("trigger must be l or r"),
+            };
+            Action::Trigger(trigger, parse_reading(args.next())?)
+        }
+        step @ ("press" | "release") => {
+            let button = args
+                .next()
+                .and_then(Control::from_name)
+                .ok_or("unknown button")?;
+            Action::Button(button, step == "press")
+        }
+        "console" => {
+            // Everything after the step name, with its spacing kept
+            let (_, command) = line.split_once("console").unwrap_or_default();
+            Action::Console(command.trim().to_string())
+        }
+        "end" => Action::End,
+        _ => return Err("unknown step"),
+    };
+    if !matches!(action, Action::Console(_)) && args.next().is_some() {
+        return Err("too many arguments");
+    }
+    Ok(Step { time, action })
+}
+
+fn parse_reading(arg: Option<&str>) -> Result<u16, &'static str> {
+    arg.and_then(|value| value.parse().ok())
+        .filter(|value| *value <= 4095)
+        .ok_or("analog value must be between 0 and 4095")
+}
+
+/// Parses milliseconds with up to three decimals into microseconds.
+fn parse_time(text: &str) -> Option<u64> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let micros = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+    whole
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(1000)?
+        .checked_add(micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use picotroller_core::controller::Button;
+
+    #[test]
+    fn parses_steps() {
+        let steps = parse(
+            "# comment\n\
+             0 stick lx 2048\n\
+             10 trigger r 100\n\
+             \n\
+             20 press front_r # trailing comment\n\
+             24.5 release front_r\n\
+             100 console set deadzone l  radial 2000 1000 0\n\
+             500 end\n",
+        )
+        .unwrap();
+        let actions: Vec<_> = steps.iter().map(|step| (step.time, &step.action)).collect();
+        assert_eq!(
+            actions,
+            [
+                (0, &Action::Stick(StickAxis::LeftX, 2048)),
+                (10_000, &Action::Trigger(Trigger::Right, 100)),
+                (
+                    20_000,
+                    &Action::Button(Control::Button(Button::FrontR), true)
+                ),
+                (
+                    24_500,
+                    &Action::Button(Control::Button(Button::FrontR), false)
+                ),
+                (
+                    100_000,
+                    &Action::Console("set deadzone l  radial 2000 1000 0".to_string())
+                ),
+                (500_000, &Action::End),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("0"), Some(0));
+        assert_eq!(parse_time("12"), Some(12_000));
+        assert_eq!(parse_time("1.5"), Some(1_500));
+        assert_eq!(parse_time("1.005"), Some(1_005));
+        assert_eq!(parse_time("1.0005"), None);
+        assert_eq!(parse_time("-1"), None);
+        assert_eq!(parse_time("1.x"), None);
+        assert_eq!(parse_time("ms"), None);
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(error("0 end\n\n5 jump").line, 3);
+        assert_eq!(
+            error("0 stick lz 10").message,
+            "axis must be lx, ly, rx or ry"
+        );
+        assert_eq!(
+            error("0 stick lx 4096").message,
+            "analog value must be between 0 and 4095"
+        );
+        assert_eq!(error("0 trigger z 10").message, "trigger must be l or r");
+        assert_eq!(error("0 press turbo").message, "unknown button");
+        assert_eq!(error("0 end now").message, "too many arguments");
+        assert_eq!(
+            error("10 end\n5 end").message,
+            "steps must be in time order"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use crate::script::{Action, Step};
+use picotroller_core::console::{Console, Request};
+use picotroller_core::controller::{Button, Control, Controller};
+use picotroller_core::device::JoystickReport;
+use picotroller_core::input::{ButtonInput, Level, DEBOUNCE_STICK, DEBOUNCE_SWITCH};
+use picotroller_core::keyboard::Direction;
+use picotroller_core::pipeline::Pipeline;
+use picotroller_core::platform::{AnalogSource, ReportError, ReportSink, StickAxis, Trigger};
+use picotroller_core::settings::Settings;
+use std::io::{self, Write};
+
+/// Period of the report timer in the firmware.
+pub const TICK_US: u64 = 10_000;
+/// Time run past the last step, so debounced releases still show up.
+const SETTLE_US: u64 = 50_000;
+
+/// Stick and trigger readings as last set by the script, sticks centered and triggers not
+/// wired until then.
+struct Analog {
+    sticks: [u16; 4],
+    triggers: [Option<u16>; 2],
+}
+
+impl AnalogSource for Analog {
+    fn read(&mut self, axis: StickAxis) -> u16 {
+        self.sticks[axis as usize]
+    }
+
+    fn read_trigger(&mut self, trigger: Trigger) -> Option<u16> {
+        self.triggers[trigger as usize]
+    }
+}
+
+/// Collects the HID reports the firmware would send, which is only when they change.
+#[derive(Default)]
+struct JoystickStream {
+    time: u64,
+    last: JoystickReport,
+    reports: Vec<(u64, JoystickReport)>,
+}
+
+impl ReportSink for JoystickStream {
+    fn write_report(&mut self, controller: &Controller) -> Result<(), ReportError> {
+        let mut report = JoystickReport::default();
+        controller.hid_report(&mut report);
+        if report != self.last {
+            self.reports.push((self.time, report));
+            self.last = report;
+        }
+        Ok(())
+    }
+}
+
+/// The firmware pipeline with every pin, the ADC and the serial console replaced by the
+/// script.
+pub struct Simulator {
+    pipeline: Pipeline,
+    inputs: Vec<ButtonInput>,
+    analog: Analog,
+    console: Console,
+}
+
+impl Simulator {
+    pub fn new(settings: Settings) -> Self {
+        let inputs = Button::ALL
+            .into_iter()
+            .map(Control::Button)
+            .chain(Direction::ALL.into_iter().map(Control::Dpad))
+            .map(|control| {
+                let debouncer = match control {
+                    Control::Button(Button::ThumbL | Button::ThumbR) => DEBOUNCE_STICK,
+                    _ => DEBOUNCE_SWITCH,
+                };
+                ButtonInput::new(Level::High, control, debouncer)
+            })
+            .collect();
+        Self {
+            pipeline: Pipeline::new(settings),
+            inputs,
+            analog: Analog {
+                sticks: [2048; 4],
+                triggers: [None; 2],
+            },
+            console: Console::default(),
+        }
+    }
+
+    /// Runs the steps tick by tick, returning each report with the time it was sent. The
+    /// console output goes to `log`.
+    pub fn run(
+        &mut self,
+        steps: &[Step],
+        log: &mut impl Write,
+    ) -> io::Result<Vec<(u64, JoystickReport)>> {
+        let end = steps.last().map_or(0, |step| step.time) + SETTLE_US;
+        let mut stream = JoystickStream::default();
+        let mut steps = steps.iter().peekable();
+        let mut now = 0;
+        while now < end {
+            now += TICK_US;
+            while let Some(step) = steps.next_if(|step| step.time <= now) {
+                self.apply(step, log)?;
+            }
+            self.pipeline.read_buttons(&mut self.inputs, now);
+            self.pipeline.read_sticks(&mut self.analog);
+            stream.time = now;
+            self.pipeline.update(&mut stream);
+        }
+        Ok(stream.reports)
+    }
+
+    fn apply(&mut self, step: &Step, log: &mut impl Write) -> io::Result<()> {
+        match &step.action {
+            Action::Stick(axis, value) => self.analog.sticks[*axis as usize] = *value,
+            Action::Trigger(trigger, value) => {
+                self.analog.triggers[*trigger as usize] = Some(*value);
+            }
+            Action::Button(control, pressed) => {
+                if let Some(input) = self.inputs.iter_mut().find(|i| i.control == *control) {
+                    input.edge(*pressed, step.time);
+                }
+            }
+            Action::Console(command) => self.command(command, log)?,
+            Action::End => {}
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str, log: &mut impl Write) -> io::Result<()> {
+        let pipeline = &mut self.pipeline;
+        let line = format!("{}\r", command);
+        self.console.receive(
+            line.as_bytes(),
+            &mut pipeline.settings,
+            &mut pipeline.controller,
+        );
+        match self.console.take_request() {
+            Some(Request::Profile(index)) => {
+                pipeline.switch_profile(index);
+                self.console.print(pipeline.settings.profile().name());
+            }
+            Some(Request::MacroRecord(index)) => {
+                pipeline.record_macro(index);
+                self.console.print("recording, macro stop to end");
+            }
+            Some(Request::MacroPlay(index)) => {
+                let message = if pipeline.play_macro(index) {
+                    "playing"
+                } else {
+                    "nothing to play"
+                };
+                self.console.print(message);
+            }
+            Some(Request::MacroStop) => {
+                pipeline.stop_macro();
+                self.console.print("stopped");
+            }
+            Some(Request::MacroClear(index)) => {
+                pipeline.clear_macro(index);
+                self.console.print("cleared");
+            }
+            Some(Request::Defaults) => {
+                pipeline.set_settings(Settings::default());
+                self.console.print("defaults restored, save to keep them");
+            }
+            Some(Request::Save) | Some(Request::Load) => {
+                self.console.print("no flash in the simulator");
+            }
+            None => {}
+        }
+        let mut output: Vec<u8> = self
+            .console
+            .pending()
+            .iter()
+            .copied()
+            .filter(|&byte| byte != b'\r')
+            .collect();
+        self.console.consume(self.console.pending().len());
+        // Ends on the prompt, which would run into the next output
+        if output.last() != Some(&b'\n') {
+            output.push(b'\n');
+        }
+        log.write_all(&output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script;
+    use picotroller_core::controller::buttons;
+
+    fn run(script: &str) -> (Vec<(u64, JoystickReport)>, String) {
+        let steps = script::parse(script).unwrap();
+        let mut log = Vec::new();
+        let reports = Simulator::new(Settings::default())
+            .run(&steps, &mut log)
+            .unwrap();
+        (reports, String::from_utf8(log).unwrap())
+    }
+
+    #[test]
+    fn centered_sticks_send_nothing() {
+        let (reports, _) = run("100 end");
+        assert!(reports.is_empty());
+    }
+
+    #[test]
+    fn press_is_reported_on_the_next_tick() {
+        let (reports, _) = run("12 press front_r\n40 release front_r");
+        let times: Vec<_> = reports.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, [20_000, 40_000]);
+        assert_eq!(reports[0].1.buttons, buttons::BTN_SOUTH);
+        assert_eq!(reports[1].1.buttons, 0);
+    }
+
+    #[test]
+    fn bounces_are_filtered() {
+        let (reports, _) = run("10 press front_r\n10.5 release front_r\n11 press front_r\n\
+             60 release front_r");
+        let times: Vec<_> = reports.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, [10_000, 60_000]);
+        assert_eq!(reports[0].1.buttons, buttons::BTN_SOUTH);
+    }
+
+    #[test]
+    fn stick_moves_axis() {
+        let (reports, _) = run("10 stick ly 4095\n30 stick ly 2048");
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].1.ly, 127);
+        assert_eq!(reports[1].1, JoystickReport::default());
+    }
+
+    #[test]
+    fn console_changes_mapping() {
+        let (reports, log) = run("0 console map front_r east\n10 press front_r");
+        assert!(log.contains("map front_r east"));
+        assert_eq!(reports[0].1.buttons, buttons::BTN_EAST);
+    }
+
+    #[test]
+    fn console_records_and_plays_macro() {
+        let (reports, log) = run("0 console macro record 1\n\
+             20 press front_r\n\
+             50 release front_r\n\
+             100 console macro stop\n\
+             200 console macro play 1");
+        assert!(log.contains("recording, macro stop to end"));
+        assert!(log.contains("playing"));
+        let times: Vec<_> = reports.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, [20_000, 50_000, 200_000, 230_000]);
+        assert_eq!(reports[2].1.buttons, buttons::BTN_SOUTH);
+    }
+
+    #[test]
+    fn console_clears_playing_macro() {
+        for clear in ["macro clear 1", "defaults"] {
+            let script = format!(
+                "0 console macro record 1\n\
+                 20 press front_r\n\
+                 50 release front_r\n\
+                 100 console macro stop\n\
+                 110 console macro loop 1 on\n\
+                 120 console macro play 1\n\
+                 160 console {}\n\
+                 300 end",
+                clear
+            );
+            let (reports, log) = run(&script);
+            assert!(log.contains("playing"), "{}", clear);
+            let (time, last) = reports.last().unwrap();
+            assert!(*time <= 170_000, "{}", clear);
+            assert_eq!(last.buttons, 0, "{}", clear);
+        }
+    }
+
+    #[test]
+    fn trigger_moves_z_axis_and_presses_button() {
+        let (reports, _) = run("10 trigger l 1024\n30 trigger l 4095");
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].1.lz, reports[0].1.buttons), (32, 0));
+        assert_eq!(
+            (reports[1].1.lz, reports[1].1.buttons),
+            (127, buttons::BTN_TL2)
+        );
+    }
+
+    #[test]
+    fn dpad_reports_hat_with_socd_cleaning() {
+        let (reports, _) = run("10 press dpad_up\n\
+             30 press dpad_right\n\
+             50 press dpad_down\n\
+             70 console set socd up\n\
+             80 release dpad_right");
+        let hats: Vec<_> = reports.iter().map(|(_, report)| report.hat).collect();
+        // Up and down cancel out until up takes priority
+        assert_eq!(hats, [0, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn macro_replays_dpad() {
+        let (reports, _) = run("0 console macro record 1\n\
+             20 press dpad_down\n\
+             30 press dpad_right\n\
+             50 release dpad_down\n\
+             50 release dpad_right\n\
+             100 console macro stop\n\
+             200 console macro play 1");
+        let hats: Vec<_> = reports.iter().map(|(_, report)| report.hat).collect();
+        assert_eq!(hats[..3], hats[3..]);
+        assert_eq!(hats[..2], [4, 3]);
+    }
+}
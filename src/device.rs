@@ -1,100 +1,18 @@
-use usbd_human_interface_device::usb_class::prelude::*;
-use usbd_human_interface_device::UsbHidError;
 use core::default::Default;
+use defmt::{error, unwrap};
 use fugit::ExtU32;
-use usb_device::bus::UsbBus;
-use usb_device::class_prelude::UsbBusAllocator;
 use packed_struct::prelude::*;
-use defmt::{
-    error,
-    unwrap,
-    Format,
+use picotroller_core::device::{
+    JoystickHiResReport, JoystickOutputReport, JoystickReport, JOYSTICK_DESCRIPTOR,
+    JOYSTICK_HIRES_DESCRIPTOR, JOYSTICK_OUTPUT_SIZE,
 };
-
-#[rustfmt::skip]
-pub const JOYSTICK_DESCRIPTOR: &[u8] = &[
-    0x05, 0x01, // Usage Page (Generic Desktop)
-    0x09, 0x05, // Usage (Gamepad 0x05, Joystick 0x04)
-
-    0xA1, 0x01, // Collection (Application)
-        0x09, 0x01, //   Usage Page (Pointer)
-        0xA1, 0x00, //   Collection (Physical)
-            0x09, 0x30, //     Usage (X)
-            0x09, 0x31, //     Usage (Y)
-            0x09, 0x32, //     Usage (Z) Trigger (Not used)
-            0x09, 0x33, //     Usage (RX) - Second joystick
-            0x09, 0x34, //     Usage (RY) - Second joystick
-            0x09, 0x35, //     Usage (RZ) Trigger (Not used)
-            0x15, 0x81, //     Logical Minimum (-127)
-            0x25, 0x7f, //     Logical Maximum (127)
-            0x75, 0x08, //     Report Size
-            0x95, 0x06, //     Report count
-            0x81, 0x02, //     Input (Data, Variable, Absolute)
-        0xC0,       //   End Collection
-
-        0x05, 0x09, //   Usage Page (Button)
-        0x19, 0x01, //   Usage Minimum (0)
-        0x29, 0x10, //   Usage Maximum (16)
-        0x15, 0x00, //   Logical Minimum (0)
-        0x25, 0x01, //   Logical Maximum (1)
-        0x75, 0x01, //   Report Size (1)
-        0x95, 0x10, //   Report Count (16)
-        0x81, 0x02, //   Input (Data, Variable, Absolute)
-    0xC0,       // End Collection
-
-    /* TODO: 16 bit joy resolution
-    0x05, 0x01, // Usage Page (Generic Desktop)
-    0x09, 0x04, // Usage (Joystick)
-
-    0xA1, 0x01, // Collection (Application)
-    0x09, 0x01, // Usage Pointer
-        0xA1, 0x00, // Collection (Physical)
-            0x05, 0x09, // Usage Button 
-            0x19, 0x01, // Button 0
-            0x29, 0x08, // Button 8
-            0x15, 0x00, // Logical Min 
-            0x25, 0x01, // Logical Max
-            0x95, 0x08, // Report Count
-            0x75, 0x01, // Report Size in bits
-            0x81, 0x02, // Input 
-
-            0x05, 0x01, // Usage Page
-            0x09, 0x30, //     Usage (X)
-            0x09, 0x31, //     Usage (Y)
-            0x09, 0x32, //     Usage (X) - Second joystick
-            0x09, 0x33, //     Usage (Y) - Second joystick
-            0x16, 0x80, 0x01, //     Logical Minimum (-32768)
-            0x26, 0x7F, 0xFF, //     Logical Maximum (32767)
-            0x75, 0x10, //     Report Size (16)
-            0x95, 0x04, //     Report count (4)
-            0x81, 0x02, //     Input (Data, Variable, Absolute)
-        0xC0,       //   End Collection Physical,
-    0xC0,       //   End Collection Application,
-    */
-];
-
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PackedStruct)]
-#[derive(Format)]
-#[packed_struct(endian = "lsb", size_bytes = "8")]
-pub struct JoystickReport {
-    #[packed_field]
-    pub ly: i8,
-    #[packed_field]
-    pub lx: i8,
-    #[packed_field]
-    pub lz: i8,
-    #[packed_field]
-    pub ry: i8,
-    #[packed_field]
-    pub rx: i8,
-    #[packed_field]
-    pub rz: i8,
-    #[packed_field]
-    pub buttons: u16,
-}
+use usb_device::bus::UsbBus;
+use usb_device::class_prelude::UsbBusAllocator;
+use usbd_human_interface_device::usb_class::prelude::*;
+use usbd_human_interface_device::UsbHidError;
 
 pub struct Joystick<'a, B: UsbBus> {
-    interface: Interface<'a, B, InBytes8, OutNone, ReportSingle>,
+    interface: Interface<'a, B, InBytes16, OutBytes8, ReportSingle>,
 }
 
 impl<'a, B: UsbBus> Joystick<'a, B> {
@@ -108,10 +26,15 @@ impl<'a, B: UsbBus> Joystick<'a, B> {
             .map(|_| ())
             .map_err(UsbHidError::from)
     }
+
+    /// Takes the latest output report received from the host.
+    pub fn read_output(&mut self) -> Option<JoystickOutputReport> {
+        read_output(&mut self.interface)
+    }
 }
 
 impl<'a, B: UsbBus> DeviceClass<'a> for Joystick<'a, B> {
-    type I = Interface<'a, B, InBytes8, OutNone, ReportSingle>;
+    type I = Interface<'a, B, InBytes16, OutBytes8, ReportSingle>;
 
     fn interface(&mut self) -> &mut Self::I {
         &mut self.interface
@@ -125,18 +48,18 @@ impl<'a, B: UsbBus> DeviceClass<'a> for Joystick<'a, B> {
 }
 
 pub struct JoystickConfig<'a> {
-    interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>,
+    interface: InterfaceConfig<'a, InBytes16, OutBytes8, ReportSingle>,
 }
 
 impl<'a> Default for JoystickConfig<'a> {
     #[must_use]
     fn default() -> Self {
         Self::new(
-            unwrap!(unwrap!(InterfaceBuilder::new(JOYSTICK_DESCRIPTOR))
+            unwrap!(unwrap!(unwrap!(InterfaceBuilder::new(JOYSTICK_DESCRIPTOR))
                 .boot_device(InterfaceProtocol::None)
                 .description("Joystick")
                 .in_endpoint(10.millis()))
-            .without_out_endpoint()
+            .with_out_endpoint(10.millis()))
             .build(),
         )
     }
@@ -144,7 +67,7 @@ impl<'a> Default for JoystickConfig<'a> {
 
 impl<'a> JoystickConfig<'a> {
     #[must_use]
-    pub fn new(interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>) -> Self {
+    pub fn new(interface: InterfaceConfig<'a, InBytes16, OutBytes8, ReportSingle>) -> Self {
         Self { interface }
     }
 }
@@ -159,3 +82,85 @@ impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for JoystickConfig<'a> {
     }
 }
 
+pub struct JoystickHiRes<'a, B: UsbBus> {
+    interface: Interface<'a, B, InBytes16, OutBytes8, ReportSingle>,
+}
+
+impl<'a, B: UsbBus> JoystickHiRes<'a, B> {
+    pub fn write_report(&mut self, report: &JoystickHiResReport) -> Result<(), UsbHidError> {
+        let data = report.pack().map_err(|_| {
+            error!("Error packing JoystickHiResReport");
+            UsbHidError::SerializationError
+        })?;
+        self.interface
+            .write_report(&data)
+            .map(|_| ())
+            .map_err(UsbHidError::from)
+    }
+
+    /// Takes the latest output report received from the host.
+    pub fn read_output(&mut self) -> Option<JoystickOutputReport> {
+        read_output(&mut self.interface)
+    }
+}
+
+impl<'a, B: UsbBus> DeviceClass<'a> for JoystickHiRes<'a, B> {
+    type I = Interface<'a, B, InBytes16, OutBytes8, ReportSingle>;
+
+    fn interface(&mut self) -> &mut Self::I {
+        &mut self.interface
+    }
+
+    fn reset(&mut self) {}
+
+    fn tick(&mut self) -> Result<(), UsbHidError> {
+        Ok(())
+    }
+}
+
+pub struct JoystickHiResConfig<'a> {
+    interface: InterfaceConfig<'a, InBytes16, OutBytes8, ReportSingle>,
+}
+
+impl<'a> Default for JoystickHiResConfig<'a> {
+    #[must_use]
+    fn default() -> Self {
+        Self::new(
+            unwrap!(
+                unwrap!(unwrap!(InterfaceBuilder::new(JOYSTICK_HIRES_DESCRIPTOR))
+                    .boot_device(InterfaceProtocol::None)
+                    .description("Joystick")
+                    .in_endpoint(10.millis()))
+                .with_out_endpoint(10.millis())
+            )
+            .build(),
+        )
+    }
+}
+
+impl<'a> JoystickHiResConfig<'a> {
+    #[must_use]
+    pub fn new(interface: InterfaceConfig<'a, InBytes16, OutBytes8, ReportSingle>) -> Self {
+        Self { interface }
+    }
+}
+
+impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for JoystickHiResConfig<'a> {
+    type Allocated = JoystickHiRes<'a, B>;
+
+    fn allocate(self, usb_alloc: &'a UsbBusAllocator<B>) -> Self::Allocated {
+        Self::Allocated {
+            interface: Interface::new(usb_alloc, self.interface),
+        }
+    }
+}
+
+fn read_output<B: UsbBus>(
+    interface: &mut Interface<'_, B, InBytes16, OutBytes8, ReportSingle>,
+) -> Option<JoystickOutputReport> {
+    let mut data = [0u8; JOYSTICK_OUTPUT_SIZE];
+    match interface.read_report(&mut data) {
+        Ok(JOYSTICK_OUTPUT_SIZE) => JoystickOutputReport::unpack(&data).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,160 @@
+use core::default::Default;
+use defmt::debug;
+use picotroller_core::ds4::{
+    calibration_report, firmware_report, pairing_report, parse_output, Ds4Report, DS4_DESCRIPTOR,
+    DS4_REPORT_SIZE, REPORT_ID_CALIBRATION, REPORT_ID_FIRMWARE, REPORT_ID_INPUT, REPORT_ID_PAIRING,
+};
+use picotroller_core::platform::HostCommand;
+use usb_device::class_prelude::*;
+use usbd_human_interface_device::UsbHidError;
+
+const HID_INTERFACE_CLASS: u8 = 0x03;
+const HID_DESCRIPTOR_TYPE: u8 = 0x21;
+const HID_REPORT_DESCRIPTOR_TYPE: u8 = 0x22;
+
+const HID_GET_REPORT: u8 = 0x01;
+const HID_SET_IDLE: u8 = 0x0a;
+const HID_SET_REPORT: u8 = 0x09;
+const HID_REPORT_TYPE_INPUT: u8 = 0x01;
+const HID_REPORT_TYPE_OUTPUT: u8 = 0x02;
+const HID_REPORT_TYPE_FEATURE: u8 = 0x03;
+
+const DS4_PACKET_SIZE: u16 = 64;
+
+/// Sony DualShock 4, written against `usb_device` directly as the HID class does not
+/// answer feature report requests.
+pub struct Ds4<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    counter: u8,
+    last_report: [u8; DS4_REPORT_SIZE],
+    output: Option<HostCommand>,
+}
+
+impl<'a, B: UsbBus> Ds4<'a, B> {
+    pub fn new(usb_alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            interface: usb_alloc.interface(),
+            ep_in: usb_alloc.interrupt(DS4_PACKET_SIZE, 5),
+            ep_out: usb_alloc.interrupt(DS4_PACKET_SIZE, 5),
+            counter: 0,
+            last_report: Ds4Report::default().pack(0),
+            output: None,
+        }
+    }
+
+    pub fn write_report(&mut self, report: &Ds4Report) -> Result<(), UsbHidError> {
+        let data = report.pack(self.counter);
+        self.ep_in.write(&data).map_err(UsbHidError::from)?;
+        self.counter = (self.counter + 1) & 0x3f;
+        self.last_report = data;
+        Ok(())
+    }
+
+    /// Takes the latest rumble and light bar command received from the host.
+    pub fn take_output(&mut self) -> Option<HostCommand> {
+        self.output.take()
+    }
+
+    fn receive_output(&mut self, data: &[u8]) {
+        if let Some(output) = parse_output(data) {
+            debug!("DS4 output {}", output);
+            self.output = Some(output);
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for Ds4<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.interface, HID_INTERFACE_CLASS, 0, 0)?;
+        let [length_low, length_high] = (DS4_DESCRIPTOR.len() as u16).to_le_bytes();
+        writer.write(
+            HID_DESCRIPTOR_TYPE,
+            &[
+                0x11,
+                0x01, // HID 1.11
+                0x00, // Country code
+                0x01, // Number of report descriptors
+                HID_REPORT_DESCRIPTOR_TYPE,
+                length_low,
+                length_high,
+            ],
+        )?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.counter = 0;
+        self.output = None;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let request = *xfer.request();
+        if request.recipient != control::Recipient::Interface
+            || request.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+        let [report_id, report_type] = request.value.to_le_bytes();
+        match (request.request_type, request.request) {
+            (control::RequestType::Standard, control::Request::GET_DESCRIPTOR)
+                if report_type == HID_REPORT_DESCRIPTOR_TYPE =>
+            {
+                xfer.accept_with_static(DS4_DESCRIPTOR).ok();
+            }
+            (control::RequestType::Class, HID_GET_REPORT) => {
+                debug!("DS4 get report {:x} {:x}", report_type, report_id);
+                let result = match (report_type, report_id) {
+                    (HID_REPORT_TYPE_INPUT, REPORT_ID_INPUT) => xfer.accept_with(&self.last_report),
+                    (HID_REPORT_TYPE_FEATURE, REPORT_ID_CALIBRATION) => {
+                        xfer.accept_with(&calibration_report())
+                    }
+                    (HID_REPORT_TYPE_FEATURE, REPORT_ID_PAIRING) => {
+                        xfer.accept_with(&pairing_report())
+                    }
+                    (HID_REPORT_TYPE_FEATURE, REPORT_ID_FIRMWARE) => {
+                        xfer.accept_with(&firmware_report())
+                    }
+                    _ => xfer.reject(),
+                };
+                result.ok();
+            }
+            _ => {}
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let request = *xfer.request();
+        if request.request_type != control::RequestType::Class
+            || request.recipient != control::Recipient::Interface
+            || request.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+        // Some hosts send the output report as a control transfer rather than on the endpoint
+        let [_, report_type] = request.value.to_le_bytes();
+        if request.request == HID_SET_REPORT && report_type == HID_REPORT_TYPE_OUTPUT {
+            self.receive_output(xfer.data());
+        }
+        match request.request {
+            HID_SET_IDLE | HID_SET_REPORT => xfer.accept().ok(),
+            _ => xfer.reject().ok(),
+        };
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut data = [0u8; DS4_PACKET_SIZE as usize];
+        if let Ok(size) = self.ep_out.read(&mut data) {
+            self.receive_output(&data[..size]);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use picotroller_core::store::{Flash, FLASH_SECTOR_SIZE, STORE_SECTORS};
+
+const FLASH_SIZE: u32 = 2048 * 1024;
+const XIP_BASE: u32 = 0x1000_0000;
+const STORE_OFFSET: u32 = FLASH_SIZE - STORE_SECTORS * FLASH_SECTOR_SIZE;
+
+/// The RP2040 on-board flash, read through XIP. Code runs from flash too, so writes happen in
+/// a critical section where nothing else may execute from it.
+pub struct OnboardFlash;
+
+impl Flash for OnboardFlash {
+    fn read(&self, offset: u32, data: &mut [u8]) {
+        let address = (XIP_BASE + STORE_OFFSET + offset) as *const u8;
+        let stored = unsafe { core::slice::from_raw_parts(address, data.len()) };
+        data.copy_from_slice(stored);
+    }
+
+    fn erase_sector(&mut self, offset: u32) {
+        critical_section::with(|_| unsafe {
+            rp2040_flash::flash::flash_range_erase(STORE_OFFSET + offset, FLASH_SECTOR_SIZE, true);
+        });
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        critical_section::with(|_| unsafe {
+            rp2040_flash::flash::flash_range_program(STORE_OFFSET + offset, data, true);
+        });
+    }
+}
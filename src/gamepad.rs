@@ -0,0 +1,287 @@
+use crate::device::{Joystick, JoystickConfig, JoystickHiRes, JoystickHiResConfig};
+use crate::ds4::Ds4;
+use crate::switch::{SwitchPad, SwitchPadConfig};
+use crate::xinput::XInput;
+use frunk::HList;
+use picotroller_core::controller::Controller;
+use picotroller_core::device::{JoystickHiResReport, JoystickReport, ReportResolution};
+use picotroller_core::ds4::Ds4Report;
+use picotroller_core::keyboard::{KeyboardReport, MouseMotion};
+use picotroller_core::mode::UsbMode;
+use picotroller_core::platform::{HostCommand, ReportError, ReportSink};
+use picotroller_core::switch::SwitchReport;
+use picotroller_core::xinput::XInputReport;
+use usb_device::bus::UsbBus;
+use usb_device::class::UsbClass;
+use usb_device::class_prelude::UsbBusAllocator;
+use usb_device::prelude::UsbDeviceBuilder;
+use usbd_human_interface_device::device::keyboard::{BootKeyboard, BootKeyboardConfig};
+use usbd_human_interface_device::device::mouse::{WheelMouse, WheelMouseConfig, WheelMouseReport};
+use usbd_human_interface_device::page::Keyboard;
+use usbd_human_interface_device::usb_class::{UsbHidClass, UsbHidClassBuilder};
+use usbd_human_interface_device::UsbHidError;
+
+/// Sets the device descriptor fields the host uses to pick a driver for `mode`.
+pub fn configure<'a, B: UsbBus>(
+    mode: UsbMode,
+    builder: UsbDeviceBuilder<'a, B>,
+) -> UsbDeviceBuilder<'a, B> {
+    match mode {
+        // HID and CDC interfaces, the IADs group the two CDC interfaces
+        UsbMode::Hid | UsbMode::Keyboard | UsbMode::KeyboardJoystick => {
+            builder.composite_with_iads()
+        }
+        UsbMode::XInput => builder
+            .device_class(0xff)
+            .device_sub_class(0xff)
+            .device_protocol(0xff)
+            .device_release(0x0114),
+        UsbMode::Switch | UsbMode::Ds4 => builder.device_release(0x0100),
+    }
+}
+
+/// The USB class the controller state is reported through, picked once at boot.
+pub enum Gamepad<'a, B: UsbBus> {
+    Joystick {
+        hid: UsbHidClass<B, HList!(Joystick<'a, B>)>,
+        last_report: JoystickReport,
+    },
+    JoystickHiRes {
+        hid: UsbHidClass<B, HList!(JoystickHiRes<'a, B>)>,
+        last_report: JoystickHiResReport,
+    },
+    XInput {
+        xinput: XInput<'a, B>,
+        last_report: XInputReport,
+    },
+    Switch {
+        hid: UsbHidClass<B, HList!(SwitchPad<'a, B>)>,
+        last_report: SwitchReport,
+    },
+    Ds4 {
+        ds4: Ds4<'a, B>,
+        last_report: Ds4Report,
+    },
+    KeyboardMouse {
+        hid: UsbHidClass<B, HList!(WheelMouse<'a, B>, BootKeyboard<'a, B>)>,
+        last_report: KeyboardReport,
+        motion: MouseMotion,
+    },
+    /// Always reports 8 bit axes, the resolution only applies to `UsbMode::Hid`.
+    KeyboardMouseJoystick {
+        hid: UsbHidClass<B, HList!(WheelMouse<'a, B>, BootKeyboard<'a, B>, Joystick<'a, B>)>,
+        last_report: KeyboardReport,
+        last_joystick: JoystickReport,
+        motion: MouseMotion,
+    },
+}
+
+impl<'a, B: UsbBus> Gamepad<'a, B> {
+    pub fn new(
+        usb_bus: &'a UsbBusAllocator<B>,
+        mode: UsbMode,
+        resolution: ReportResolution,
+    ) -> Self {
+        match (mode, resolution) {
+            (UsbMode::XInput, _) => Self::XInput {
+                xinput: XInput::new(usb_bus),
+                last_report: XInputReport::default(),
+            },
+            (UsbMode::Switch, _) => Self::Switch {
+                hid: UsbHidClassBuilder::new()
+                    .add_device(SwitchPadConfig::default())
+                    .build(usb_bus),
+                last_report: SwitchReport::default(),
+            },
+            (UsbMode::Ds4, _) => Self::Ds4 {
+                ds4: Ds4::new(usb_bus),
+                last_report: Ds4Report::default(),
+            },
+            (UsbMode::Keyboard, _) => Self::KeyboardMouse {
+                hid: UsbHidClassBuilder::new()
+                    .add_device(BootKeyboardConfig::default())
+                    .add_device(WheelMouseConfig::default())
+                    .build(usb_bus),
+                last_report: KeyboardReport::default(),
+                motion: MouseMotion::default(),
+            },
+            (UsbMode::KeyboardJoystick, _) => Self::KeyboardMouseJoystick {
+                hid: UsbHidClassBuilder::new()
+                    .add_device(JoystickConfig::default())
+                    .add_device(BootKeyboardConfig::default())
+                    .add_device(WheelMouseConfig::default())
+                    .build(usb_bus),
+                last_report: KeyboardReport::default(),
+                last_joystick: JoystickReport::default(),
+                motion: MouseMotion::default(),
+            },
+            (UsbMode::Hid, ReportResolution::Low) => Self::Joystick {
+                hid: UsbHidClassBuilder::new()
+                    .add_device(JoystickConfig::default())
+                    .build(usb_bus),
+                last_report: JoystickReport::default(),
+            },
+            (UsbMode::Hid, ReportResolution::High) => Self::JoystickHiRes {
+                hid: UsbHidClassBuilder::new()
+                    .add_device(JoystickHiResConfig::default())
+                    .build(usb_bus),
+                last_report: JoystickHiResReport::default(),
+            },
+        }
+    }
+
+    pub fn class(&mut self) -> &mut dyn UsbClass<B> {
+        match self {
+            Self::Joystick { hid, .. } => hid,
+            Self::JoystickHiRes { hid, .. } => hid,
+            Self::XInput { xinput, .. } => xinput,
+            Self::Switch { hid, .. } => hid,
+            Self::Ds4 { ds4, .. } => ds4,
+            Self::KeyboardMouse { hid, .. } => hid,
+            Self::KeyboardMouseJoystick { hid, .. } => hid,
+        }
+    }
+
+    /// Takes the latest rumble and LED command received from the host, the Switch and the
+    /// keyboard and mouse have no way to send one.
+    pub fn take_command(&mut self) -> Option<HostCommand> {
+        match self {
+            Self::Joystick { hid, .. } => hid.device().read_output().map(HostCommand::from),
+            Self::JoystickHiRes { hid, .. } => hid.device().read_output().map(HostCommand::from),
+            Self::XInput { xinput, .. } => xinput.take_output().map(HostCommand::from),
+            Self::Ds4 { ds4, .. } => ds4.take_output(),
+            Self::KeyboardMouseJoystick { hid, .. } => hid
+                .device::<Joystick<'a, B>, _>()
+                .read_output()
+                .map(HostCommand::from),
+            Self::Switch { .. } | Self::KeyboardMouse { .. } => None,
+        }
+    }
+
+    /// Sends the controller state to the host, unless it is unchanged since the last report.
+    fn write_hid_report(&mut self, controller: &Controller) -> Result<(), UsbHidError> {
+        match self {
+            Self::Joystick { hid, last_report } => {
+                let mut report = JoystickReport::default();
+                controller.hid_report(&mut report);
+                if report != *last_report {
+                    hid.device().write_report(&report)?;
+                    *last_report = report;
+                }
+            }
+            Self::JoystickHiRes { hid, last_report } => {
+                let mut report = JoystickHiResReport::default();
+                controller.hid_report_hires(&mut report);
+                if report != *last_report {
+                    hid.device().write_report(&report)?;
+                    *last_report = report;
+                }
+            }
+            Self::XInput {
+                xinput,
+                last_report,
+            } => {
+                let mut report = XInputReport::default();
+                controller.xinput_report(&mut report);
+                if report != *last_report {
+                    xinput.write_report(&report)?;
+                    *last_report = report;
+                }
+            }
+            Self::Switch { hid, last_report } => {
+                let mut report = SwitchReport::default();
+                controller.switch_report(&mut report);
+                if report != *last_report {
+                    hid.device().write_report(&report)?;
+                    *last_report = report;
+                }
+            }
+            Self::Ds4 { ds4, last_report } => {
+                let mut report = Ds4Report::default();
+                controller.ds4_report(&mut report);
+                if report != *last_report {
+                    ds4.write_report(&report)?;
+                    *last_report = report;
+                }
+            }
+            Self::KeyboardMouse {
+                hid,
+                last_report,
+                motion,
+            } => {
+                // Repeats the keyboard report at the idle rate set by the host
+                hid.tick()?;
+                let mut report = KeyboardReport::default();
+                controller.keyboard_report(&mut report);
+                write_keys(hid.device(), &report, last_report)?;
+                write_mouse(hid.device(), &report, last_report, motion)?;
+                *last_report = report;
+            }
+            Self::KeyboardMouseJoystick {
+                hid,
+                last_report,
+                last_joystick,
+                motion,
+            } => {
+                hid.tick()?;
+                let mut joystick = JoystickReport::default();
+                controller.hid_report(&mut joystick);
+                if joystick != *last_joystick {
+                    hid.device::<Joystick<'a, B>, _>().write_report(&joystick)?;
+                    *last_joystick = joystick;
+                }
+                let mut report = KeyboardReport::default();
+                controller.keyboard_report(&mut report);
+                write_keys(hid.device(), &report, last_report)?;
+                write_mouse(hid.device(), &report, last_report, motion)?;
+                *last_report = report;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<B: UsbBus> ReportSink for Gamepad<'_, B> {
+    fn write_report(&mut self, controller: &Controller) -> Result<(), ReportError> {
+        self.write_hid_report(controller)
+            .map_err(|error| match error {
+                UsbHidError::WouldBlock => ReportError::WouldBlock,
+                _ => ReportError::Failed,
+            })
+    }
+}
+
+/// Sends the held keys, unless they are unchanged since the last report.
+fn write_keys<B: UsbBus>(
+    keyboard: &mut BootKeyboard<'_, B>,
+    report: &KeyboardReport,
+    last_report: &KeyboardReport,
+) -> Result<(), UsbHidError> {
+    if report.keys == last_report.keys {
+        return Ok(());
+    }
+    // Different inputs can hold the same key, which leaves the boot report unchanged
+    match keyboard.write_report(report.pressed_keys().map(Keyboard::from)) {
+        Err(UsbHidError::Duplicate) => Ok(()),
+        result => result,
+    }
+}
+
+/// Sends the mouse buttons and movement, while the mouse moves or its buttons change.
+fn write_mouse<B: UsbBus>(
+    mouse: &mut WheelMouse<'_, B>,
+    report: &KeyboardReport,
+    last_report: &KeyboardReport,
+    motion: &mut MouseMotion,
+) -> Result<(), UsbHidError> {
+    let (x, y) = motion.step(report.mouse);
+    if x == 0 && y == 0 && report.mouse_buttons == last_report.mouse_buttons {
+        return Ok(());
+    }
+    mouse.write_report(&WheelMouseReport {
+        buttons: report.mouse_buttons,
+        x,
+        y,
+        ..WheelMouseReport::default()
+    })
+}
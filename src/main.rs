@@ -7,62 +7,146 @@ use core::cell::RefCell;
 
 use bsp::hal;
 use bsp::{entry, Pins};
-use cortex_m::prelude::{_embedded_hal_adc_OneShot, _embedded_hal_timer_CountDown};
+use cortex_m::prelude::_embedded_hal_timer_CountDown;
 use critical_section::Mutex;
+use defmt::{info, warn};
 use fugit::ExtU32;
+#[cfg(any(feature = "ads1115", feature = "mcp3208", feature = "mcp23017"))]
+use fugit::RateExtU32;
 use hal::{
-    clocks::init_clocks_and_plls, clocks::Clock, gpio, gpio::Interrupt, pac, pac::interrupt,
-    pio::PIOExt, timer::Timer, watchdog::Watchdog, Sio,
+    clocks::init_clocks_and_plls, clocks::Clock, pac, pac::interrupt, pio::PIOExt, timer::Timer,
+    watchdog::Watchdog, Sio,
 };
+#[cfg(feature = "key-matrix")]
+use hal::gpio::dynpin::DynPin;
 use panic_halt as _;
 use smart_leds::colors;
 use smart_leds::{brightness, SmartLedsWrite};
 use usb_device::class_prelude::UsbBusAllocator;
+use usb_device::device::UsbDeviceState;
 use usb_device::prelude::{UsbDeviceBuilder, UsbVidPid};
-use usbd_human_interface_device::usb_class::UsbHidClassBuilder;
-use usbd_human_interface_device::UsbHidError;
+use usbd_serial::SerialPort;
 use waveshare_rp2040_zero as bsp;
 use ws2812_pio::Ws2812;
 
-mod controller;
-use controller::*;
-
 mod device;
-use device::JoystickReport;
 
-const USB_VENDOR: u16 = 0x045e;
-const USB_PRODUCT: u16 = 0x028e;
+mod ds4;
+
+mod flash;
+use flash::OnboardFlash;
+
+mod gamepad;
+use gamepad::Gamepad;
+
+mod pinmap;
+use pinmap::{ButtonPin, Pull};
+
+#[cfg(feature = "rumble")]
+mod rumble;
+#[cfg(feature = "rumble")]
+use rumble::RumbleMotors;
+
+mod status;
+use status::StatusLed;
+
+mod sticks;
+use sticks::AdcSticks;
+
+mod switch;
+
+mod xinput;
+
+use picotroller_core::console::{Console, Request};
+use picotroller_core::controller::Button;
+#[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+use picotroller_core::controller::Control;
+use picotroller_core::device::ReportResolution;
+#[cfg(any(feature = "ads1115", feature = "mcp3208"))]
+use picotroller_core::external_adc::{AnalogInput, ExternalAnalog, MAX_CHANNELS};
+#[cfg(feature = "ads1115")]
+use picotroller_core::external_adc::{Ads1115, ADS1115_ADDRESS};
+#[cfg(feature = "mcp3208")]
+use picotroller_core::external_adc::Mcp3208;
+use picotroller_core::input::{ButtonSet, Level, DEBOUNCE_STICK, DEBOUNCE_SWITCH};
+#[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+use picotroller_core::keyboard::Direction;
+use picotroller_core::mode::UsbMode;
+use picotroller_core::pipeline::{Event, Pipeline};
+#[cfg(any(feature = "ads1115", feature = "mcp3208"))]
+use picotroller_core::platform::Trigger;
+#[cfg(feature = "rumble")]
+use picotroller_core::platform::Rumble;
+use picotroller_core::platform::StatusIndicator;
+#[cfg(feature = "key-matrix")]
+use picotroller_core::scanner::KeyMatrix;
+#[cfg(feature = "mcp23017")]
+use picotroller_core::scanner::{Mcp23017, MCP23017_ADDRESS};
+#[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+use picotroller_core::scanner::{ScannedButton, ScannedButtons};
+#[cfg(feature = "hc165")]
+use picotroller_core::scanner::ShiftRegisters;
+use picotroller_core::settings::Settings;
+
 const USB_MANUFACTURER: &'static str = "Nameless";
 const USB_PRODUCT_NAME: &'static str = "Picotroller";
 const USB_SERIALNUM: &'static str = "CTLPICO";
 
-type ButtonPinThumbL = gpio::Pin<gpio::bank0::Gpio14, gpio::PullUpInput>;
-type ButtonPinThumbR = gpio::Pin<gpio::bank0::Gpio8, gpio::PullUpInput>;
-type ButtonPinUnderL = gpio::Pin<gpio::bank0::Gpio13, gpio::PullDownInput>;
-type ButtonPinUnderR = gpio::Pin<gpio::bank0::Gpio9, gpio::PullDownInput>;
-type ButtonPinFrontL = gpio::Pin<gpio::bank0::Gpio10, gpio::PullDownInput>;
-type ButtonPinFrontR = gpio::Pin<gpio::bank0::Gpio11, gpio::PullDownInput>;
-type ButtonPinStart = gpio::Pin<gpio::bank0::Gpio12, gpio::PullDownInput>;
-type ButtonPinSelect = gpio::Pin<gpio::bank0::Gpio7, gpio::PullDownInput>;
-
-static BUTTON_PIN_THUMB_L: Mutex<RefCell<Option<ButtonPinThumbL>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_THUMB_R: Mutex<RefCell<Option<ButtonPinThumbR>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_UNDER_L: Mutex<RefCell<Option<ButtonPinUnderL>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_UNDER_R: Mutex<RefCell<Option<ButtonPinUnderR>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_FRONT_L: Mutex<RefCell<Option<ButtonPinFrontL>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_FRONT_R: Mutex<RefCell<Option<ButtonPinFrontR>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_START: Mutex<RefCell<Option<ButtonPinStart>>> = Mutex::new(RefCell::new(None));
-static BUTTON_PIN_SELECT: Mutex<RefCell<Option<ButtonPinSelect>>> = Mutex::new(RefCell::new(None));
-
-// state
-static BUTTON_THUMB_L: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_THUMB_R: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_UNDER_L: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_UNDER_R: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_FRONT_L: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_FRONT_R: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_START: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
-static BUTTON_SELECT: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
+const BUTTON_PIN_COUNT: usize = 8;
+
+#[cfg(all(feature = "ads1115", feature = "mcp3208"))]
+compile_error!("only one external ADC can be enabled");
+
+/// What each channel of the external ADC is wired to, the other inputs stay on the ADC pins.
+#[cfg(any(feature = "ads1115", feature = "mcp3208"))]
+const EXTERNAL_ANALOG: [Option<AnalogInput>; MAX_CHANNELS] = [
+    Some(AnalogInput::Trigger(Trigger::Left)),
+    Some(AnalogInput::Trigger(Trigger::Right)),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+#[cfg(any(
+    all(feature = "hc165", feature = "mcp23017"),
+    all(feature = "hc165", feature = "key-matrix"),
+    all(feature = "mcp23017", feature = "key-matrix"),
+))]
+compile_error!("only one switch scanner can be enabled");
+#[cfg(any(
+    all(feature = "key-matrix", any(feature = "ads1115", feature = "mcp3208")),
+    all(feature = "hc165", any(feature = "ads1115", feature = "mcp3208")),
+    all(feature = "mcp23017", feature = "mcp3208"),
+))]
+compile_error!("the switch scanner and the external ADC share pins");
+#[cfg(all(feature = "rumble", any(feature = "key-matrix", feature = "mcp3208")))]
+compile_error!("the rumble motors share pins with the key matrix and the MCP3208");
+
+/// Key map of the scanned switches, by switch number, merged with the pin map. Each button
+/// can be wired to a switch, a pin or both, e.g. to move them all off the GPIOs. The D-pad
+/// only has switches, there are no pins left for it.
+#[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+#[rustfmt::skip]
+const KEY_MAP: [ScannedButton; 12] = [
+    ScannedButton::new(0, Level::Low, Control::Button(Button::FrontR), DEBOUNCE_SWITCH),
+    ScannedButton::new(1, Level::Low, Control::Button(Button::FrontL), DEBOUNCE_SWITCH),
+    ScannedButton::new(2, Level::Low, Control::Button(Button::UnderR), DEBOUNCE_SWITCH),
+    ScannedButton::new(3, Level::Low, Control::Button(Button::UnderL), DEBOUNCE_SWITCH),
+    ScannedButton::new(4, Level::Low, Control::Button(Button::Start), DEBOUNCE_SWITCH),
+    ScannedButton::new(5, Level::Low, Control::Button(Button::Select), DEBOUNCE_SWITCH),
+    ScannedButton::new(6, Level::Low, Control::Button(Button::ThumbL), DEBOUNCE_SWITCH),
+    ScannedButton::new(7, Level::Low, Control::Button(Button::ThumbR), DEBOUNCE_SWITCH),
+    ScannedButton::new(8, Level::Low, Control::Dpad(Direction::Up), DEBOUNCE_SWITCH),
+    ScannedButton::new(9, Level::Low, Control::Dpad(Direction::Down), DEBOUNCE_SWITCH),
+    ScannedButton::new(10, Level::Low, Control::Dpad(Direction::Left), DEBOUNCE_SWITCH),
+    ScannedButton::new(11, Level::Low, Control::Dpad(Direction::Right), DEBOUNCE_SWITCH),
+];
+
+static BUTTON_PINS: Mutex<RefCell<Option<[ButtonPin; BUTTON_PIN_COUNT]>>> =
+    Mutex::new(RefCell::new(None));
 
 #[entry]
 fn main() -> ! {
@@ -102,6 +186,111 @@ fn main() -> ! {
 
     // START SETUP
 
+    let mut flash = OnboardFlash;
+    let mut settings = Settings::load(&flash);
+
+    // Pin map of every button, setup, interrupts and reading the state all follow from it
+    #[rustfmt::skip]
+    let button_pins: [ButtonPin; BUTTON_PIN_COUNT] = [
+        ButtonPin::new(pins.gp14.into(), Pull::Up, Level::Low, Button::ThumbL, DEBOUNCE_STICK),
+        ButtonPin::new(pins.gp8.into(), Pull::Up, Level::Low, Button::ThumbR, DEBOUNCE_STICK),
+        ButtonPin::new(pins.gp13.into(), Pull::Down, Level::High, Button::UnderL, DEBOUNCE_SWITCH),
+        ButtonPin::new(pins.gp9.into(), Pull::Down, Level::High, Button::UnderR, DEBOUNCE_SWITCH),
+        ButtonPin::new(pins.gp10.into(), Pull::Down, Level::High, Button::FrontL, DEBOUNCE_SWITCH),
+        ButtonPin::new(pins.gp11.into(), Pull::Down, Level::High, Button::FrontR, DEBOUNCE_SWITCH),
+        ButtonPin::new(pins.gp12.into(), Pull::Down, Level::High, Button::Start, DEBOUNCE_SWITCH),
+        ButtonPin::new(pins.gp7.into(), Pull::Down, Level::High, Button::Select, DEBOUNCE_SWITCH),
+    ];
+
+    // Key matrix with rows on GP0 to GP2 and columns on GP3 to GP6 and GP15
+    #[cfg(feature = "key-matrix")]
+    let mut keys = {
+        let mut rows: [DynPin; 3] = [pins.gp0.into(), pins.gp1.into(), pins.gp2.into()];
+        let mut cols: [DynPin; 5] = [
+            pins.gp3.into(),
+            pins.gp4.into(),
+            pins.gp5.into(),
+            pins.gp6.into(),
+            pins.gp15.into(),
+        ];
+        rows.iter_mut().for_each(|row| row.into_push_pull_output());
+        cols.iter_mut().for_each(|col| col.into_pull_up_input());
+        ScannedButtons::new(KeyMatrix::new(rows, cols).unwrap(), KEY_MAP)
+    };
+
+    // 74HC165 chain on SPI0, GP0 (QH), GP1 (SH/LD) and GP2 (CLK)
+    #[cfg(feature = "hc165")]
+    let mut keys = {
+        let _miso = pins.gp0.into_mode::<hal::gpio::FunctionSpi>();
+        let _sck = pins.gp2.into_mode::<hal::gpio::FunctionSpi>();
+        let spi = hal::Spi::<_, _, 8>::new(pac.SPI0).init(
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+            1.MHz(),
+            &embedded_hal::spi::MODE_0,
+        );
+        let load = pins.gp1.into_push_pull_output();
+        ScannedButtons::new(ShiftRegisters::<_, _, 2>::new(spi, load), KEY_MAP)
+    };
+
+    // MCP23017 on I2C1, GP2 (SDA) and GP3 (SCL)
+    #[cfg(feature = "mcp23017")]
+    let mut keys = {
+        let i2c = hal::I2C::i2c1(
+            pac.I2C1,
+            pins.gp2.into_mode::<hal::gpio::FunctionI2C>(),
+            pins.gp3.into_mode::<hal::gpio::FunctionI2C>(),
+            400.kHz(),
+            &mut pac.RESETS,
+            clocks.system_clock.freq(),
+        );
+        ScannedButtons::new(Mcp23017::new(i2c, MCP23017_ADDRESS), KEY_MAP)
+    };
+
+    // A pass over every row of a key matrix, given time to settle in between
+    #[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+    for _ in 0..8 {
+        keys.scan(0).ok();
+        cortex_m::asm::delay(1_000);
+    }
+    let is_held = |button: Button| {
+        #[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+        if keys.is_held(button) {
+            return true;
+        }
+        pinmap::is_held(&button_pins, button)
+    };
+
+    // Holding the right stick button while plugging in toggles the report resolution
+    if is_held(Button::ThumbR) {
+        settings.resolution = match settings.resolution {
+            ReportResolution::Low => ReportResolution::High,
+            ReportResolution::High => ReportResolution::Low,
+        };
+        info!("Report resolution changed to {}", settings.resolution);
+        settings.save(&mut flash);
+    }
+    // Holding one of these while plugging in switches the USB mode
+    for (button, mode) in [
+        (Button::Start, UsbMode::XInput),
+        (Button::Select, UsbMode::Hid),
+        (Button::UnderL, UsbMode::Switch),
+        (Button::UnderR, UsbMode::Ds4),
+        (Button::FrontL, UsbMode::Keyboard),
+        (Button::FrontR, UsbMode::KeyboardJoystick),
+    ] {
+        if is_held(button) && settings.mode != mode {
+            settings.mode = mode;
+            info!("USB mode changed to {}", settings.mode);
+            settings.save(&mut flash);
+        }
+    }
+
+    for pin in button_pins.iter() {
+        pin.listen();
+    }
+    critical_section::with(|cs| BUTTON_PINS.borrow(cs).replace(Some(button_pins)));
+
     let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
         pac.USBCTRL_REGS,
         pac.USBCTRL_DPRAM,
@@ -109,79 +298,73 @@ fn main() -> ! {
         true,
         &mut pac.RESETS,
     ));
-    let mut joy_hid = UsbHidClassBuilder::new()
-        .add_device(device::JoystickConfig::default())
-        .build(&usb_bus);
+    let mut gamepad = Gamepad::new(&usb_bus, settings.mode, settings.resolution);
+    let mut serial = settings.mode.has_console().then(|| SerialPort::new(&usb_bus));
+    let mut console = Console::default();
 
-    let mut usb_device = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(USB_VENDOR, USB_PRODUCT))
+    let (vid, pid) = settings.mode.vid_pid();
+    let usb_builder = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(vid, pid))
         .manufacturer(USB_MANUFACTURER)
         .product(USB_PRODUCT_NAME)
-        .serial_number(USB_SERIALNUM)
-        .device_class(2)
-        .build();
+        .serial_number(USB_SERIALNUM);
+    let mut usb_device = gamepad::configure(settings.mode, usb_builder).build();
 
     led.write(brightness(core::iter::once(colors::RED), 6))
         .unwrap();
 
-    // Setup joystick button interrupt pins
-    {
-        let l_joy_btn_pin = pins.gp14.into_mode();
-        l_joy_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        l_joy_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_THUMB_L.borrow(cs).replace(Some(l_joy_btn_pin)));
-    }
-    {
-        let r_joy_btn_pin = pins.gp8.into_mode();
-        r_joy_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        r_joy_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_THUMB_R.borrow(cs).replace(Some(r_joy_btn_pin)));
-    }
-    // Setup under / front button interrupt pins
-    {
-        let l_under_btn_pin = pins.gp13.into_mode();
-        l_under_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        l_under_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_UNDER_L.borrow(cs).replace(Some(l_under_btn_pin)));
-    }
-    {
-        let r_under_btn_pin = pins.gp9.into_mode();
-        r_under_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        r_under_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_UNDER_R.borrow(cs).replace(Some(r_under_btn_pin)));
-    }
-    {
-        let l_front_btn_pin = pins.gp10.into_mode();
-        l_front_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        l_front_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_FRONT_L.borrow(cs).replace(Some(l_front_btn_pin)));
-    }
-    {
-        let r_front_btn_pin = pins.gp11.into_mode();
-        r_front_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        r_front_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_FRONT_R.borrow(cs).replace(Some(r_front_btn_pin)));
-    }
-    {
-        let start_btn_pin = pins.gp12.into_mode();
-        start_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        start_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_START.borrow(cs).replace(Some(start_btn_pin)));
-    }
-    {
-        let select_btn_pin = pins.gp7.into_mode();
-        select_btn_pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
-        select_btn_pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
-        critical_section::with(|cs| BUTTON_PIN_SELECT.borrow(cs).replace(Some(select_btn_pin)));
-    }
-
     // Setup adc for joystick x / y
-    let mut adc = hal::adc::Adc::new(pac.ADC, &mut pac.RESETS);
-    let mut l_joy_x_pin = pins.gp26.into_floating_input();
-    let mut l_joy_y_pin = pins.gp27.into_floating_input();
-    let mut r_joy_x_pin = pins.gp28.into_floating_input();
-    let mut r_joy_y_pin = pins.gp29.into_floating_input();
+    let mut sticks = AdcSticks::new(
+        hal::adc::Adc::new(pac.ADC, &mut pac.RESETS),
+        pins.gp26.into_floating_input(),
+        pins.gp27.into_floating_input(),
+        pins.gp28.into_floating_input(),
+        pins.gp29.into_floating_input(),
+    );
 
-    let mut controller = Controller::default();
+    // External ADC on I2C0, GP0 (SDA) and GP1 (SCL)
+    #[cfg(feature = "ads1115")]
+    let mut sticks = {
+        let i2c = hal::I2C::i2c0(
+            pac.I2C0,
+            pins.gp0.into_mode::<hal::gpio::FunctionI2C>(),
+            pins.gp1.into_mode::<hal::gpio::FunctionI2C>(),
+            400.kHz(),
+            &mut pac.RESETS,
+            clocks.system_clock.freq(),
+        );
+        ExternalAnalog::new(sticks, Ads1115::new(i2c, ADS1115_ADDRESS), EXTERNAL_ANALOG)
+    };
+
+    // External ADC on SPI0, GP3 (MOSI), GP4 (MISO), GP5 (CS) and GP6 (SCK)
+    #[cfg(feature = "mcp3208")]
+    let mut sticks = {
+        let _mosi = pins.gp3.into_mode::<hal::gpio::FunctionSpi>();
+        let _miso = pins.gp4.into_mode::<hal::gpio::FunctionSpi>();
+        let _sck = pins.gp6.into_mode::<hal::gpio::FunctionSpi>();
+        let spi = hal::Spi::<_, _, 8>::new(pac.SPI0).init(
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+            1.MHz(),
+            &embedded_hal::spi::MODE_0,
+        );
+        let cs = pins.gp5.into_push_pull_output();
+        ExternalAnalog::new(sticks, Mcp3208::new(spi, cs), EXTERNAL_ANALOG)
+    };
+
+    // Rumble motors on GP4 (heavy) and GP5 (light)
+    #[cfg(feature = "rumble")]
+    let mut rumble = {
+        let _heavy = pins.gp4.into_mode::<hal::gpio::FunctionPwm>();
+        let _light = pins.gp5.into_mode::<hal::gpio::FunctionPwm>();
+        RumbleMotors::new(hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS).pwm2)
+    };
+
+    #[cfg(any(feature = "ads1115", feature = "mcp3208"))]
+    let mut adc_failed = false;
+    #[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+    let mut scan_failed = false;
+
+    let mut pipeline = Pipeline::new(settings);
 
     // Allow interrupts last, in case something is not set up fully and IRQ fires
     unsafe {
@@ -193,184 +376,180 @@ fn main() -> ! {
     let mut joy_timer = timer.count_down();
     joy_timer.start(10.millis());
 
-    let mut report = JoystickReport::default();
-    let mut last_report = JoystickReport::default();
+    let mut status_led = StatusLed::new(led);
 
-    let mut led_colour = colors::GREEN;
-    let mut next_led_colour = led_colour;
+    loop {
+        // The external ADC converts between ticks, read on the tick with the ADC pins
+        #[cfg(any(feature = "ads1115", feature = "mcp3208"))]
+        match sticks.sample() {
+            Ok(()) => adc_failed = false,
+            Err(_) if !adc_failed => {
+                warn!("External ADC not responding");
+                adc_failed = true;
+            }
+            Err(_) => {}
+        }
 
-    led.write(brightness(core::iter::once(led_colour), 12))
-        .unwrap();
+        // Scanned switches are debounced from the edges between scans, like the pins
+        #[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+        match keys.scan(timer.get_counter().ticks()) {
+            Ok(()) => scan_failed = false,
+            Err(_) if !scan_failed => {
+                warn!("Switch scanner not responding");
+                scan_failed = true;
+            }
+            Err(_) => {}
+        }
 
-    loop {
         if joy_timer.wait().is_ok() {
             // READ STATE
-            controller.joy_l.button =
-                critical_section::with(|cs| *BUTTON_THUMB_L.borrow(cs).borrow());
-            controller.joy_r.button =
-                critical_section::with(|cs| *BUTTON_THUMB_R.borrow(cs).borrow());
-            controller.under_l = critical_section::with(|cs| *BUTTON_UNDER_L.borrow(cs).borrow());
-            controller.under_r = critical_section::with(|cs| *BUTTON_UNDER_R.borrow(cs).borrow());
-            controller.front_l = critical_section::with(|cs| *BUTTON_FRONT_L.borrow(cs).borrow());
-            controller.front_r = critical_section::with(|cs| *BUTTON_FRONT_R.borrow(cs).borrow());
-            controller.joy_l.x = adc.read(&mut l_joy_x_pin).unwrap();
-            controller.joy_l.y = adc.read(&mut l_joy_y_pin).unwrap();
-            controller.joy_r.x = adc.read(&mut r_joy_x_pin).unwrap();
-            controller.joy_r.y = adc.read(&mut r_joy_y_pin).unwrap();
-
-            controller.hid_report(&mut report);
-
-            if last_report != report {
-                match joy_hid.device().write_report(&report) {
-                    Err(UsbHidError::WouldBlock) => {
-                        next_led_colour = colors::DARK_CYAN;
+            let now = timer.get_counter().ticks();
+            let mut buttons = ButtonSet::default();
+            critical_section::with(|cs| {
+                if let Some(pins) = BUTTON_PINS.borrow(cs).borrow_mut().as_mut() {
+                    buttons.read(pins.iter_mut(), now);
+                }
+            });
+            #[cfg(any(feature = "hc165", feature = "mcp23017", feature = "key-matrix"))]
+            buttons.read(keys.buttons.iter_mut(), now);
+            pipeline.set_buttons(&buttons, now);
+            pipeline.read_sticks(&mut sticks);
+
+            let events = pipeline.update(&mut gamepad);
+            for event in events {
+                match event {
+                    Event::CalibrationStarted => info!("Starting stick calibration"),
+                    Event::CalibrationRejected => {
+                        warn!("Calibration rejected, sticks did not travel far enough");
+                    }
+                    Event::ProfileSwitched(_) => {
+                        info!("Switched to profile {}", pipeline.settings.profile().name());
                     }
-                    Err(_e) => {
-                        next_led_colour = colors::RED;
-                        //core::panic!("Unable to write hid report: {:?}", e)
+                    Event::TurboToggled(button, enabled) => {
+                        info!("Turbo on {} {}", button.name(), enabled);
                     }
-                    Ok(_) => {
-                        next_led_colour = colors::GREEN;
-                    } // Setup under / front button interrupt pins
+                    Event::MacroRecorded(index) => info!("Recorded macro {}", index + 1),
+                    Event::Calibrated | Event::TurboSettled => {}
                 }
             }
-            last_report = report;
-        } else {
-            next_led_colour = colors::GREEN;
+            // Once for all the changes of the tick
+            if events.changes_settings() {
+                pipeline.settings.save(&mut flash);
+            }
         }
 
-        if !usb_device.poll(&mut [&mut joy_hid]) {
-            next_led_colour = colors::ORANGE;
-            led.write(brightness(core::iter::once(next_led_colour), 12))
-                .unwrap();
-            led_colour = next_led_colour;
+        match serial.as_mut() {
+            Some(serial) => usb_device.poll(&mut [gamepad.class(), serial]),
+            None => usb_device.poll(&mut [gamepad.class()]),
+        };
+        let configured = usb_device.state() == UsbDeviceState::Configured;
+        pipeline.set_usb_idle(!configured);
+
+        // Rumble and LED from the host, the LED takes over from the ready colour
+        if let Some(command) = gamepad.take_command() {
+            #[cfg(feature = "rumble")]
+            if let Some(strength) = command.rumble {
+                rumble.set(strength);
+            }
+            if let Some(led) = command.led {
+                pipeline.set_host_led(led);
+            }
         }
-
-        if !usb_device.poll(&mut [&mut joy_hid]) {
-            next_led_colour = colors::ORANGE;
+        // Nobody left to stop the motors once the host is gone
+        #[cfg(feature = "rumble")]
+        if !configured {
+            rumble.set(Rumble::default());
         }
-        if next_led_colour != led_colour {
-            led.write(brightness(core::iter::once(next_led_colour), 12))
-                .unwrap();
-            led_colour = next_led_colour;
+
+        if let Some(serial) = serial.as_mut() {
+            let mut buf = [0u8; 64];
+            if let Ok(count) = serial.read(&mut buf) {
+                console.receive(
+                    &buf[..count],
+                    &mut pipeline.settings,
+                    &mut pipeline.controller,
+                );
+            }
+            match console.take_request() {
+                Some(Request::Save) => {
+                    pipeline.settings.save(&mut flash);
+                    console.print("saved");
+                }
+                Some(Request::Load) => {
+                    pipeline.set_settings(Settings::load(&flash));
+                    console.print("loaded");
+                }
+                Some(Request::Profile(index)) => {
+                    pipeline.switch_profile(index);
+                    info!("Switched to profile {}", pipeline.settings.profile().name());
+                    pipeline.settings.save(&mut flash);
+                    console.print(pipeline.settings.profile().name());
+                }
+                Some(Request::MacroRecord(index)) => {
+                    pipeline.record_macro(index);
+                    console.print("recording, macro stop to end");
+                }
+                Some(Request::MacroPlay(index)) => {
+                    let message = if pipeline.play_macro(index) {
+                        "playing"
+                    } else {
+                        "nothing to play"
+                    };
+                    console.print(message);
+                }
+                Some(Request::MacroStop) => {
+                    if let Some(Event::MacroRecorded(index)) = pipeline.stop_macro() {
+                        info!("Recorded macro {}", index + 1);
+                        pipeline.settings.save(&mut flash);
+                    }
+                    console.print("stopped");
+                }
+                Some(Request::MacroClear(index)) => {
+                    pipeline.clear_macro(index);
+                    console.print("cleared");
+                }
+                Some(Request::Defaults) => {
+                    pipeline.set_settings(Settings::default());
+                    console.print("defaults restored, save to keep them");
+                }
+                None => {}
+            }
+            if !console.pending().is_empty() {
+                if let Ok(count) = serial.write(console.pending()) {
+                    console.consume(count);
+                }
+            }
         }
+
+        status_led.show(pipeline.status());
     }
 }
 
 #[interrupt]
 fn IO_IRQ_BANK0() {
-    static mut L_THUMB_BUTTON_PIN: Option<ButtonPinThumbL> = None;
-    static mut R_THUMB_BUTTON_PIN: Option<ButtonPinThumbR> = None;
-
-    static mut L_UNDER_BUTTON_PIN: Option<ButtonPinUnderL> = None;
-    static mut R_UNDER_BUTTON_PIN: Option<ButtonPinUnderR> = None;
-
-    static mut L_FRONT_BUTTON_PIN: Option<ButtonPinFrontL> = None;
-    static mut R_FRONT_BUTTON_PIN: Option<ButtonPinFrontR> = None;
-
-    static mut START_BUTTON_PIN: Option<ButtonPinStart> = None;
-    static mut SELECT_BUTTON_PIN: Option<ButtonPinSelect> = None;
-
-    if L_THUMB_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *L_THUMB_BUTTON_PIN = BUTTON_PIN_THUMB_L.borrow(cs).take());
-    }
-    if let Some(pin) = L_THUMB_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_THUMB_L.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_THUMB_L.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-
-    if R_THUMB_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *R_THUMB_BUTTON_PIN = BUTTON_PIN_THUMB_R.borrow(cs).take());
-    }
-    if let Some(pin) = R_THUMB_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_THUMB_R.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_THUMB_R.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-
-    if L_UNDER_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *L_UNDER_BUTTON_PIN = BUTTON_PIN_UNDER_L.borrow(cs).take());
-    }
-    if let Some(pin) = L_UNDER_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_UNDER_L.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_UNDER_L.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-
-    if R_UNDER_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *R_UNDER_BUTTON_PIN = BUTTON_PIN_UNDER_R.borrow(cs).take());
-    }
-    if let Some(pin) = R_UNDER_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_UNDER_R.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_UNDER_R.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-
-    if L_FRONT_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *L_FRONT_BUTTON_PIN = BUTTON_PIN_FRONT_L.borrow(cs).take());
-    }
-    if let Some(pin) = L_FRONT_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_FRONT_L.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_FRONT_L.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-
-    if R_FRONT_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *R_FRONT_BUTTON_PIN = BUTTON_PIN_FRONT_R.borrow(cs).take());
-    }
-    if let Some(pin) = R_FRONT_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_FRONT_R.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_FRONT_R.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
-        }
-    }
-    if START_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *START_BUTTON_PIN = BUTTON_PIN_START.borrow(cs).take());
-    }
-    if let Some(pin) = START_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_START.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_START.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
+    let now = timer_ticks();
+    critical_section::with(|cs| {
+        if let Some(pins) = BUTTON_PINS.borrow(cs).borrow_mut().as_mut() {
+            for pin in pins.iter_mut() {
+                pin.on_interrupt(now);
+            }
         }
-    }
+    });
+}
 
-    if SELECT_BUTTON_PIN.is_none() {
-        critical_section::with(|cs| *SELECT_BUTTON_PIN = BUTTON_PIN_SELECT.borrow(cs).take());
-    }
-    if let Some(pin) = SELECT_BUTTON_PIN {
-        if pin.interrupt_status(Interrupt::EdgeLow) {
-            critical_section::with(|cs| *BUTTON_SELECT.borrow(cs).borrow_mut() = false);
-            pin.clear_interrupt(Interrupt::EdgeLow);
-        } else if pin.interrupt_status(Interrupt::EdgeHigh) {
-            critical_section::with(|cs| *BUTTON_SELECT.borrow(cs).borrow_mut() = true);
-            pin.clear_interrupt(Interrupt::EdgeHigh);
+/// Microseconds since boot, read straight from the timer registers. The `Timer` itself stays
+/// in `main`, where the LED and the report tick borrow it for their countdowns.
+fn timer_ticks() -> u64 {
+    // SAFETY: only reads the free running counter, which nothing writes to
+    let timer = unsafe { &*pac::TIMER::ptr() };
+    // Read high, low, high again, in case low wrapped around in between
+    let mut high = timer.timerawh.read().bits();
+    loop {
+        let low = timer.timerawl.read().bits();
+        let next_high = timer.timerawh.read().bits();
+        if next_high == high {
+            return (u64::from(high) << 32) | u64::from(low);
         }
+        high = next_high;
     }
 }
@@ -0,0 +1,76 @@
+use embedded_hal::digital::v2::InputPin;
+use picotroller_core::controller::{Button, Control};
+use picotroller_core::debounce::Debouncer;
+use picotroller_core::input::{ButtonInput, Level};
+use picotroller_core::platform::DigitalInput;
+use waveshare_rp2040_zero::hal::gpio::{dynpin::DynPin, Interrupt};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pull {
+    Up,
+    Down,
+}
+
+/// A GPIO wired to one of the controller buttons or D-pad directions, one entry of the pin
+/// map.
+pub struct ButtonPin {
+    pin: DynPin,
+    pub input: ButtonInput,
+}
+
+impl ButtonPin {
+    pub fn new(
+        mut pin: DynPin,
+        pull: Pull,
+        active: Level,
+        control: impl Into<Control>,
+        debouncer: Debouncer,
+    ) -> Self {
+        match pull {
+            Pull::Up => pin.into_pull_up_input(),
+            Pull::Down => pin.into_pull_down_input(),
+        }
+        Self {
+            pin,
+            input: ButtonInput::new(active, control.into(), debouncer),
+        }
+    }
+
+    /// Reads the pin directly, without waiting for an edge or debouncing.
+    pub fn is_pressed(&self) -> bool {
+        self.input.is_active(self.pin.is_high().unwrap())
+    }
+
+    pub fn listen(&self) {
+        self.pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
+        self.pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+    }
+
+    /// Feeds a pending edge interrupt of the pin to its debouncer. A bounce can latch both
+    /// edges before the interrupt runs, so the level comes from the pin rather than the edge.
+    pub fn on_interrupt(&mut self, now: u64) {
+        let low = self.pin.interrupt_status(Interrupt::EdgeLow);
+        let high = self.pin.interrupt_status(Interrupt::EdgeHigh);
+        if low || high {
+            self.pin.clear_interrupt(Interrupt::EdgeLow);
+            self.pin.clear_interrupt(Interrupt::EdgeHigh);
+            self.input.edge(self.pin.is_high().unwrap(), now);
+        }
+    }
+}
+
+impl DigitalInput for ButtonPin {
+    fn control(&self) -> Control {
+        self.input.control
+    }
+
+    fn poll(&mut self, now: u64) -> bool {
+        self.input.poll(now)
+    }
+}
+
+/// Whether any pin mapped to `button` is pressed right now, for checking buttons held at boot.
+pub fn is_held(pins: &[ButtonPin], button: Button) -> bool {
+    pins.iter()
+        .any(|pin| pin.input.control == Control::Button(button) && pin.is_pressed())
+}
@@ -0,0 +1,32 @@
+use embedded_hal::PwmPin;
+use picotroller_core::platform::Rumble;
+use waveshare_rp2040_zero::hal::pwm::{FreeRunning, Pwm2, Slice};
+
+/// Counter wrap of the PWM, 25kHz from the 125MHz system clock, above what the motors whine
+/// at.
+const TOP: u16 = 5_000;
+
+/// Two rumble motors on PWM slice 2, each switched by a transistor, the heavy motor on GP4
+/// and the light one on GP5.
+pub struct RumbleMotors {
+    slice: Slice<Pwm2, FreeRunning>,
+}
+
+impl RumbleMotors {
+    pub fn new(mut slice: Slice<Pwm2, FreeRunning>) -> Self {
+        slice.set_top(TOP);
+        slice.channel_a.set_duty(0);
+        slice.channel_b.set_duty(0);
+        slice.enable();
+        Self { slice }
+    }
+
+    pub fn set(&mut self, rumble: Rumble) {
+        self.slice.channel_a.set_duty(duty(rumble.left));
+        self.slice.channel_b.set_duty(duty(rumble.right));
+    }
+}
+
+fn duty(strength: u8) -> u16 {
+    (strength as u32 * TOP as u32 / u8::MAX as u32) as u16
+}
@@ -0,0 +1,60 @@
+use picotroller_core::platform::{HostLed, Status, StatusIndicator};
+use picotroller_core::profile::PROFILE_COUNT;
+use smart_leds::{brightness, colors, SmartLedsWrite, RGB8};
+
+const PROFILE_COLOURS: [RGB8; PROFILE_COUNT] =
+    [colors::WHITE, colors::MAGENTA, colors::CYAN, colors::PURPLE];
+/// Colours of the player numbers the host sets, the same as a DualShock 4 light bar.
+const PLAYER_COLOURS: [RGB8; 4] = [colors::BLUE, colors::RED, colors::LIME, colors::HOT_PINK];
+const BRIGHTNESS: u8 = 12;
+
+/// The NeoPixel on the board, only written when the colour changes. Each profile has its
+/// own colour, shown for a moment after switching to it.
+pub struct StatusLed<L> {
+    led: L,
+    colour: Option<RGB8>,
+}
+
+impl<L> StatusLed<L> {
+    pub fn new(led: L) -> Self {
+        Self { led, colour: None }
+    }
+}
+
+fn colour(status: Status) -> RGB8 {
+    match status {
+        Status::Ready => colors::GREEN,
+        Status::Busy => colors::DARK_CYAN,
+        Status::Error => colors::RED,
+        Status::UsbIdle => colors::ORANGE,
+        Status::CalibrateCenter => colors::YELLOW,
+        Status::CalibrateExtents => colors::BLUE,
+        Status::Profile(index) => PROFILE_COLOURS[index],
+        Status::Turbo(true) => colors::GOLD,
+        Status::Turbo(false) => colors::DIM_GRAY,
+        Status::MacroRecording => colors::DEEP_PINK,
+        Status::MacroPlaying => colors::SPRING_GREEN,
+        Status::Host(HostLed::Colour(r, g, b)) => RGB8::new(r, g, b),
+        Status::Host(HostLed::Player(player)) => {
+            PLAYER_COLOURS[(player as usize).saturating_sub(1) % PLAYER_COLOURS.len()]
+        }
+        // The pipeline never shows it, the host handing the LED back means ready
+        Status::Host(HostLed::Off) => colors::GREEN,
+    }
+}
+
+impl<L> StatusIndicator for StatusLed<L>
+where
+    L: SmartLedsWrite<Color = RGB8>,
+    L::Error: core::fmt::Debug,
+{
+    fn show(&mut self, status: Status) {
+        let colour = colour(status);
+        if self.colour != Some(colour) {
+            self.led
+                .write(brightness(core::iter::once(colour), BRIGHTNESS))
+                .unwrap();
+            self.colour = Some(colour);
+        }
+    }
+}
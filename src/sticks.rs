@@ -0,0 +1,43 @@
+use embedded_hal::adc::OneShot;
+use picotroller_core::platform::{AnalogSource, StickAxis};
+use waveshare_rp2040_zero::hal::adc::Adc;
+use waveshare_rp2040_zero::hal::gpio::bank0::{Gpio26, Gpio27, Gpio28, Gpio29};
+use waveshare_rp2040_zero::hal::gpio::{FloatingInput, Pin};
+
+/// Both sticks, wired to the four ADC inputs of the RP2040.
+pub struct AdcSticks {
+    adc: Adc,
+    left_x: Pin<Gpio26, FloatingInput>,
+    left_y: Pin<Gpio27, FloatingInput>,
+    right_x: Pin<Gpio28, FloatingInput>,
+    right_y: Pin<Gpio29, FloatingInput>,
+}
+
+impl AdcSticks {
+    pub fn new(
+        adc: Adc,
+        left_x: Pin<Gpio26, FloatingInput>,
+        left_y: Pin<Gpio27, FloatingInput>,
+        right_x: Pin<Gpio28, FloatingInput>,
+        right_y: Pin<Gpio29, FloatingInput>,
+    ) -> Self {
+        Self {
+            adc,
+            left_x,
+            left_y,
+            right_x,
+            right_y,
+        }
+    }
+}
+
+impl AnalogSource for AdcSticks {
+    fn read(&mut self, axis: StickAxis) -> u16 {
+        match axis {
+            StickAxis::LeftX => self.adc.read(&mut self.left_x).unwrap(),
+            StickAxis::LeftY => self.adc.read(&mut self.left_y).unwrap(),
+            StickAxis::RightX => self.adc.read(&mut self.right_x).unwrap(),
+            StickAxis::RightY => self.adc.read(&mut self.right_y).unwrap(),
+        }
+    }
+}
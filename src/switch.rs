@@ -0,0 +1,76 @@
+use core::default::Default;
+use defmt::{error, unwrap};
+use fugit::ExtU32;
+use packed_struct::prelude::*;
+use picotroller_core::switch::{SwitchReport, SWITCH_DESCRIPTOR};
+use usb_device::bus::UsbBus;
+use usb_device::class_prelude::UsbBusAllocator;
+use usbd_human_interface_device::usb_class::prelude::*;
+use usbd_human_interface_device::UsbHidError;
+
+pub struct SwitchPad<'a, B: UsbBus> {
+    interface: Interface<'a, B, InBytes8, OutNone, ReportSingle>,
+}
+
+impl<'a, B: UsbBus> SwitchPad<'a, B> {
+    pub fn write_report(&mut self, report: &SwitchReport) -> Result<(), UsbHidError> {
+        let data = report.pack().map_err(|_| {
+            error!("Error packing SwitchReport");
+            UsbHidError::SerializationError
+        })?;
+        self.interface
+            .write_report(&data)
+            .map(|_| ())
+            .map_err(UsbHidError::from)
+    }
+}
+
+impl<'a, B: UsbBus> DeviceClass<'a> for SwitchPad<'a, B> {
+    type I = Interface<'a, B, InBytes8, OutNone, ReportSingle>;
+
+    fn interface(&mut self) -> &mut Self::I {
+        &mut self.interface
+    }
+
+    fn reset(&mut self) {}
+
+    fn tick(&mut self) -> Result<(), UsbHidError> {
+        Ok(())
+    }
+}
+
+pub struct SwitchPadConfig<'a> {
+    interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>,
+}
+
+impl<'a> Default for SwitchPadConfig<'a> {
+    #[must_use]
+    fn default() -> Self {
+        // The Switch does not send the output report to the pad, so there is no OUT endpoint
+        Self::new(
+            unwrap!(unwrap!(InterfaceBuilder::new(SWITCH_DESCRIPTOR))
+                .boot_device(InterfaceProtocol::None)
+                .description("Pokken Controller")
+                .in_endpoint(8.millis()))
+            .without_out_endpoint()
+            .build(),
+        )
+    }
+}
+
+impl<'a> SwitchPadConfig<'a> {
+    #[must_use]
+    pub fn new(interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>) -> Self {
+        Self { interface }
+    }
+}
+
+impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for SwitchPadConfig<'a> {
+    type Allocated = SwitchPad<'a, B>;
+
+    fn allocate(self, usb_alloc: &'a UsbBusAllocator<B>) -> Self::Allocated {
+        Self::Allocated {
+            interface: Interface::new(usb_alloc, self.interface),
+        }
+    }
+}
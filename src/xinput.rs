@@ -0,0 +1,106 @@
+use core::default::Default;
+use defmt::{debug, error};
+use packed_struct::prelude::*;
+use picotroller_core::xinput::{XInputOutput, XInputReport, XINPUT_REPORT_SIZE};
+use usb_device::class_prelude::*;
+use usbd_human_interface_device::UsbHidError;
+
+const XINPUT_INTERFACE_CLASS: u8 = 0xff;
+const XINPUT_INTERFACE_SUB_CLASS: u8 = 0x5d;
+const XINPUT_INTERFACE_PROTOCOL: u8 = 0x01;
+const XINPUT_DESCRIPTOR_TYPE: u8 = 0x21;
+
+const SECURITY_INTERFACE_SUB_CLASS: u8 = 0xfd;
+const SECURITY_INTERFACE_PROTOCOL: u8 = 0x13;
+const SECURITY_DESCRIPTOR_TYPE: u8 = 0x41;
+
+const XINPUT_PACKET_SIZE: u16 = 32;
+
+/// Vendor specific class used by the Xbox 360 controller, matching its VID / PID so Windows
+/// loads the XInput driver for it.
+pub struct XInput<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    security_interface: InterfaceNumber,
+    output: Option<XInputOutput>,
+}
+
+impl<'a, B: UsbBus> XInput<'a, B> {
+    pub fn new(usb_alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            interface: usb_alloc.interface(),
+            ep_in: usb_alloc.interrupt(XINPUT_PACKET_SIZE, 4),
+            ep_out: usb_alloc.interrupt(XINPUT_PACKET_SIZE, 8),
+            security_interface: usb_alloc.interface(),
+            output: None,
+        }
+    }
+
+    pub fn write_report(&mut self, report: &XInputReport) -> Result<(), UsbHidError> {
+        let data = report.pack().map_err(|_| {
+            error!("Error packing XInputReport");
+            UsbHidError::SerializationError
+        })?;
+        self.ep_in
+            .write(&data)
+            .map(|_| ())
+            .map_err(UsbHidError::from)
+    }
+
+    /// Takes the latest rumble or LED command received from the host.
+    pub fn take_output(&mut self) -> Option<XInputOutput> {
+        self.output.take()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for XInput<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(
+            self.interface,
+            XINPUT_INTERFACE_CLASS,
+            XINPUT_INTERFACE_SUB_CLASS,
+            XINPUT_INTERFACE_PROTOCOL,
+        )?;
+        // Undocumented descriptor copied from a wired controller, referencing both endpoints
+        #[rustfmt::skip]
+        let descriptor = [
+            0x00, 0x01, 0x01, 0x25,
+            self.ep_in.address().into(), XINPUT_REPORT_SIZE, 0x00, 0x00, 0x00, 0x00,
+            0x13, self.ep_out.address().into(), 0x08, 0x00, 0x00,
+        ];
+        writer.write(XINPUT_DESCRIPTOR_TYPE, &descriptor)?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)?;
+
+        // Stub of the interface the console uses for authentication, Windows only checks it exists
+        writer.interface(
+            self.security_interface,
+            XINPUT_INTERFACE_CLASS,
+            SECURITY_INTERFACE_SUB_CLASS,
+            SECURITY_INTERFACE_PROTOCOL,
+        )?;
+        writer.write(SECURITY_DESCRIPTOR_TYPE, &[0x00, 0x01, 0x01, 0x03])?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.output = None;
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut data = [0u8; XINPUT_PACKET_SIZE as usize];
+        if let Ok(size) = self.ep_out.read(&mut data) {
+            if let Some(output) = XInputOutput::parse(&data[..size]) {
+                debug!("XInput output {}", output);
+                self.output = Some(output);
+            }
+        }
+    }
+}